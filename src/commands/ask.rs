@@ -1,8 +1,26 @@
+use super::{guild_id, thread_id};
+use crate::docs::types::{PhaseUsage, QaRecord, QueryOutcome, Scope};
 use crate::commands::config::is_admin;
+use crate::rlm::context::truncate;
+use crate::rlm::conversation::{FollowUp, Turn};
+use crate::rlm::progress::{Progress, ProgressLog, ProgressSink};
+use crate::rlm::scheduler::Ticket;
+use crate::rlm::usage::UsageMeter;
+use crate::rlm::QueryContext;
 use crate::state::Context;
-use tracing::info;
+use poise::serenity_prelude as serenity;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
 
-/// Ask a question about ingested documents
+/// Discord's limit on thread names.
+const THREAD_NAME_MAX: usize = 100;
+/// Minimum time between status message edits (Discord rate-limits edits).
+const STATUS_EDIT_INTERVAL: Duration = Duration::from_secs(2);
+/// How often a queued question re-checks its place in line.
+const QUEUE_POLL: Duration = Duration::from_secs(2);
+
+/// Ask a question about ingested documents. Asked in a channel, the answer goes
+/// to a new thread; asked in a thread, follow-ups build on earlier answers.
 #[poise::command(slash_command, guild_only)]
 pub async fn ask(
     ctx: Context<'_>,
@@ -12,42 +30,257 @@ pub async fn ask(
     #[description = "Your question"] question: String,
     #[description = "Show debug evidence (admin only)"] debug: Option<bool>,
 ) -> Result<(), anyhow::Error> {
-    // Acknowledge immediately so the user isn't staring at a loading spinner
+    // Opening the answer thread is a round-trip — keep the interaction alive
+    ctx.defer().await?;
     let user_mention = format!("<@{}>", ctx.author().id);
-    ctx.say(format!(
-        "Got it — researching **{}** for you. I'll ping you when the answer is ready, {}",
-        topic, user_mention
-    ))
-    .await?;
-
+    let guild = guild_id(&ctx)?;
     let is_admin = is_admin(&ctx).await;
+
+    // Rate limits; admins queue ahead of everyone else
+    let mut ticket = match ctx
+        .data()
+        .scheduler
+        .submit(ctx.author().id.get(), guild, is_admin)
+    {
+        Ok(ticket) => ticket,
+        Err(limited) => {
+            let whose = match limited.limit {
+                "user" => "your",
+                _ => "this server's",
+            };
+            info!(
+                user = ctx.author().name,
+                limit = limited.limit,
+                "Question rate-limited"
+            );
+            ctx.say(format!(
+                "Slow down — you've hit {} limit of {} questions per hour. Try again in {} minute(s).",
+                whose,
+                limited.per_hour,
+                limited.retry_after.as_secs().div_ceil(60)
+            ))
+            .await?;
+            return Ok(());
+        }
+    };
+
+    // Asked in a thread: answer there and continue its conversation. Asked
+    // anywhere else: open a thread for the question and ping back here.
+    let current_thread = thread_id(&ctx).await;
+    let new_thread = match current_thread {
+        Some(_) => None,
+        None => match open_question_thread(&ctx, &topic, &question).await {
+            Ok(channel) => Some(channel),
+            Err(e) => {
+                warn!(error = %e, "Failed to create question thread, answering in channel");
+                None
+            }
+        },
+    };
+    let thread = current_thread.or(new_thread.map(|c| c.get()));
+
+    // Listed in `/edgar jobs` until this handler returns
+    let job = ctx.data().jobs.start(
+        guild,
+        ctx.author().id.get(),
+        &ctx.author().name,
+        &topic,
+        &question,
+    );
+
+    let ack = match new_thread {
+        Some(channel) => format!(
+            "Got it — researching **{}** in <#{}>. I'll ping you here when the answer is ready, {}",
+            topic, channel, user_mention
+        ),
+        None => format!(
+            "Got it — researching **{}** for you. I'll ping you when the answer is ready, {}",
+            topic, user_mention
+        ),
+    };
+    let ack = format!(
+        "{}\n-# Job #{} — stop it with `/edgar jobs cancel id:{}`",
+        ack,
+        job.id(),
+        job.id()
+    );
+    let status = ctx.say(ack.clone()).await?;
+
     let show_debug = debug.unwrap_or(false) && is_admin;
 
-    // Read current config
-    let config = ctx.data().rlm_config.read().await;
-    let max_iterations = config.max_iterations;
-    let min_code_executions = config.min_code_executions;
-    let min_answer_len = config.min_answer_len;
-    let parallel_loops = config.parallel_loops;
-    drop(config);
+    // Read this guild's config and visible scopes
+    let config = ctx.data().guild_settings(guild).await?.rlm;
+    let view = ctx.data().view(guild).await?;
+
+    // In a thread, continue its conversation; other answered threads on the
+    // topic come along as extra context
+    let mut followup = match thread {
+        Some(thread) => ctx.data().conversations.begin(thread, &topic).await,
+        None => FollowUp::default(),
+    };
+    let epoch = followup.epoch;
+    followup.related = ctx
+        .data()
+        .rlm
+        .related_threads(&view, &topic, &question, thread)
+        .await;
 
     info!(
         user = ctx.author().name,
-        topic, question, is_admin, "RLM query started"
+        topic,
+        question,
+        is_admin,
+        prior_turns = followup.history.len(),
+        related_threads = followup.related.len(),
+        job = job.id(),
+        "RLM query started"
     );
 
-    let result = ctx
-        .data()
-        .rlm
-        .query(
+    // Run the query while rendering its progress into the status message.
+    // Cancelling drops the query: its sub-loop tasks are aborted and their
+    // Python sessions shut down. Nested rlm_query() loops and
+    // llm_query_batch() calls also stop on the job's token.
+    let (progress, mut events) = ProgressSink::channel();
+    // Kept here so a cancelled or failed query's usage is still accounted for
+    let meter = UsageMeter::default();
+    let started = Instant::now();
+    let query = async {
+        let run = async {
+            wait_in_line(&mut ticket, &progress).await;
+            let context = QueryContext {
+                user_id: ctx.author().id.get(),
+                followup,
+                progress,
+                cancel: job.token(),
+                meter: meter.clone(),
+            };
+            ctx.data()
+                .rlm
+                .query(&view, &topic, &question, &config, context)
+                .await
+        };
+        tokio::select! {
+            result = run => Some(result),
+            _ = job.cancelled() => None,
+        }
+    };
+    let render = async {
+        let mut log = ProgressLog::default();
+        let mut tick = tokio::time::interval(STATUS_EDIT_INTERVAL);
+        let mut dirty = false;
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Some(event) => {
+                        log.apply(event);
+                        job.set_phase(log.phase());
+                        dirty = true;
+                    }
+                    // Every sender is gone: the query finished
+                    None => break,
+                },
+                _ = tick.tick(), if dirty => {
+                    edit_status(&ctx, &status, &ack, "Researching...", &log).await;
+                    dirty = false;
+                }
+            }
+        }
+        log
+    };
+    let (outcome, log) = tokio::join!(query, render);
+    // Free the running slot for the next question in line
+    drop(ticket);
+    let Some(result) = outcome else {
+        info!(job = job.id(), "RLM query cancelled");
+        record_unanswered(
+            &ctx,
+            view.home(),
             &topic,
             &question,
-            max_iterations,
-            min_code_executions,
-            min_answer_len,
-            parallel_loops,
+            QueryOutcome::Cancelled,
+            &meter,
+            started,
         )
-        .await?;
+        .await;
+        edit_status(&ctx, &status, &ack, "Cancelled", &log).await;
+        if let Some(thread) = thread {
+            ctx.data().conversations.abandon(thread, epoch).await;
+        }
+        if let Some(channel) = new_thread {
+            channel
+                .say(ctx.http(), "This question was cancelled before it was answered.")
+                .await?;
+        }
+        ctx.channel_id()
+            .say(
+                ctx.http(),
+                format!("{} your question was cancelled.", user_mention),
+            )
+            .await?;
+        return Ok(());
+    };
+    let mut result = match result {
+        Ok(result) => {
+            edit_status(&ctx, &status, &ack, "Done", &log).await;
+            result
+        }
+        Err(e) => {
+            record_unanswered(
+                &ctx,
+                view.home(),
+                &topic,
+                &question,
+                QueryOutcome::Failed,
+                &meter,
+                started,
+            )
+            .await;
+            edit_status(&ctx, &status, &ack, "Failed", &log).await;
+            if let Some(thread) = thread {
+                ctx.data().conversations.abandon(thread, epoch).await;
+            }
+            if let Some(channel) = new_thread {
+                channel
+                    .say(ctx.http(), "This question failed before it was answered.")
+                    .await?;
+            }
+            ctx.channel_id()
+                .say(
+                    ctx.http(),
+                    format!("{} your question failed.", user_mention),
+                )
+                .await?;
+            return Err(e);
+        }
+    };
+
+    if let Some(thread) = thread {
+        let turn = Turn {
+            question: question.clone(),
+            standalone: result.standalone_question.clone(),
+            answer: result.answer.clone(),
+            files_read: result.files_read.clone(),
+        };
+        ctx.data()
+            .conversations
+            .finish(thread, epoch, turn, result.session.take())
+            .await;
+        if let Err(e) = ctx
+            .data()
+            .store
+            .record_thread_answer(
+                view.home(),
+                &topic,
+                thread,
+                result.standalone_question.as_deref().unwrap_or(&question),
+                &result.answer,
+                &result.cited_urls,
+            )
+            .await
+        {
+            warn!(error = %e, "Failed to record thread answer");
+        }
+    }
 
     info!(
         iterations = result.iterations,
@@ -56,9 +289,13 @@ pub async fn ask(
         "RLM query complete"
     );
 
+    let asked = match &result.standalone_question {
+        Some(standalone) => format!("{}\n*Follow-up, researched as:* {}", question, standalone),
+        None => question.clone(),
+    };
     let mut full = format!(
         "{} here's what I found:\n\n**Q:** {}\n**Topic:** {} | **Iterations:** {}\n\n**A:** {}",
-        user_mention, question, topic, result.iterations, result.answer
+        user_mention, asked, topic, result.iterations, result.answer
     );
 
     // Append cited URLs as clickable Discord markdown links
@@ -71,30 +308,157 @@ pub async fn ask(
         }
     }
 
-    // Admin-only debug evidence
+    // Admin-only debug usage and evidence
+    if show_debug {
+        let total = PhaseUsage::sum(result.usage.values());
+        full.push_str(&format!(
+            "\n\n-# [Debug] {} LLM calls, {} prompt + {} completion tokens, {:.1} tok/s, {:.1}s total",
+            total.calls,
+            total.prompt_tokens,
+            total.completion_tokens,
+            total.tokens_per_sec(),
+            result.duration_ms as f64 / 1000.0
+        ));
+    }
     if show_debug && !result.evidence.is_empty() {
         full.push_str("\n\n---\n**[Debug] Evidence collected from documents:**\n");
         for (i, ev) in result.evidence.iter().enumerate().take(3) {
-            let snippet = if ev.len() > 800 {
-                &ev[..800]
-            } else {
-                ev.as_str()
-            };
-            full.push_str(&format!("\n**[{}]** ```\n{}```\n", i + 1, snippet));
+            full.push_str(&format!("\n**[{}]** ```\n{}```\n", i + 1, truncate(ev, 800)));
+        }
+    }
+
+    // Send in chunks if needed; a new thread gets the answer and the asking
+    // channel a ping linking to it
+    send_chunked(&ctx, new_thread.unwrap_or(ctx.channel_id()), &full).await?;
+    if let Some(channel) = new_thread {
+        ctx.channel_id()
+            .say(
+                ctx.http(),
+                format!("{} your answer is ready: <#{}>", user_mention, channel),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Store the usage of a question that ended without an answer, for
+/// `/edgar usage`. Nothing is stored if no LLM call was made.
+async fn record_unanswered(
+    ctx: &Context<'_>,
+    scope: Scope,
+    topic: &str,
+    question: &str,
+    outcome: QueryOutcome,
+    meter: &UsageMeter,
+    started: Instant,
+) {
+    let usage = meter.snapshot();
+    if usage.is_empty() {
+        return;
+    }
+    let record = QaRecord::unanswered(
+        scope,
+        topic,
+        question,
+        ctx.author().id.get(),
+        outcome,
+        usage,
+        started.elapsed().as_millis() as u64,
+    );
+    if let Err(e) = ctx.data().store.store_qa(&record).await {
+        warn!(error = %e, "Failed to store Q/A record");
+    }
+}
+
+/// Wait for the ticket's turn to run, reporting its place in line.
+async fn wait_in_line(ticket: &mut Ticket, progress: &ProgressSink) {
+    let mut shown = None;
+    loop {
+        let position = ticket.position();
+        if position != shown {
+            if let Some(position) = position {
+                progress.emit(Progress::Queued { position });
+            }
+            shown = position;
+        }
+        tokio::select! {
+            _ = ticket.admitted() => return,
+            _ = tokio::time::sleep(QUEUE_POLL) => {}
         }
     }
+}
+
+/// Show the query's progress as an embed under the acknowledgment. Edits go
+/// through the interaction token, which expires after 15 minutes, so a failed
+/// edit is only logged.
+async fn edit_status(
+    ctx: &Context<'_>,
+    status: &poise::ReplyHandle<'_>,
+    ack: &str,
+    title: &str,
+    log: &ProgressLog,
+) {
+    let embed = serenity::CreateEmbed::new()
+        .title(title)
+        .description(log.render());
+    let reply = poise::CreateReply::default().content(ack).embed(embed);
+    if let Err(e) = status.edit(*ctx, reply).await {
+        warn!(error = %e, "Failed to update status message");
+    }
+}
+
+/// Create a public thread for a question in the invoking channel, opened with
+/// the question itself.
+async fn open_question_thread(
+    ctx: &Context<'_>,
+    topic: &str,
+    question: &str,
+) -> Result<serenity::ChannelId, anyhow::Error> {
+    let thread = ctx
+        .channel_id()
+        .create_thread(
+            ctx.http(),
+            serenity::CreateThread::new(thread_name(question))
+                .kind(serenity::ChannelType::PublicThread),
+        )
+        .await?;
+    thread
+        .id
+        .say(
+            ctx.http(),
+            format!(
+                "<@{}> asked about **{}**:\n**Q:** {}",
+                ctx.author().id,
+                topic,
+                question
+            ),
+        )
+        .await?;
+    Ok(thread.id)
+}
 
-    // Send in chunks if needed
-    send_chunked(&ctx, &full).await
+/// A thread name from the question's first line, cut to Discord's limit.
+fn thread_name(question: &str) -> String {
+    let line = question.lines().next().unwrap_or_default().trim();
+    if line.chars().count() <= THREAD_NAME_MAX {
+        return line.to_string();
+    }
+    let mut name: String = line.chars().take(THREAD_NAME_MAX - 3).collect();
+    name.push_str("...");
+    name
 }
 
-/// Send a message in Discord-safe chunks (max 1990 chars).
-/// Uses ctx.say() for all chunks — poise routes follow-ups through the
-/// interaction webhook, which doesn't require Send Messages channel permission.
-async fn send_chunked(ctx: &Context<'_>, text: &str) -> Result<(), anyhow::Error> {
+/// Send a message in Discord-safe chunks (max 1990 chars) to `channel`. Posted
+/// as channel messages rather than interaction follow-ups: a question can run
+/// past the 15 minutes an interaction token stays valid.
+async fn send_chunked(
+    ctx: &Context<'_>,
+    channel: serenity::ChannelId,
+    text: &str,
+) -> Result<(), anyhow::Error> {
     let mut remaining = text;
     while !remaining.is_empty() {
-        let chunk_len = remaining.len().min(1990);
+        let chunk_len = truncate(remaining, 1990).len();
         let split_at = if chunk_len < remaining.len() {
             remaining[..chunk_len]
                 .rfind('\n')
@@ -107,14 +471,20 @@ async fn send_chunked(ctx: &Context<'_>, text: &str) -> Result<(), anyhow::Error
         let chunk = &remaining[..split_at];
         remaining = &remaining[split_at..];
 
-        ctx.say(chunk).await?;
+        channel.say(ctx.http(), chunk).await?;
     }
     Ok(())
 }
 
-/// Autocomplete for topic names from ingested document labels.
-async fn autocomplete_topic(ctx: Context<'_>, partial: &str) -> Vec<String> {
-    let labels = ctx.data().store.labels().await.unwrap_or_default();
+/// Autocomplete for topic names from the labels visible in the invoking guild.
+pub(super) async fn autocomplete_topic(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let Ok(guild) = guild_id(&ctx) else {
+        return Vec::new();
+    };
+    let labels = match ctx.data().view(guild).await {
+        Ok(view) => ctx.data().store.labels(&view.scopes()).await.unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
 
     labels
         .into_iter()
@@ -1,12 +1,22 @@
 use poise::serenity_prelude as serenity;
 
-use crate::state::Context;
+use super::guild_id;
+use crate::docs::types::{ConfigChange, Scope};
+use crate::state::{Context, ADMIN_ROLES_KEY, LIBRARY_KEY, RLM_CONFIG_KEY};
 
-/// Configure Edgar bot settings (admin only)
+/// Configure Edgar bot settings for this server (admin only)
 #[poise::command(
     slash_command,
     guild_only,
-    subcommands("rlm", "roles_list", "roles_add", "roles_remove")
+    subcommands(
+        "rlm",
+        "routes",
+        "roles_list",
+        "roles_add",
+        "roles_remove",
+        "library",
+        "audit"
+    )
 )]
 pub async fn config(_ctx: Context<'_>) -> Result<(), anyhow::Error> {
     Ok(())
@@ -20,52 +30,114 @@ pub async fn rlm(
     #[description = "Min code executions required"] min_code_executions: Option<u32>,
     #[description = "Min answer length (chars)"] min_answer_len: Option<u32>,
     #[description = "Parallel reasoning loops"] parallel_loops: Option<u32>,
+    #[description = "Follow-ups in a thread reuse the previous REPL session"]
+    reuse_session: Option<bool>,
+    #[description = "How deep rlm_query() may nest investigations (0 disables it)"]
+    rlm_query_depth: Option<u32>,
+    #[description = "Iterations each rlm_query() investigation gets"]
+    rlm_query_iterations: Option<u32>,
 ) -> Result<(), anyhow::Error> {
     if !is_admin(&ctx).await {
         ctx.say("This command is admin-only.").await?;
         return Ok(());
     }
+    // A loop with no iterations never gets to answer
+    if max_iterations == Some(0) || rlm_query_iterations == Some(0) {
+        ctx.say("`max_iterations` and `rlm_query_iterations` must be at least 1.")
+            .await?;
+        return Ok(());
+    }
 
     let has_updates = max_iterations.is_some()
         || min_code_executions.is_some()
         || min_answer_len.is_some()
-        || parallel_loops.is_some();
+        || parallel_loops.is_some()
+        || reuse_session.is_some()
+        || rlm_query_depth.is_some()
+        || rlm_query_iterations.is_some();
 
     if has_updates {
-        let mut config = ctx.data().rlm_config.write().await;
+        // Hold the write lock across the store commit so concurrent updates
+        // can't interleave, and only apply the change once it's persisted.
+        let mut settings = ctx.data().guild_settings_mut(guild_id(&ctx)?).await?;
+        let mut updated = settings.rlm.clone();
         let mut changes = Vec::new();
 
         if let Some(v) = max_iterations {
-            config.max_iterations = v;
-            changes.push(format!("`max_iterations` -> {v}"));
+            changes.push(format!(
+                "`max_iterations` {} -> {v}",
+                updated.max_iterations
+            ));
+            updated.max_iterations = v;
         }
         if let Some(v) = min_code_executions {
-            config.min_code_executions = v;
-            changes.push(format!("`min_code_executions` -> {v}"));
+            changes.push(format!(
+                "`min_code_executions` {} -> {v}",
+                updated.min_code_executions
+            ));
+            updated.min_code_executions = v;
         }
         if let Some(v) = min_answer_len {
-            config.min_answer_len = v as usize;
-            changes.push(format!("`min_answer_len` -> {v}"));
+            changes.push(format!(
+                "`min_answer_len` {} -> {v}",
+                updated.min_answer_len
+            ));
+            updated.min_answer_len = v as usize;
         }
         if let Some(v) = parallel_loops {
-            config.parallel_loops = v;
-            changes.push(format!("`parallel_loops` -> {v}"));
+            changes.push(format!(
+                "`parallel_loops` {} -> {v}",
+                updated.parallel_loops
+            ));
+            updated.parallel_loops = v;
+        }
+        if let Some(v) = reuse_session {
+            changes.push(format!(
+                "`reuse_session` {} -> {v}",
+                updated.reuse_session
+            ));
+            updated.reuse_session = v;
+        }
+        if let Some(v) = rlm_query_depth {
+            changes.push(format!(
+                "`rlm_query_depth` {} -> {v}",
+                updated.rlm_query_depth
+            ));
+            updated.rlm_query_depth = v;
         }
+        if let Some(v) = rlm_query_iterations {
+            changes.push(format!(
+                "`rlm_query_iterations` {} -> {v}",
+                updated.rlm_query_iterations
+            ));
+            updated.rlm_query_iterations = v;
+        }
+
+        let change = config_change(&ctx, RLM_CONFIG_KEY, changes.join(", "))?;
+        persist(&ctx, RLM_CONFIG_KEY, &updated, &change).await?;
+        settings.rlm = updated;
+        drop(settings);
 
         ctx.say(format!("**Updated:**\n{}", changes.join("\n")))
             .await?;
     } else {
-        let config = ctx.data().rlm_config.read().await;
+        let config = ctx.data().guild_settings(guild_id(&ctx)?).await?.rlm;
         ctx.say(format!(
             "**RLM Configuration:**\n\
              `max_iterations`: {}\n\
              `min_code_executions`: {}\n\
              `min_answer_len`: {}\n\
-             `parallel_loops`: {}",
+             `parallel_loops`: {}\n\
+             `reuse_session`: {}\n\
+             `rlm_query_depth`: {}\n\
+             `rlm_query_iterations`: {}",
             config.max_iterations,
             config.min_code_executions,
             config.min_answer_len,
             config.parallel_loops,
+            config.reuse_session,
+            config.rlm_query_depth,
+            config.rlm_query_iterations,
         ))
         .await?;
     }
@@ -73,6 +145,34 @@ pub async fn rlm(
     Ok(())
 }
 
+/// Show which model, sampling and endpoint each phase of a query uses
+#[poise::command(slash_command, guild_only)]
+pub async fn routes(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    if !is_admin(&ctx).await {
+        ctx.say("This command is admin-only.").await?;
+        return Ok(());
+    }
+
+    let llm = &ctx.data().llm;
+    let mut output = String::from("**LLM Routes:**\n");
+    for (phase, route) in llm.routes() {
+        output.push_str(&format!(
+            "`{}`: `{}` · temperature {} · {} max tokens · <{}>\n",
+            phase.as_str(),
+            route.model,
+            route.temperature,
+            route.max_tokens,
+            route.base_url
+        ));
+    }
+    output.push_str(&format!(
+        "Context window: {} tokens. Set with `LLM_ROUTES_FILE` or `LLM_ROUTE_<PHASE>_*` env vars.",
+        llm.context_tokens()
+    ));
+    ctx.say(output).await?;
+    Ok(())
+}
+
 /// List configured admin roles
 #[poise::command(slash_command, guild_only, rename = "roles-list")]
 pub async fn roles_list(ctx: Context<'_>) -> Result<(), anyhow::Error> {
@@ -81,7 +181,11 @@ pub async fn roles_list(ctx: Context<'_>) -> Result<(), anyhow::Error> {
         return Ok(());
     }
 
-    let role_ids = ctx.data().admin_role_ids.read().await;
+    let role_ids = ctx
+        .data()
+        .guild_settings(guild_id(&ctx)?)
+        .await?
+        .admin_roles;
     if role_ids.is_empty() {
         ctx.say("**Admin Roles:** none configured").await?;
     } else {
@@ -105,7 +209,20 @@ pub async fn roles_add(
     }
 
     let role_id = role.id.get();
-    let inserted = ctx.data().admin_role_ids.write().await.insert(role_id);
+    let mut settings = ctx.data().guild_settings_mut(guild_id(&ctx)?).await?;
+    let inserted = !settings.admin_roles.contains(&role_id);
+    if inserted {
+        let mut updated = settings.admin_roles.clone();
+        updated.insert(role_id);
+        let change = config_change(
+            &ctx,
+            ADMIN_ROLES_KEY,
+            format!("added role {} ({role_id})", role.name),
+        )?;
+        persist_roles(&ctx, &updated, &change).await?;
+        settings.admin_roles = updated;
+    }
+    drop(settings);
 
     if inserted {
         ctx.say(format!("Added <@&{role_id}> as admin role."))
@@ -130,7 +247,20 @@ pub async fn roles_remove(
     }
 
     let role_id = role.id.get();
-    let removed = ctx.data().admin_role_ids.write().await.remove(&role_id);
+    let mut settings = ctx.data().guild_settings_mut(guild_id(&ctx)?).await?;
+    let removed = settings.admin_roles.contains(&role_id);
+    if removed {
+        let mut updated = settings.admin_roles.clone();
+        updated.remove(&role_id);
+        let change = config_change(
+            &ctx,
+            ADMIN_ROLES_KEY,
+            format!("removed role {} ({role_id})", role.name),
+        )?;
+        persist_roles(&ctx, &updated, &change).await?;
+        settings.admin_roles = updated;
+    }
+    drop(settings);
 
     if removed {
         ctx.say(format!("Removed <@&{role_id}> from admin roles."))
@@ -143,12 +273,135 @@ pub async fn roles_remove(
     Ok(())
 }
 
+/// Show or set whether this server sees the shared global document library
+#[poise::command(slash_command, guild_only)]
+pub async fn library(
+    ctx: Context<'_>,
+    #[description = "Include global library topics and documents"] enabled: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    if !is_admin(&ctx).await {
+        ctx.say("This command is admin-only.").await?;
+        return Ok(());
+    }
+
+    let Some(enabled) = enabled else {
+        let settings = ctx.data().guild_settings(guild_id(&ctx)?).await?;
+        ctx.say(format!(
+            "Global library is **{}** for this server.",
+            if settings.include_global {
+                "enabled"
+            } else {
+                "disabled"
+            }
+        ))
+        .await?;
+        return Ok(());
+    };
+
+    let mut settings = ctx.data().guild_settings_mut(guild_id(&ctx)?).await?;
+    if settings.include_global != enabled {
+        let change = config_change(
+            &ctx,
+            LIBRARY_KEY,
+            format!("`include_global` {} -> {enabled}", settings.include_global),
+        )?;
+        persist(&ctx, LIBRARY_KEY, &enabled, &change).await?;
+        settings.include_global = enabled;
+    }
+    drop(settings);
+
+    ctx.say(format!(
+        "Global library **{}** for this server.",
+        if enabled { "enabled" } else { "disabled" }
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Show recent configuration changes
+#[poise::command(slash_command, guild_only)]
+pub async fn audit(
+    ctx: Context<'_>,
+    #[description = "Max entries to show"] limit: Option<u32>,
+) -> Result<(), anyhow::Error> {
+    if !is_admin(&ctx).await {
+        ctx.say("This command is admin-only.").await?;
+        return Ok(());
+    }
+
+    let limit = limit.unwrap_or(10).min(25) as usize;
+    let scope = Scope::Guild(guild_id(&ctx)?);
+    let changes = ctx.data().store.config_audit(scope, limit).await?;
+    if changes.is_empty() {
+        ctx.say("No configuration changes recorded.").await?;
+        return Ok(());
+    }
+
+    let mut output = String::from("**Config Changes:**\n");
+    for c in &changes {
+        let line = format!(
+            "<t:{}:f> **{}** by <@{}>: {}\n",
+            c.timestamp, c.key, c.user_id, c.summary
+        );
+        if output.len() + line.len() > 1990 {
+            break;
+        }
+        output.push_str(&line);
+    }
+    ctx.say(output).await?;
+    Ok(())
+}
+
+fn config_change(
+    ctx: &Context<'_>,
+    key: &str,
+    summary: String,
+) -> Result<ConfigChange, anyhow::Error> {
+    Ok(ConfigChange {
+        key: key.to_string(),
+        scope: Scope::Guild(guild_id(ctx)?),
+        user_id: ctx.author().id.get(),
+        user_name: ctx.author().name.clone(),
+        summary,
+        timestamp: chrono::Utc::now().timestamp(),
+    })
+}
+
+/// Persist a setting in the invoking guild's scope.
+async fn persist<T: serde::Serialize>(
+    ctx: &Context<'_>,
+    key: &str,
+    value: &T,
+    change: &ConfigChange,
+) -> Result<(), anyhow::Error> {
+    ctx.data()
+        .store
+        .put_config(change.scope, key, value, change)
+        .await
+}
+
+async fn persist_roles(
+    ctx: &Context<'_>,
+    role_ids: &std::collections::HashSet<u64>,
+    change: &ConfigChange,
+) -> Result<(), anyhow::Error> {
+    let mut sorted: Vec<u64> = role_ids.iter().copied().collect();
+    sorted.sort_unstable();
+    persist(ctx, ADMIN_ROLES_KEY, &sorted, change).await
+}
+
+/// Check if the invoking user is a bot operator (`ADMIN_USER_IDS`), who can
+/// manage the global library.
+pub fn is_operator(ctx: &Context<'_>) -> bool {
+    ctx.data().admin_ids.contains(&ctx.author().id.get())
+}
+
 /// Check if the invoking user is an admin via: user ID allowlist → guild owner → admin roles.
 pub async fn is_admin(ctx: &Context<'_>) -> bool {
     let user_id = ctx.author().id.get();
 
     // 1. Explicit user ID allowlist (env var)
-    if ctx.data().admin_ids.contains(&user_id) {
+    if is_operator(ctx) {
         return true;
     }
 
@@ -159,8 +412,17 @@ pub async fn is_admin(ctx: &Context<'_>) -> bool {
         }
     }
 
-    // 3. Admin roles (if any configured)
-    let role_ids = ctx.data().admin_role_ids.read().await;
+    // 3. This guild's admin roles (if any configured)
+    let Some(guild) = ctx.guild_id() else {
+        return false;
+    };
+    let role_ids = match ctx.data().guild_settings(guild.get()).await {
+        Ok(settings) => settings.admin_roles,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to load guild settings");
+            return false;
+        }
+    };
     if !role_ids.is_empty() {
         if let Some(member) = ctx.author_member().await {
             if member.roles.iter().any(|r| role_ids.contains(&r.get())) {
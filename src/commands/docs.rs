@@ -0,0 +1,429 @@
+use poise::serenity_prelude as serenity;
+
+use super::ask::autocomplete_topic;
+use super::config::{is_admin, is_operator};
+use super::guild_id;
+use crate::docs::types::Scope;
+use crate::state::Context;
+
+/// Manage this server's documents (admin only; bot operators also manage the global library)
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands("info", "files", "relabel", "delete", "history", "rollback")
+)]
+pub async fn docs(_ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    Ok(())
+}
+
+/// Show a document's metadata and top-level file tree
+#[poise::command(slash_command, guild_only)]
+pub async fn info(
+    ctx: Context<'_>,
+    #[description = "Document"]
+    #[autocomplete = "autocomplete_doc"]
+    doc: String,
+) -> Result<(), anyhow::Error> {
+    if !is_admin(&ctx).await {
+        ctx.say("This command is admin-only.").await?;
+        return Ok(());
+    }
+
+    let store = &ctx.data().store;
+    let doc_id = store.resolve_id(&managed_scopes(&ctx)?, &doc).await?;
+    let meta = store.get_meta(&doc_id).await?;
+    let files = store.list_files(&doc_id).await?;
+
+    let ingested = chrono::DateTime::from_timestamp(meta.ingested_at, 0)
+        .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default();
+    let status = if meta.superseded {
+        "superseded"
+    } else {
+        "active"
+    };
+
+    let mut output = format!(
+        "**{}**\n\
+         Doc ID: `{}`\n\
+         Topic: **{}**\n\
+         Source: {}\n\
+         Version: v{} ({})\n\
+         Commit: {}\n\
+         Size: {} bytes\n\
+         Ingested: {}\n\
+         URL context: {}\n\
+         Files: {}\n",
+        meta.name,
+        meta.id,
+        meta.label,
+        meta.source,
+        meta.version,
+        status,
+        meta.commit.as_deref().unwrap_or("—"),
+        meta.size,
+        ingested,
+        meta.url_context.as_deref().unwrap_or("—"),
+        files.len(),
+    );
+
+    let tree = top_level_tree(files.iter().map(|f| f.path.as_str()));
+    if !tree.is_empty() {
+        output.push_str("```\n");
+        for (entry, count) in &tree {
+            if *count > 1 || entry.ends_with('/') {
+                output.push_str(&format!("{:<40} {} files\n", entry, count));
+            } else {
+                output.push_str(&format!("{}\n", entry));
+            }
+        }
+        output.push_str("```");
+    }
+
+    ctx.say(truncate_message(output)).await?;
+    Ok(())
+}
+
+/// List a document's files
+#[poise::command(slash_command, guild_only)]
+pub async fn files(
+    ctx: Context<'_>,
+    #[description = "Document"]
+    #[autocomplete = "autocomplete_doc"]
+    doc: String,
+    #[description = "Only paths starting with this prefix, e.g. docs/"] prefix: Option<String>,
+) -> Result<(), anyhow::Error> {
+    if !is_admin(&ctx).await {
+        ctx.say("This command is admin-only.").await?;
+        return Ok(());
+    }
+
+    let store = &ctx.data().store;
+    let doc_id = store.resolve_id(&managed_scopes(&ctx)?, &doc).await?;
+    let prefix = prefix.unwrap_or_default();
+    let files: Vec<_> = store
+        .list_files(&doc_id)
+        .await?
+        .into_iter()
+        .filter(|f| f.path.starts_with(&prefix))
+        .collect();
+
+    if files.is_empty() {
+        ctx.say("No matching files.").await?;
+        return Ok(());
+    }
+
+    let mut output = format!("**{} files**\n```\n", files.len());
+    for f in &files {
+        output.push_str(&format!("{} ({} B)\n", f.path, f.size));
+    }
+    output.push_str("```");
+
+    // Chunk at line boundaries, reopening the code block in each message
+    let mut chunk = String::new();
+    for line in output.lines() {
+        if chunk.len() + line.len() + 5 > 1990 {
+            if !chunk.ends_with("```\n") {
+                chunk.push_str("```");
+            }
+            ctx.say(std::mem::take(&mut chunk)).await?;
+            chunk.push_str("```\n");
+        }
+        chunk.push_str(line);
+        chunk.push('\n');
+    }
+    if !chunk.is_empty() {
+        ctx.say(chunk).await?;
+    }
+    Ok(())
+}
+
+/// Move a document to another topic
+#[poise::command(slash_command, guild_only)]
+pub async fn relabel(
+    ctx: Context<'_>,
+    #[description = "Document"]
+    #[autocomplete = "autocomplete_doc"]
+    doc: String,
+    #[description = "New topic label"]
+    #[autocomplete = "autocomplete_topic"]
+    topic: String,
+) -> Result<(), anyhow::Error> {
+    if !is_admin(&ctx).await {
+        ctx.say("This command is admin-only.").await?;
+        return Ok(());
+    }
+
+    let store = &ctx.data().store;
+    let doc_id = store.resolve_id(&managed_scopes(&ctx)?, &doc).await?;
+    let old = store.get_meta(&doc_id).await?;
+    let meta = store.relabel(&doc_id, &topic).await?;
+
+    tracing::info!(user = ctx.author().name, doc_id = %doc_id, from = %old.label, to = %topic, "Document relabeled");
+    ctx.say(format!(
+        "Moved **{}** from **'{}'** to **'{}'** (v{}).",
+        meta.name, old.label, meta.label, meta.version
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Delete a document and the Q/A records that cite it
+#[poise::command(slash_command, guild_only)]
+pub async fn delete(
+    ctx: Context<'_>,
+    #[description = "Document"]
+    #[autocomplete = "autocomplete_doc"]
+    doc: String,
+) -> Result<(), anyhow::Error> {
+    if !is_admin(&ctx).await {
+        ctx.say("This command is admin-only.").await?;
+        return Ok(());
+    }
+
+    let store = &ctx.data().store;
+    let doc_id = store.resolve_id(&managed_scopes(&ctx)?, &doc).await?;
+    let meta = store.get_meta(&doc_id).await?;
+    let restored = store.delete(&doc_id).await?;
+
+    tracing::info!(user = ctx.author().name, doc_id = %doc_id, label = %meta.label, "Document deleted");
+    let restored_note = restored
+        .map(|prev| {
+            format!(
+                "\nv{} of {} is active in **'{}'** again.",
+                prev.version, prev.source, prev.label
+            )
+        })
+        .unwrap_or_default();
+    ctx.say(format!(
+        "Deleted **{}** (`{}`) from topic **'{}'**.{}",
+        meta.name,
+        &doc_id[..12],
+        meta.label,
+        restored_note
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Show the version history of a topic's sources
+#[poise::command(slash_command, guild_only)]
+pub async fn history(
+    ctx: Context<'_>,
+    #[description = "Topic label"]
+    #[autocomplete = "autocomplete_topic"]
+    topic: String,
+    #[description = "Source, e.g. github:owner/repo (default: all sources in the topic)"]
+    source: Option<String>,
+    #[description = "Show details of one version (requires source)"] version: Option<u32>,
+    #[description = "Use the global library (bot operators only)"] global: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    if !is_admin(&ctx).await {
+        ctx.say("This command is admin-only.").await?;
+        return Ok(());
+    }
+    let Some(scope) = target_scope(&ctx, global).await? else {
+        return Ok(());
+    };
+
+    let store = &ctx.data().store;
+
+    if let (Some(source), Some(version)) = (source.as_deref(), version) {
+        let meta = store.get_version(scope, &topic, source, version).await?;
+        ctx.say(format!(
+            "**{}** v{}{}\nDoc ID: `{}`\nSize: {} bytes\nCommit: {}\nURL context: {}",
+            meta.name,
+            version,
+            if meta.superseded { " (superseded)" } else { " (active)" },
+            meta.id,
+            meta.size,
+            meta.commit.as_deref().unwrap_or("—"),
+            meta.url_context.as_deref().unwrap_or("—"),
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    let histories = match source {
+        Some(source) => store.source_history(scope, &topic, &source).await?.into_iter().collect(),
+        None => store.list_sources(scope, &topic).await?,
+    };
+
+    if histories.is_empty() {
+        ctx.say(format!("No versioned sources in topic **'{}'**.", topic))
+            .await?;
+        return Ok(());
+    }
+
+    let mut output = format!("**Version history for '{}'**\n\n", topic);
+    for history in &histories {
+        output.push_str(&format!("**{}**\n", history.source));
+        for v in history.versions.iter().rev() {
+            let when = chrono::DateTime::from_timestamp(v.ingested_at, 0)
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            let commit = v
+                .commit
+                .as_deref()
+                .map(|c| format!(" @ `{}`", &c[..c.len().min(10)]))
+                .unwrap_or_default();
+            let active = if v.version == history.active { " **(active)**" } else { "" };
+            output.push_str(&format!(
+                "  - v{} — `{}` — {}{}{}\n",
+                v.version,
+                &v.doc_id[..12],
+                when,
+                commit,
+                active
+            ));
+        }
+        output.push('\n');
+    }
+
+    ctx.say(truncate_message(output)).await?;
+    Ok(())
+}
+
+/// Make an earlier (or later) version of a source active in its topic
+#[poise::command(slash_command, guild_only)]
+pub async fn rollback(
+    ctx: Context<'_>,
+    #[description = "Topic label"]
+    #[autocomplete = "autocomplete_topic"]
+    topic: String,
+    #[description = "Source, e.g. github:owner/repo"] source: String,
+    #[description = "Version number to activate"] version: u32,
+    #[description = "Use the global library (bot operators only)"] global: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    if !is_admin(&ctx).await {
+        ctx.say("This command is admin-only.").await?;
+        return Ok(());
+    }
+    let Some(scope) = target_scope(&ctx, global).await? else {
+        return Ok(());
+    };
+
+    let meta = ctx
+        .data()
+        .store
+        .rollback(scope, &topic, &source, version)
+        .await?;
+
+    ctx.say(format!(
+        "Topic **'{}'** now uses **{}** v{} (`{}`).",
+        topic,
+        meta.name,
+        version,
+        &meta.id[..12]
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Scopes whose documents the invoking admin may manage: their guild's, plus
+/// the global library for bot operators.
+fn managed_scopes(ctx: &Context<'_>) -> Result<Vec<Scope>, anyhow::Error> {
+    let mut scopes = vec![Scope::Guild(guild_id(ctx)?)];
+    if is_operator(ctx) {
+        scopes.push(Scope::Global);
+    }
+    Ok(scopes)
+}
+
+/// The scope a history/rollback targets. Replies and returns `None` if a
+/// non-operator asks for the global library.
+async fn target_scope(
+    ctx: &Context<'_>,
+    global: Option<bool>,
+) -> Result<Option<Scope>, anyhow::Error> {
+    if !global.unwrap_or(false) {
+        return Ok(Some(Scope::Guild(guild_id(ctx)?)));
+    }
+    if !is_operator(ctx) {
+        ctx.say("Only bot operators can manage the global library.")
+            .await?;
+        return Ok(None);
+    }
+    Ok(Some(Scope::Global))
+}
+
+/// Autocomplete documents by name, topic or ID prefix. Values are full doc IDs.
+async fn autocomplete_doc(ctx: Context<'_>, partial: &str) -> Vec<serenity::AutocompleteChoice> {
+    let Ok(scopes) = managed_scopes(&ctx) else {
+        return Vec::new();
+    };
+    let docs = ctx.data().store.list(&scopes, usize::MAX, 0).await.unwrap_or_default();
+    let partial = partial.to_lowercase();
+
+    docs.into_iter()
+        .filter(|d| {
+            d.name.to_lowercase().contains(&partial)
+                || d.label.to_lowercase().contains(&partial)
+                || d.id.starts_with(&partial)
+        })
+        .take(25)
+        .map(|d| {
+            let mut status = if d.superseded { ", superseded" } else { "" }.to_string();
+            if d.scope == Scope::Global {
+                status.push_str(", global");
+            }
+            let name = format!("{} [{}] v{}{} — {}", d.name, d.label, d.version, status, &d.id[..12]);
+            // Discord caps choice names at 100 chars
+            let name: String = name.chars().take(100).collect();
+            serenity::AutocompleteChoice::new(name, d.id)
+        })
+        .collect()
+}
+
+/// Group paths by their first component: `("src/", 42)`, `("README.md", 1)`.
+fn top_level_tree<'a>(paths: impl Iterator<Item = &'a str>) -> Vec<(String, usize)> {
+    let mut tree: std::collections::BTreeMap<String, usize> = std::collections::BTreeMap::new();
+    for path in paths {
+        let entry = match path.split_once('/') {
+            Some((dir, _)) => format!("{}/", dir),
+            None => path.to_string(),
+        };
+        *tree.entry(entry).or_default() += 1;
+    }
+    tree.into_iter().collect()
+}
+
+/// Keep a message within Discord's 2000-char limit, cutting at a line break.
+fn truncate_message(mut output: String) -> String {
+    if output.len() > 1990 {
+        let mut cut = 1990;
+        while !output.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        let cut = output[..cut].rfind('\n').unwrap_or(cut);
+        output.truncate(cut);
+        // Close an open code block
+        if output.matches("```").count() % 2 == 1 {
+            output.push_str("\n```");
+        }
+        output.push_str("\n…");
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_top_level_tree() {
+        let tree = top_level_tree(["src/a.rs", "src/b/c.rs", "README.md"].into_iter());
+        assert_eq!(
+            tree,
+            vec![("README.md".to_string(), 1), ("src/".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_truncate_message_closes_code_block() {
+        let long = format!("```\n{}", "line\n".repeat(500));
+        let out = truncate_message(long);
+        assert!(out.len() <= 2000);
+        assert_eq!(out.matches("```").count(), 2);
+    }
+}
@@ -1,6 +1,9 @@
+use super::config::is_operator;
+use super::guild_id;
 use crate::docs::ingest as doc_ingest;
+use crate::docs::types::Scope;
 use crate::state::Context;
-use tracing::info;
+use tracing::{info, warn};
 
 /// Ingest a document from a URL (GitHub repo or web page)
 #[poise::command(slash_command, guild_only)]
@@ -10,16 +13,30 @@ pub async fn ingest(
     #[description = "Topic label for this document"] label: String,
     #[description = "Type: documentation, code, minimal"]
     doc_type: Option<String>,
-    #[description = "Git branch (default: main)"] branch: Option<String>,
+    #[description = "Git branch, tag, or commit SHA (default: repo default branch)"] branch: Option<String>,
     #[description = "URL attribution context, e.g. 'files in docs/ map to https://example.com/docs'"]
     url_context: Option<String>,
+    #[description = "Add to the global library shared with all servers (bot operators only)"]
+    global: Option<bool>,
 ) -> Result<(), anyhow::Error> {
+    let scope = if global.unwrap_or(false) {
+        if !is_operator(&ctx) {
+            ctx.say("Only bot operators can add to the global library.")
+                .await?;
+            return Ok(());
+        }
+        Scope::Global
+    } else {
+        Scope::Guild(guild_id(&ctx)?)
+    };
+
     ctx.defer().await?;
 
     info!(
         user = ctx.author().name,
         url,
         label,
+        scope = %scope.key(),
         "Ingestion started"
     );
 
@@ -29,6 +46,7 @@ pub async fn ingest(
     let (doc_id, detail) = if is_github {
         let (id, file_count) = doc_ingest::ingest_github_repo(
             store,
+            scope,
             &url,
             &label,
             doc_type.as_deref(),
@@ -38,21 +56,43 @@ pub async fn ingest(
         .await?;
         (id, format!("{} files", file_count))
     } else {
-        let (id, size) = doc_ingest::ingest_url(store, &url, &label).await?;
+        let (id, size) = doc_ingest::ingest_url(store, scope, &url, &label).await?;
         (id, format!("{} bytes", size))
     };
 
     let meta = store.get_meta(&doc_id).await?;
 
+    // Semantic search is optional — keyword search still works if embedding fails
+    let embed_note = match doc_ingest::embed_document(store, &ctx.data().llm, &doc_id).await {
+        Ok(0) => String::new(),
+        Ok(n) => format!("\nEmbedded {} passages for semantic search", n),
+        Err(e) => {
+            warn!(doc_id = %doc_id, error = %e, "Embedding failed");
+            "\nEmbedding failed; semantic search unavailable for this doc".to_string()
+        }
+    };
+
+    let commit_note = meta
+        .commit
+        .as_deref()
+        .map(|c| format!("\nCommit: `{}`", c))
+        .unwrap_or_default();
+
     let url_note = meta
         .url_context
         .as_deref()
         .map(|u| format!("\nURL context: {}", u))
         .unwrap_or_default();
 
+    let library = if scope == Scope::Global {
+        " in the global library"
+    } else {
+        ""
+    };
+
     ctx.say(format!(
-        "Ingested **{}** ({}) under topic **'{}'**\nDoc ID: `{}`\nSize: {} bytes{}",
-        meta.name, detail, label, doc_id, meta.size, url_note
+        "Ingested **{}** ({}) under topic **'{}'**{}\nDoc ID: `{}`\nSize: {} bytes{}{}{}",
+        meta.name, detail, label, library, doc_id, meta.size, commit_note, url_note, embed_note
     ))
     .await?;
 
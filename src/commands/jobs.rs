@@ -0,0 +1,80 @@
+use super::config::is_admin;
+use super::guild_id;
+use crate::state::Context;
+
+/// Inspect and cancel questions Edgar is researching in this server
+#[poise::command(slash_command, guild_only, subcommands("list", "cancel"))]
+pub async fn jobs(_ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    Ok(())
+}
+
+/// List the questions being researched in this server
+#[poise::command(slash_command, guild_only)]
+pub async fn list(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    let jobs = ctx.data().jobs.list(guild_id(&ctx)?);
+    if jobs.is_empty() {
+        ctx.say("No questions are being researched right now.")
+            .await?;
+        return Ok(());
+    }
+
+    let mut output = String::from("**Running Jobs:**\n");
+    for job in &jobs {
+        let question: String = job.question.chars().take(120).collect();
+        let ellipsis = if question.len() < job.question.len() {
+            "..."
+        } else {
+            ""
+        };
+        let line = format!(
+            "`#{}` **{}** by <@{}>, started <t:{}:R> — {}\n> {}{}\n",
+            job.id, job.topic, job.user_id, job.started_at, job.phase, question, ellipsis
+        );
+        if output.len() + line.len() > 1990 {
+            break;
+        }
+        output.push_str(&line);
+    }
+    ctx.say(output).await?;
+    Ok(())
+}
+
+/// Cancel a running question (your own; admins can cancel any)
+#[poise::command(slash_command, guild_only)]
+pub async fn cancel(
+    ctx: Context<'_>,
+    #[description = "Job number from /edgar jobs list"] id: u64,
+) -> Result<(), anyhow::Error> {
+    let job = ctx
+        .data()
+        .jobs
+        .get(id)
+        .filter(|job| Some(job.guild) == ctx.guild_id().map(|g| g.get()));
+    let Some(job) = job else {
+        ctx.say(format!("No running job #{} in this server.", id))
+            .await?;
+        return Ok(());
+    };
+
+    if job.user_id != ctx.author().id.get() && !is_admin(&ctx).await {
+        ctx.say("You can only cancel your own questions.").await?;
+        return Ok(());
+    }
+
+    if ctx.data().jobs.cancel(id) {
+        tracing::info!(
+            job = id,
+            by = ctx.author().name,
+            owner = job.user_name,
+            "Job cancelled"
+        );
+        ctx.say(format!(
+            "Cancelled job #{} (**{}** asked by <@{}>).",
+            id, job.topic, job.user_id
+        ))
+        .await?;
+    } else {
+        ctx.say(format!("Job #{} already finished.", id)).await?;
+    }
+    Ok(())
+}
@@ -1,14 +1,32 @@
+use super::thread_id;
 use crate::state::Context;
 use poise::serenity_prelude as serenity;
 
-/// Clear session (stateless PoC — acknowledgment only)
+/// Forget this thread's conversation: earlier questions, answers and REPL state
 #[poise::command(slash_command, guild_only)]
 pub async fn clear(ctx: Context<'_>) -> Result<(), anyhow::Error> {
-    ctx.say("Session cleared.").await?;
+    let Some(thread) = thread_id(&ctx).await else {
+        ctx.say("Conversation memory lives in threads — run this inside the thread you want to reset.")
+            .await?;
+        return Ok(());
+    };
+
+    let forgotten = ctx.data().conversations.clear(thread).await;
+    if forgotten == 0 {
+        ctx.say("Nothing to clear — this thread has no conversation yet.")
+            .await?;
+    } else {
+        ctx.say(format!(
+            "Conversation cleared ({} earlier question(s) forgotten). The next question starts fresh.",
+            forgotten
+        ))
+        .await?;
+    }
     Ok(())
 }
 
-/// Create a new conversation thread
+/// Create a new conversation thread; questions asked in it can follow up on
+/// each other
 #[poise::command(slash_command, guild_only)]
 pub async fn thread(
     ctx: Context<'_>,
@@ -1,8 +1,13 @@
 mod ask;
 mod config;
+mod docs;
 mod ingest;
+mod jobs;
 mod manage;
 mod sources;
+mod usage;
+
+use anyhow::Context as _;
 
 use crate::state::Context;
 
@@ -15,9 +20,25 @@ use crate::state::Context;
         "sources::sources",
         "manage::clear",
         "manage::thread",
-        "config::config"
+        "config::config",
+        "docs::docs",
+        "jobs::jobs",
+        "usage::usage"
     )
 )]
 pub async fn edgar(_ctx: Context<'_>) -> Result<(), anyhow::Error> {
     Ok(())
 }
+
+/// The invoking guild. Every subcommand is `guild_only`, so this only fails in DMs.
+fn guild_id(ctx: &Context<'_>) -> anyhow::Result<u64> {
+    ctx.guild_id()
+        .map(|g| g.get())
+        .context("this command only works in a server")
+}
+
+/// The invoking channel's ID if it is a thread — where conversation memory lives.
+async fn thread_id(ctx: &Context<'_>) -> Option<u64> {
+    let channel = ctx.guild_channel().await?;
+    channel.thread_metadata.is_some().then(|| channel.id.get())
+}
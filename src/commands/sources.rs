@@ -1,13 +1,25 @@
+use super::guild_id;
+use crate::docs::types::Scope;
+use crate::rlm::context::truncate;
 use crate::state::Context;
 
-/// List ingested document sources
+/// List document sources visible in this server
 #[poise::command(slash_command, guild_only)]
 pub async fn sources(
     ctx: Context<'_>,
     #[description = "Max documents to show"] limit: Option<u32>,
 ) -> Result<(), anyhow::Error> {
     let limit = limit.unwrap_or(20) as usize;
-    let docs = ctx.data().store.list(limit, 0).await?;
+    let view = ctx.data().view(guild_id(&ctx)?).await?;
+    let docs: Vec<_> = ctx
+        .data()
+        .store
+        .list(&view.scopes(), usize::MAX, 0)
+        .await?
+        .into_iter()
+        .filter(|d| !d.superseded)
+        .take(limit)
+        .collect();
 
     if docs.is_empty() {
         ctx.say("No documents ingested yet. Use `/edgar ingest` to add some.")
@@ -30,9 +42,19 @@ pub async fn sources(
         output.push_str(&format!("**Topic: {}**\n", label));
         for doc in label_docs {
             let size_kb = doc.size / 1024;
+            let version = if doc.version > 0 {
+                format!(" v{}", doc.version)
+            } else {
+                String::new()
+            };
+            let global = if doc.scope == Scope::Global {
+                " [global]"
+            } else {
+                ""
+            };
             output.push_str(&format!(
-                "  - {} ({} KB) — `{}`\n    Source: {}\n",
-                doc.name, size_kb, &doc.id[..12], doc.source
+                "  - {}{}{} ({} KB) — `{}`\n    Source: {}\n",
+                doc.name, version, global, size_kb, &doc.id[..12], doc.source
             ));
         }
         output.push('\n');
@@ -45,7 +67,7 @@ pub async fn sources(
     } else {
         let mut remaining = output.as_str();
         while !remaining.is_empty() {
-            let end = remaining.len().min(1990);
+            let end = truncate(remaining, 1990).len();
             let split_at = if end < remaining.len() {
                 remaining[..end]
                     .rfind('\n')
@@ -0,0 +1,132 @@
+use poise::serenity_prelude as serenity;
+
+use super::ask::autocomplete_topic;
+use super::config::is_admin;
+use super::guild_id;
+use crate::docs::types::{PhaseUsage, QueryOutcome, Scope};
+use crate::rlm::usage::{by_phase, summarize, Grouping};
+use crate::state::Context;
+
+/// How many days `/edgar usage` looks back by default.
+const DEFAULT_DAYS: u32 = 7;
+
+#[derive(Debug, poise::ChoiceParameter)]
+pub enum GroupBy {
+    User,
+    Topic,
+    Day,
+}
+
+impl From<GroupBy> for Grouping {
+    fn from(group: GroupBy) -> Self {
+        match group {
+            GroupBy::User => Grouping::User,
+            GroupBy::Topic => Grouping::Topic,
+            GroupBy::Day => Grouping::Day,
+        }
+    }
+}
+
+/// LLM token usage of this server's questions (admin only)
+#[poise::command(slash_command, guild_only)]
+pub async fn usage(
+    ctx: Context<'_>,
+    #[description = "Group by user, topic or day (default: user)"] by: Option<GroupBy>,
+    #[description = "Days to look back (default: 7)"] days: Option<u32>,
+    #[description = "Only this user's questions"] user: Option<serenity::User>,
+    #[description = "Only this topic"]
+    #[autocomplete = "autocomplete_topic"]
+    topic: Option<String>,
+) -> Result<(), anyhow::Error> {
+    if !is_admin(&ctx).await {
+        ctx.say("This command is admin-only.").await?;
+        return Ok(());
+    }
+
+    let days = days.unwrap_or(DEFAULT_DAYS).max(1);
+    let since = chrono::Utc::now().timestamp() - i64::from(days) * 24 * 60 * 60;
+    let grouping = Grouping::from(by.unwrap_or(GroupBy::User));
+
+    let mut records = ctx
+        .data()
+        .store
+        .list_qa_since(Scope::Guild(guild_id(&ctx)?), since)
+        .await?;
+    if let Some(user) = &user {
+        records.retain(|r| r.user_id == user.id.get());
+    }
+    if let Some(topic) = &topic {
+        records.retain(|r| r.topic == *topic);
+    }
+    if records.is_empty() {
+        ctx.say(format!("No questions in the last {} day(s).", days))
+            .await?;
+        return Ok(());
+    }
+
+    let phases = by_phase(&records);
+    let total = PhaseUsage::sum(phases.values());
+    let unanswered = records
+        .iter()
+        .filter(|r| r.outcome != QueryOutcome::Answered)
+        .count();
+    let mut output = format!(
+        "**LLM usage, last {} day(s):** {} question(s), {}\n",
+        days,
+        records.len(),
+        describe(&total)
+    );
+    if unanswered > 0 {
+        output.push_str(&format!(
+            "-# Includes {} cancelled or failed question(s)\n",
+            unanswered
+        ));
+    }
+
+    output.push_str("\n**By phase:**\n");
+    for (phase, usage) in &phases {
+        output.push_str(&format!("`{}` {}\n", phase, describe(usage)));
+    }
+
+    output.push_str(&format!("\n**By {}:**\n", grouping_name(grouping)));
+    for (key, totals) in summarize(&records, grouping) {
+        let label = match grouping {
+            Grouping::User if key == "0" => "unknown".to_string(),
+            Grouping::User => format!("<@{}>", key),
+            _ => format!("**{}**", key),
+        };
+        let line = format!(
+            "{} — {} question(s), {}, avg {:.0}s per question\n",
+            label,
+            totals.queries,
+            describe(&totals.usage),
+            totals.duration_ms as f64 / 1000.0 / f64::from(totals.queries)
+        );
+        if output.len() + line.len() > 1990 {
+            break;
+        }
+        output.push_str(&line);
+    }
+
+    ctx.say(output).await?;
+    Ok(())
+}
+
+fn grouping_name(grouping: Grouping) -> &'static str {
+    match grouping {
+        Grouping::User => "user",
+        Grouping::Topic => "topic",
+        Grouping::Day => "day",
+    }
+}
+
+/// e.g. "12 calls, 9000 prompt + 800 completion tokens, 21.5 tok/s"
+fn describe(usage: &PhaseUsage) -> String {
+    format!(
+        "{} calls, {} prompt + {} completion tokens, {:.1} tok/s",
+        usage.calls,
+        usage.prompt_tokens,
+        usage.completion_tokens,
+        usage.tokens_per_sec()
+    )
+}
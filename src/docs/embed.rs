@@ -0,0 +1,122 @@
+//! Passage embeddings for semantic search.
+//!
+//! Embeddings are computed per index passage (see `index.rs`), so semantic and
+//! BM25 hits share passage ids and can be fused with reciprocal-rank fusion.
+//! Vectors are L2-normalized at store time, making cosine similarity a dot product.
+
+use serde::{Deserialize, Serialize};
+
+/// RRF damping constant from the original paper — dampens the head of each list.
+const RRF_K: f32 = 60.0;
+
+/// Header stored next to the raw vector bytes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingMeta {
+    pub model: String,
+    pub dim: usize,
+    pub count: usize,
+}
+
+/// A document's passage embeddings, flattened row-major (`count × dim`).
+#[derive(Debug, Clone)]
+pub struct DocEmbeddings {
+    pub meta: EmbeddingMeta,
+    pub vectors: Vec<f32>,
+}
+
+impl DocEmbeddings {
+    /// Top-`k` passages by cosine similarity to `query` (which need not be normalized).
+    pub fn nearest(&self, query: &[f32], k: usize) -> Vec<(usize, f32)> {
+        if query.len() != self.meta.dim || self.meta.dim == 0 {
+            return Vec::new();
+        }
+        let mut q = query.to_vec();
+        normalize(&mut q);
+
+        let mut scored: Vec<(usize, f32)> = self
+            .vectors
+            .chunks_exact(self.meta.dim)
+            .enumerate()
+            .map(|(i, v)| (i, v.iter().zip(&q).map(|(a, b)| a * b).sum()))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored.truncate(k);
+        scored
+    }
+}
+
+pub fn normalize(v: &mut [f32]) {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// Encode vectors as little-endian f32 bytes (normalizing each one).
+pub fn encode(vectors: &[Vec<f32>]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(vectors.iter().map(|v| v.len() * 4).sum());
+    for v in vectors {
+        let mut v = v.clone();
+        normalize(&mut v);
+        for x in v {
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+    out
+}
+
+pub fn decode(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+/// Reciprocal-rank fusion over ranked lists of passage ids.
+/// Returns `(passage, fused score)` best-first.
+pub fn rrf_fuse(lists: &[Vec<usize>]) -> Vec<(usize, f32)> {
+    let mut scores: std::collections::HashMap<usize, f32> = std::collections::HashMap::new();
+    for list in lists {
+        for (rank, &pid) in list.iter().enumerate() {
+            *scores.entry(pid).or_default() += 1.0 / (RRF_K + rank as f32 + 1.0);
+        }
+    }
+    let mut fused: Vec<(usize, f32)> = scores.into_iter().collect();
+    fused.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    fused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode_normalizes() {
+        let bytes = encode(&[vec![3.0, 4.0]]);
+        assert_eq!(decode(&bytes), vec![0.6, 0.8]);
+    }
+
+    #[test]
+    fn test_nearest() {
+        let emb = DocEmbeddings {
+            meta: EmbeddingMeta {
+                model: "m".to_string(),
+                dim: 2,
+                count: 3,
+            },
+            vectors: decode(&encode(&[vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 1.0]])),
+        };
+        let hits = emb.nearest(&[0.0, 5.0], 2);
+        assert_eq!(hits[0].0, 1);
+        assert_eq!(hits[1].0, 2);
+        assert!(emb.nearest(&[1.0, 0.0, 0.0], 2).is_empty());
+    }
+
+    #[test]
+    fn test_rrf_fuse_rewards_agreement() {
+        let fused = rrf_fuse(&[vec![1, 2, 3], vec![3, 1, 4]]);
+        assert_eq!(fused[0].0, 1);
+        assert_eq!(fused[1].0, 3);
+        assert_eq!(fused.len(), 4);
+    }
+}
@@ -0,0 +1,328 @@
+//! BM25 inverted index over document passages.
+//!
+//! Documents are split into passages (~1200 chars, never spanning two files).
+//! Each passage has two fields — the file path and the body — scored with
+//! per-field BM25 and combined with field boosts. Postings are stored per term
+//! in cnidarium so a query only loads the terms it mentions.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::types::FileEntry;
+
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+/// A query term in the file path is a strong relevance signal.
+const PATH_BOOST: f32 = 2.5;
+const BODY_BOOST: f32 = 1.0;
+/// Passages end at the first line break past this many chars.
+const PASSAGE_CHARS: usize = 1200;
+/// Tokens longer than this are hashes/base64 noise — not worth indexing.
+const MAX_TOKEN_LEN: usize = 40;
+
+/// A contiguous, searchable slice of a document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Passage {
+    /// Owning file path (None for single-blob documents)
+    pub file: Option<String>,
+    /// Char offset into the combined document text (for `get_section`)
+    pub offset: usize,
+    pub byte_offset: usize,
+    pub byte_len: usize,
+    /// Field lengths in tokens
+    pub body_len: u32,
+    pub path_len: u32,
+}
+
+/// Per-document index header: passage table and average field lengths.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IndexMeta {
+    pub passages: Vec<Passage>,
+    pub avg_body_len: f32,
+    pub avg_path_len: f32,
+}
+
+/// `(passage index, body term frequency, path term frequency)`
+pub type Posting = (u32, u32, u32);
+
+pub struct BuiltIndex {
+    pub meta: IndexMeta,
+    pub postings: BTreeMap<String, Vec<Posting>>,
+}
+
+/// A parsed search query. `"quoted phrases"` must appear verbatim (token-wise);
+/// every token — inside or outside quotes — contributes to the BM25 score.
+#[derive(Debug, Default, PartialEq)]
+pub struct Query {
+    pub terms: Vec<String>,
+    pub phrases: Vec<Vec<String>>,
+}
+
+/// A scored passage.
+#[derive(Debug)]
+pub struct Hit {
+    pub passage: usize,
+    pub score: f32,
+    /// Distinct query terms present in the passage (body or path)
+    pub matched: Vec<String>,
+}
+
+/// Lowercased alphanumeric tokens. `_`, `-`, `/`, `.` etc. all split, so
+/// `private_ip` and `private-ip` both index as `private` + `ip`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| t.len() >= 2 && t.len() <= MAX_TOKEN_LEN)
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+pub fn parse_query(query: &str) -> Query {
+    let mut terms = Vec::new();
+    let mut phrases = Vec::new();
+
+    for (i, part) in query.split('"').enumerate() {
+        let tokens = tokenize(part);
+        // Odd segments sit between quotes
+        if i % 2 == 1 && tokens.len() > 1 {
+            phrases.push(tokens.clone());
+        }
+        terms.extend(tokens);
+    }
+
+    let mut seen = HashSet::new();
+    terms.retain(|t| seen.insert(t.clone()));
+    Query { terms, phrases }
+}
+
+/// Build the index for a document's combined text. `files` is the document's
+/// manifest (empty for single-blob documents); file header lines are skipped.
+pub fn build(text: &str, files: &[FileEntry]) -> BuiltIndex {
+    let mut passages: Vec<Passage> = Vec::new();
+    let mut postings: BTreeMap<String, Vec<Posting>> = BTreeMap::new();
+
+    let mut pending: Option<PendingPassage> = None;
+    let mut next_file = 0;
+    let mut current_file: Option<&str> = None;
+    let mut char_offset = 0;
+    let mut byte_offset = 0;
+
+    for line in text.split_inclusive('\n') {
+        let line_chars = line.chars().count();
+        let is_header = next_file < files.len() && char_offset >= files[next_file].offset;
+
+        if is_header {
+            if let Some(p) = pending.take() {
+                p.finish(text, &mut passages, &mut postings);
+            }
+            current_file = Some(files[next_file].path.as_str());
+            next_file += 1;
+        } else {
+            let p = pending.get_or_insert_with(|| PendingPassage {
+                file: current_file.map(|f| f.to_string()),
+                offset: char_offset,
+                byte_offset,
+                byte_len: 0,
+                chars: 0,
+            });
+            p.byte_len += line.len();
+            p.chars += line_chars;
+            if p.chars >= PASSAGE_CHARS {
+                if let Some(p) = pending.take() {
+                    p.finish(text, &mut passages, &mut postings);
+                }
+            }
+        }
+
+        char_offset += line_chars;
+        byte_offset += line.len();
+    }
+    if let Some(p) = pending.take() {
+        p.finish(text, &mut passages, &mut postings);
+    }
+
+    let n = passages.len().max(1) as f32;
+    let avg_body_len = passages.iter().map(|p| p.body_len as f32).sum::<f32>() / n;
+    let avg_path_len = passages.iter().map(|p| p.path_len as f32).sum::<f32>() / n;
+
+    BuiltIndex {
+        meta: IndexMeta {
+            passages,
+            avg_body_len,
+            avg_path_len,
+        },
+        postings,
+    }
+}
+
+struct PendingPassage {
+    file: Option<String>,
+    offset: usize,
+    byte_offset: usize,
+    byte_len: usize,
+    chars: usize,
+}
+
+impl PendingPassage {
+    fn finish(
+        self,
+        text: &str,
+        passages: &mut Vec<Passage>,
+        postings: &mut BTreeMap<String, Vec<Posting>>,
+    ) {
+        let body = text
+            .get(self.byte_offset..self.byte_offset + self.byte_len)
+            .unwrap_or("");
+        let body_tokens = tokenize(body);
+        if body_tokens.is_empty() {
+            return;
+        }
+        let path_tokens = self.file.as_deref().map(tokenize).unwrap_or_default();
+
+        let mut tf: HashMap<&str, (u32, u32)> = HashMap::new();
+        for t in &body_tokens {
+            tf.entry(t.as_str()).or_default().0 += 1;
+        }
+        for t in &path_tokens {
+            tf.entry(t.as_str()).or_default().1 += 1;
+        }
+
+        let id = passages.len() as u32;
+        for (term, (body_tf, path_tf)) in tf {
+            postings
+                .entry(term.to_string())
+                .or_default()
+                .push((id, body_tf, path_tf));
+        }
+
+        passages.push(Passage {
+            file: self.file,
+            offset: self.offset,
+            byte_offset: self.byte_offset,
+            byte_len: self.byte_len,
+            body_len: body_tokens.len() as u32,
+            path_len: path_tokens.len() as u32,
+        });
+    }
+}
+
+/// Score passages against the query terms. `postings` holds the posting list
+/// for each query term that exists in the index. Results are sorted best-first.
+pub fn score(meta: &IndexMeta, postings: &HashMap<String, Vec<Posting>>, query: &Query) -> Vec<Hit> {
+    let n = meta.passages.len() as f32;
+    let mut scores: HashMap<u32, (f32, Vec<String>)> = HashMap::new();
+
+    for term in &query.terms {
+        let Some(list) = postings.get(term) else {
+            continue;
+        };
+        let df = list.len() as f32;
+        let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+
+        for &(pid, body_tf, path_tf) in list {
+            let Some(p) = meta.passages.get(pid as usize) else {
+                continue;
+            };
+            let body = bm25_tf(body_tf, p.body_len, meta.avg_body_len);
+            let path = bm25_tf(path_tf, p.path_len, meta.avg_path_len);
+            let entry = scores.entry(pid).or_default();
+            entry.0 += idf * (BODY_BOOST * body + PATH_BOOST * path);
+            entry.1.push(term.clone());
+        }
+    }
+
+    let mut hits: Vec<Hit> = scores
+        .into_iter()
+        .map(|(pid, (score, matched))| Hit {
+            passage: pid as usize,
+            score,
+            matched,
+        })
+        .collect();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.passage.cmp(&b.passage)));
+    hits
+}
+
+fn bm25_tf(tf: u32, len: u32, avg_len: f32) -> f32 {
+    if tf == 0 {
+        return 0.0;
+    }
+    let tf = tf as f32;
+    let norm = if avg_len > 0.0 { len as f32 / avg_len } else { 1.0 };
+    tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * norm))
+}
+
+/// Whether `phrase` occurs as consecutive tokens in `text` or `path`.
+pub fn contains_phrase(text: &str, path: Option<&str>, phrase: &[String]) -> bool {
+    let matches = |tokens: Vec<String>| tokens.windows(phrase.len()).any(|w| w == phrase);
+    matches(tokenize(text)) || path.map(|p| matches(tokenize(p))).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, offset: usize) -> FileEntry {
+        FileEntry {
+            path: path.to_string(),
+            size: 0,
+            language: None,
+            offset,
+            len: 0,
+        }
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("Private_IP lease-Manager a"),
+            vec!["private", "ip", "lease", "manager"]
+        );
+    }
+
+    #[test]
+    fn test_parse_query_phrases() {
+        let q = parse_query(r#"gpu "bid price" pricing"#);
+        assert_eq!(q.terms, vec!["gpu", "bid", "price", "pricing"]);
+        assert_eq!(q.phrases, vec![vec!["bid".to_string(), "price".to_string()]]);
+    }
+
+    #[test]
+    fn test_build_skips_headers_and_tracks_files() {
+        let text = "=== a.md ===\nalpha beta\n\n=== b.md ===\ngamma\n\n";
+        let files = vec![entry("a.md", 0), entry("b.md", 25)];
+        let built = build(text, &files);
+        assert_eq!(built.meta.passages.len(), 2);
+        assert_eq!(built.meta.passages[0].file.as_deref(), Some("a.md"));
+        assert_eq!(built.meta.passages[1].file.as_deref(), Some("b.md"));
+        assert_eq!(built.meta.passages[1].offset, 38);
+        assert!(built.postings.contains_key("gamma"));
+        // "md" comes from the path field of both passages
+        assert_eq!(built.postings["md"].len(), 2);
+    }
+
+    #[test]
+    fn test_score_prefers_path_and_rare_terms() {
+        let text = "=== docs/staking.md ===\nrewards are paid\n\n=== docs/other.md ===\nstaking staking rewards\n\n";
+        let files = vec![entry("docs/staking.md", 0), entry("docs/other.md", 41)];
+        let built = build(text, &files);
+        let query = parse_query("staking");
+        let postings: HashMap<String, Vec<Posting>> = built
+            .postings
+            .iter()
+            .filter(|(t, _)| query.terms.contains(t))
+            .map(|(t, p)| (t.clone(), p.clone()))
+            .collect();
+        let hits = score(&built.meta, &postings, &query);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(built.meta.passages[hits[0].passage].file.as_deref(), Some("docs/staking.md"));
+    }
+
+    #[test]
+    fn test_contains_phrase() {
+        let phrase = vec!["bid".to_string(), "price".to_string()];
+        assert!(contains_phrase("the Bid-Price is set", None, &phrase));
+        assert!(!contains_phrase("price of a bid", None, &phrase));
+        assert!(contains_phrase("", Some("docs/bid_price.md"), &phrase));
+    }
+}
@@ -1,8 +1,12 @@
 use anyhow::{Context, Result};
-use tracing::info;
+use tracing::{debug, info};
 
-use super::types::DocId;
+use super::types::{DocId, DocInfo, Scope};
 use super::DocumentStore;
+use crate::llm::LlmClient;
+
+/// Passages per `/embeddings` request.
+const EMBED_BATCH: usize = 64;
 
 /// Derive a default URL context from a GitHub repo URL + branch.
 /// Returns a natural-language instruction the LLM can use to construct links.
@@ -18,10 +22,134 @@ fn derive_github_url_context(url: &str, branch: &str) -> Option<String> {
     }
 }
 
+/// Clone `url` with `git_ref` (branch, tag or full commit SHA) checked out; the
+/// default branch when `None`. Returns the repository and the resolved commit SHA.
+fn clone_at_ref(url: &str, git_ref: Option<&str>) -> Result<(git2::Repository, String)> {
+    let repo = match git_ref {
+        None => githem_core::clone_repository(url, None)?,
+        // Branches clone directly; tags and SHAs need an explicit fetch
+        Some(r) => match githem_core::clone_repository(url, Some(r)) {
+            Ok(repo) => repo,
+            Err(e) => {
+                debug!(git_ref = r, error = %e, "not a branch, fetching as tag or commit");
+                let repo = githem_core::clone_repository(url, None)?;
+                checkout_fetched_ref(&repo, r)?;
+                repo
+            }
+        },
+    };
+
+    let commit = repo
+        .head()
+        .and_then(|h| h.peel_to_commit())
+        .context("Failed to resolve HEAD commit")?
+        .id()
+        .to_string();
+    Ok((repo, commit))
+}
+
+/// Shallow-fetch a tag or commit SHA into `repo` and check it out (detached HEAD).
+fn checkout_fetched_ref(repo: &git2::Repository, git_ref: &str) -> Result<()> {
+    let mut remote = repo.find_remote("origin")?;
+    let mut fetch_opts = git2::FetchOptions::new();
+    fetch_opts.depth(1);
+    fetch_opts.download_tags(git2::AutotagOption::None);
+
+    let tag_spec = format!("+refs/tags/{0}:refs/tags/{0}", git_ref);
+    let commit = if remote.fetch(&[&tag_spec], Some(&mut fetch_opts), None).is_ok() {
+        repo.revparse_single(&format!("refs/tags/{}", git_ref))?
+            .peel_to_commit()?
+    } else if is_full_sha(git_ref) {
+        remote
+            .fetch(&[git_ref], Some(&mut fetch_opts), None)
+            .with_context(|| format!("Commit {} not found on remote", git_ref))?;
+        repo.find_commit(git2::Oid::from_str(git_ref)?)?
+    } else {
+        anyhow::bail!(
+            "Ref '{}' not found — expected a branch, tag, or full 40-character commit SHA",
+            git_ref
+        );
+    };
+
+    repo.checkout_tree(
+        commit.as_object(),
+        Some(git2::build::CheckoutBuilder::new().force()),
+    )?;
+    repo.set_head_detached(commit.id())?;
+    Ok(())
+}
+
+fn is_full_sha(s: &str) -> bool {
+    s.len() == 40 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Split githem-core output into `(path, body)` pairs.
+/// Githem writes each file as `=== path ===\n{content}\n\n`.
+fn split_githem_output(text: &str) -> Vec<(String, String)> {
+    let mut files: Vec<(String, String)> = Vec::new();
+    let mut current: Option<(String, Vec<&str>)> = None;
+
+    for line in text.lines() {
+        if line.starts_with("=== ") && line.ends_with(" ===") && line.len() > 8 {
+            if let Some((path, body)) = current.take() {
+                files.push((path, body.join("\n").trim_end_matches('\n').to_string()));
+            }
+            let path = line.trim_start_matches("=== ").trim_end_matches(" ===");
+            current = Some((path.to_string(), Vec::new()));
+        } else if let Some((_, body)) = current.as_mut() {
+            body.push(line);
+        }
+    }
+    if let Some((path, body)) = current {
+        files.push((path, body.join("\n").trim_end_matches('\n').to_string()));
+    }
+
+    files
+}
+
+/// Guess a file's language from its extension (or well-known filename).
+pub fn detect_language(path: &str) -> Option<&'static str> {
+    let filename = path.rsplit('/').next().unwrap_or(path);
+    match filename {
+        "Dockerfile" => return Some("dockerfile"),
+        "Makefile" | "justfile" => return Some("make"),
+        _ => {}
+    }
+    let ext = filename.rsplit_once('.')?.1.to_lowercase();
+    let lang = match ext.as_str() {
+        "rs" => "rust",
+        "go" => "go",
+        "py" => "python",
+        "js" | "mjs" | "cjs" | "jsx" => "javascript",
+        "ts" | "tsx" => "typescript",
+        "java" => "java",
+        "kt" => "kotlin",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "hpp" => "cpp",
+        "cs" => "csharp",
+        "rb" => "ruby",
+        "sh" | "bash" => "shell",
+        "sol" => "solidity",
+        "proto" => "protobuf",
+        "md" | "mdx" => "markdown",
+        "rst" => "rst",
+        "txt" => "text",
+        "html" | "htm" => "html",
+        "css" | "scss" => "css",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        "sql" => "sql",
+        _ => return None,
+    };
+    Some(lang)
+}
+
 /// Ingest a GitHub repository using githem-core.
 /// Returns (doc_id, file_count).
 pub async fn ingest_github_repo(
     store: &DocumentStore,
+    scope: Scope,
     url: &str,
     label: &str,
     doc_type: Option<&str>,
@@ -40,21 +168,23 @@ pub async fn ingest_github_repo(
 
     let opts = githem_core::IngestOptions::with_preset(preset);
 
-    // Clone and ingest — this is blocking I/O so run in spawn_blocking
+    // Clone at the requested ref and ingest — this is blocking I/O so run in spawn_blocking
     let url_owned = url.to_string();
-    let output = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
-        let ingester =
-            githem_core::Ingester::from_url_cached(&url_owned, opts)?;
+    let ref_owned = branch.map(|b| b.to_string());
+    let (output, commit) = tokio::task::spawn_blocking(move || -> Result<(Vec<u8>, String)> {
+        let (repo, commit) = clone_at_ref(&url_owned, ref_owned.as_deref())?;
+        let ingester = githem_core::Ingester::new(repo, opts);
         let mut output = Vec::new();
         ingester.ingest(&mut output)?;
-        Ok(output)
+        Ok((output, commit))
     })
     .await
     .context("spawn_blocking join failed")??;
 
-    // Count files from githem output format: "=== path/to/file ===\n"
+    // Split githem output ("=== path/to/file ===\n") into per-file records
     let text = String::from_utf8_lossy(&output);
-    let file_count = text.matches("=== ").count();
+    let files = split_githem_output(&text);
+    let file_count = files.len();
 
     // Extract repo name from URL for the document name
     let name = url
@@ -68,28 +198,34 @@ pub async fn ingest_github_repo(
         .join("/");
 
     let source = format!("github:{}", name);
+    // Show non-default refs in the name, e.g. "owner/repo@v1.2.0"
+    let name = match branch {
+        Some(r) => format!("{}@{}", name, r),
+        None => name,
+    };
 
-    // Derive url_context: explicit override > auto-derived from GitHub URL
-    let effective_branch = branch.unwrap_or("main");
+    // Derive url_context: explicit override > auto-derived from GitHub URL,
+    // pinned to the commit so links stay valid as the branch moves
     let effective_url_context = url_context
         .map(|s| s.to_string())
-        .or_else(|| derive_github_url_context(url, effective_branch));
-
-    let doc_id = store
-        .store(
-            &output,
-            &name,
-            &source,
-            label,
-            effective_url_context.as_deref(),
-        )
-        .await?;
+        .or_else(|| derive_github_url_context(url, &commit));
+
+    let info = DocInfo {
+        scope,
+        name: &name,
+        source: &source,
+        label,
+        url_context: effective_url_context.as_deref(),
+        commit: Some(&commit),
+    };
+    let doc_id = store.store_files(&files, &info).await?;
 
     info!(
         doc_id = %doc_id,
         file_count,
         size = output.len(),
         label,
+        commit,
         url_context = ?effective_url_context,
         "GitHub repo ingested"
     );
@@ -100,6 +236,7 @@ pub async fn ingest_github_repo(
 /// Ingest a web page by fetching its content.
 pub async fn ingest_url(
     store: &DocumentStore,
+    scope: Scope,
     url: &str,
     label: &str,
 ) -> Result<(DocId, usize)> {
@@ -130,8 +267,71 @@ pub async fn ingest_url(
         .next()
         .unwrap_or(url);
     let source = format!("url:{}", url);
-    let doc_id = store.store(text.as_bytes(), name, &source, label, None).await?;
+    let info = DocInfo {
+        scope,
+        name,
+        source: &source,
+        label,
+        url_context: None,
+        commit: None,
+    };
+    let doc_id = store.store(text.as_bytes(), &info).await?;
 
     info!(doc_id = %doc_id, size = text.len(), label, "URL ingested");
     Ok((doc_id, text.len()))
 }
+
+/// Embed every search passage of a document so `semantic_search` can use it.
+/// Returns the number of passages embedded, or 0 when no embedding model is configured.
+pub async fn embed_document(store: &DocumentStore, llm: &LlmClient, doc_id: &str) -> Result<usize> {
+    let Some(model) = llm.embedding_model() else {
+        return Ok(0);
+    };
+
+    let passages = store.passage_texts(doc_id).await?;
+    let mut vectors = Vec::with_capacity(passages.len());
+    for batch in passages.chunks(EMBED_BATCH) {
+        vectors.extend(llm.embed(batch).await?);
+    }
+    store.store_embeddings(doc_id, model, &vectors).await?;
+
+    info!(doc_id, model, passages = vectors.len(), "Document embedded");
+    Ok(vectors.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_githem_output() {
+        let text = "=== README.md ===\n# Title\n\nIntro\n\n=== src/main.rs ===\nfn main() {}\n\n";
+        let files = split_githem_output(text);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, "README.md");
+        assert_eq!(files[0].1, "# Title\n\nIntro");
+        assert_eq!(files[1].0, "src/main.rs");
+        assert_eq!(files[1].1, "fn main() {}");
+    }
+
+    #[test]
+    fn test_split_githem_output_ignores_preamble() {
+        let files = split_githem_output("noise\n=== a.txt ===\nbody\n");
+        assert_eq!(files, vec![("a.txt".to_string(), "body".to_string())]);
+    }
+
+    #[test]
+    fn test_is_full_sha() {
+        assert!(is_full_sha("0123456789abcdef0123456789ABCDEF01234567"));
+        assert!(!is_full_sha("0123456"));
+        assert!(!is_full_sha("v1.2.3"));
+    }
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(detect_language("src/lib.rs"), Some("rust"));
+        assert_eq!(detect_language("docs/Guide.MD"), Some("markdown"));
+        assert_eq!(detect_language("build/Dockerfile"), Some("dockerfile"));
+        assert_eq!(detect_language("LICENSE"), None);
+    }
+}
@@ -1,20 +1,33 @@
+pub mod embed;
+pub mod index;
 pub mod ingest;
 pub mod types;
 
 use std::path::Path;
+use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use cnidarium::{StateDelta, StateWrite, Storage};
 use futures::StreamExt;
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
 
-use types::{DocExcerpt, DocId, DocMeta, QaRecord};
+use types::{
+    AnsweredThread, ConfigChange, DocExcerpt, DocId, DocInfo, DocMeta, FileEntry, QaRecord, Scope,
+    ScopeView, SourceHistory, SourceVersion,
+};
 
 // Key prefixes (no trailing slashes — cnidarium convention)
 const CONTENT_PREFIX: &str = "doc/content";
 const META_PREFIX: &str = "doc/meta";
+const FILE_PREFIX: &str = "doc/file";
+const MANIFEST_PREFIX: &str = "doc/manifest";
+const INDEX_PREFIX: &str = "doc/index";
+const EMBED_PREFIX: &str = "doc/embed";
 const LABEL_PREFIX: &str = "doc/label";
+const SOURCE_PREFIX: &str = "doc/source";
 const QA_PREFIX: &str = "qa";
+const CONFIG_PREFIX: &str = "config";
+const THREAD_PREFIX: &str = "thread";
 
 fn content_key(id: &str) -> String {
     format!("{}/{}", CONTENT_PREFIX, id)
@@ -22,17 +35,356 @@ fn content_key(id: &str) -> String {
 fn meta_key(id: &str) -> String {
     format!("{}/{}", META_PREFIX, id)
 }
-fn label_key(label: &str, id: &str) -> String {
-    format!("{}/{}:{}", LABEL_PREFIX, label, id)
+fn file_key(id: &str, path: &str) -> String {
+    format!("{}/{}/{}", FILE_PREFIX, id, path)
 }
-fn qa_key(topic: &str, id: &str) -> String {
-    format!("{}/{}/{}", QA_PREFIX, topic, id)
+fn manifest_key(id: &str) -> String {
+    format!("{}/{}", MANIFEST_PREFIX, id)
+}
+fn index_meta_key(id: &str) -> String {
+    format!("{}/{}/meta", INDEX_PREFIX, id)
+}
+fn index_term_key(id: &str, term: &str) -> String {
+    format!("{}/{}/t/{}", INDEX_PREFIX, id, term)
+}
+fn embed_meta_key(id: &str) -> String {
+    format!("{}/{}/meta", EMBED_PREFIX, id)
+}
+fn embed_vectors_key(id: &str) -> String {
+    format!("{}/{}/vectors", EMBED_PREFIX, id)
+}
+fn label_key(scope: Scope, label: &str, id: &str) -> String {
+    format!("{}/{}/{}:{}", LABEL_PREFIX, scope.key(), label, id)
+}
+fn source_key(scope: Scope, label: &str, source: &str) -> String {
+    format!("{}/{}/{}:{}", SOURCE_PREFIX, scope.key(), label, source)
+}
+/// Key of the source history `meta` is a version in.
+fn history_key(meta: &DocMeta) -> String {
+    source_key(meta.scope, &meta.label, &meta.source)
+}
+fn config_key(scope: Scope, name: &str) -> String {
+    format!("{}/value/{}/{}", CONFIG_PREFIX, scope.key(), name)
+}
+fn config_audit_prefix(scope: Scope) -> String {
+    format!("{}/audit/{}/", CONFIG_PREFIX, scope.key())
+}
+/// `r` plus the zero-padded nanos left until `i64::MAX`, so audit keys sort
+/// newest first and a prefix scan can stop after the latest changes. Keys
+/// written before were the plain nanos, oldest first.
+fn config_audit_key(scope: Scope, timestamp_nanos: i64) -> String {
+    format!(
+        "{}{}{:020}",
+        config_audit_prefix(scope),
+        AUDIT_NEWEST_FIRST,
+        i64::MAX - timestamp_nanos
+    )
+}
+/// Marks audit keys that sort newest first.
+const AUDIT_NEWEST_FIRST: char = 'r';
+/// Topics are escaped in keys so one topic's prefix never covers another's,
+/// as "a/" would cover "a/b"'s records.
+fn escape_topic(topic: &str) -> String {
+    topic.replace('%', "%25").replace('/', "%2F")
+}
+fn qa_prefix(scope: Scope, topic: &str) -> String {
+    format!("{}/{}/{}/", QA_PREFIX, scope.key(), escape_topic(topic))
+}
+fn qa_key(scope: Scope, topic: &str, id: &str) -> String {
+    format!("{}{}", qa_prefix(scope, topic), id)
+}
+fn thread_prefix(scope: Scope, topic: &str) -> String {
+    format!("{}/{}/{}/", THREAD_PREFIX, scope.key(), escape_topic(topic))
+}
+fn thread_key(scope: Scope, topic: &str, thread_id: u64) -> String {
+    format!("{}{}", thread_prefix(scope, topic), thread_id)
+}
+/// Marks that pre-scoping keys have been moved under the global scope.
+const SCOPE_MIGRATION_KEY: &str = "config/migrations/scopes";
+/// Marks that Q/A records have been moved under escaped topic keys.
+const QA_TOPIC_MIGRATION_KEY: &str = "config/migrations/qa-topics";
+
+/// Content-addressed DocId, keyed by guild and topic so identical content
+/// ingested by two guilds, or into two topics, never shares metadata. A
+/// nonzero `rehash` picks another ID, for when a doc moved to another topic
+/// holds this one.
+fn doc_id(scope: Scope, label: &str, content: &[u8], rehash: u32) -> DocId {
+    let mut hasher = blake3::Hasher::new();
+    if let Scope::Guild(guild) = scope {
+        hasher.update(format!("guild:{}\0", guild).as_bytes());
+    }
+    hasher.update(format!("label:{}\0", label).as_bytes());
+    if rehash > 0 {
+        hasher.update(format!("rehash:{}\0", rehash).as_bytes());
+    }
+    hasher.update(content);
+    hasher.finalize().to_hex().to_string()
+}
+
+/// DocId of docs stored before the topic was hashed in. Global docs hashed the
+/// bare content (matching IDs from before scoping).
+fn unlabeled_doc_id(scope: Scope, content: &[u8]) -> DocId {
+    match scope {
+        Scope::Global => blake3::hash(content).to_hex().to_string(),
+        Scope::Guild(guild) => {
+            let mut hasher = blake3::Hasher::new();
+            hasher.update(format!("guild:{}\0", guild).as_bytes());
+            hasher.update(content);
+            hasher.finalize().to_hex().to_string()
+        }
+    }
 }
 
 pub struct DocumentStore {
     storage: Storage,
     /// Cache document content in memory after first read to avoid repeated cnidarium lookups.
-    content_cache: tokio::sync::RwLock<std::collections::HashMap<String, Vec<u8>>>,
+    content_cache: tokio::sync::RwLock<std::collections::HashMap<String, Arc<str>>>,
+    /// Cache parsed file manifests — `read_file`/`list_files` hit these on every REPL call.
+    manifest_cache: tokio::sync::RwLock<std::collections::HashMap<String, Vec<FileEntry>>>,
+    /// Cache index headers (passage tables); postings are loaded per query term.
+    index_cache: tokio::sync::RwLock<std::collections::HashMap<String, Arc<index::IndexMeta>>>,
+    /// Cache decoded passage embeddings.
+    embed_cache: tokio::sync::RwLock<std::collections::HashMap<String, Arc<embed::DocEmbeddings>>>,
+    /// Serializes read-modify-writes of thread entries and source histories,
+    /// so concurrent updates don't drop each other's changes.
+    key_locks: KeyLocks,
+}
+
+/// One lock per store key being read, changed and written back.
+#[derive(Default)]
+struct KeyLocks(std::sync::Mutex<std::collections::HashMap<String, Arc<tokio::sync::Mutex<()>>>>);
+
+impl KeyLocks {
+    /// Lock `keys`, in a fixed order so callers locking several can't deadlock.
+    async fn lock(&self, mut keys: Vec<String>) -> Vec<KeyGuard<'_>> {
+        keys.sort();
+        keys.dedup();
+        let mut guards = Vec::with_capacity(keys.len());
+        for key in keys {
+            let lock = self
+                .0
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .entry(key.clone())
+                .or_default()
+                .clone();
+            guards.push(KeyGuard {
+                locks: self,
+                key,
+                guard: Some(lock.lock_owned().await),
+            });
+        }
+        guards
+    }
+}
+
+/// A held key lock. Dropping it forgets the key once no one else awaits it.
+struct KeyGuard<'a> {
+    locks: &'a KeyLocks,
+    key: String,
+    guard: Option<tokio::sync::OwnedMutexGuard<()>>,
+}
+
+impl Drop for KeyGuard<'_> {
+    fn drop(&mut self) {
+        self.guard.take();
+        let mut locks = self
+            .locks
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Waiters hold clones, which are only handed out under this lock
+        if locks
+            .get(&self.key)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+/// A ranked passage and the query term (if any) its excerpt should center on.
+struct RankedPassage {
+    passage: usize,
+    score: f32,
+    match_count: usize,
+    anchor: Option<String>,
+}
+
+fn passage_text<'a>(text: &'a str, passage: &index::Passage) -> &'a str {
+    text.get(passage.byte_offset..passage.byte_offset + passage.byte_len)
+        .unwrap_or("")
+}
+
+/// Cuts passages out of a document. A multi-file document is never assembled:
+/// each file's section is read from its record the first time one of its
+/// passages is needed.
+struct PassageReader<'a> {
+    store: &'a DocumentStore,
+    doc_id: &'a str,
+    text: PassageText,
+}
+
+enum PassageText {
+    /// Content of a single-blob document
+    Blob(Arc<str>),
+    /// Byte offset of each file's section in the combined text, and the
+    /// sections read so far
+    Files {
+        starts: std::collections::HashMap<String, usize>,
+        sections: std::collections::HashMap<String, String>,
+    },
+}
+
+impl<'a> PassageReader<'a> {
+    async fn open(store: &'a DocumentStore, doc_id: &'a str) -> Result<Self> {
+        let text = match store.get_manifest(doc_id).await? {
+            Some(manifest) => {
+                let mut starts = std::collections::HashMap::with_capacity(manifest.len());
+                let mut start = 0;
+                for entry in &manifest {
+                    starts.insert(entry.path.clone(), start);
+                    start += section_bytes(entry);
+                }
+                PassageText::Files {
+                    starts,
+                    sections: std::collections::HashMap::new(),
+                }
+            }
+            None => PassageText::Blob(store.get_content(doc_id).await?),
+        };
+        Ok(Self {
+            store,
+            doc_id,
+            text,
+        })
+    }
+
+    /// The passage's text, as `passage_text` cuts it from the combined text.
+    async fn text(&mut self, passage: &index::Passage) -> Result<&str> {
+        let (starts, sections) = match &mut self.text {
+            PassageText::Blob(text) => return Ok(passage_text(text, passage)),
+            PassageText::Files { starts, sections } => (starts, sections),
+        };
+        let Some((file, start)) = passage
+            .file
+            .as_ref()
+            .and_then(|file| Some((file, *starts.get(file)?)))
+        else {
+            return Ok("");
+        };
+        if !sections.contains_key(file) {
+            let body = self.store.file_record(self.doc_id, file).await?;
+            sections.insert(file.clone(), render_section(file, &body));
+        }
+        let offset = passage.byte_offset.saturating_sub(start);
+        Ok(sections[file]
+            .get(offset..offset + passage.byte_len)
+            .unwrap_or(""))
+    }
+}
+
+/// Build the BM25 index for a document and stage it into `delta`.
+fn put_index(
+    delta: &mut StateDelta<cnidarium::Snapshot>,
+    id: &str,
+    text: &str,
+    manifest: &[FileEntry],
+) -> Result<index::IndexMeta> {
+    let built = index::build(text, manifest);
+    for (term, postings) in &built.postings {
+        delta.put_raw(
+            index_term_key(id, term),
+            serde_json::to_vec(postings).context("serialize postings")?,
+        );
+    }
+    delta.put_raw(
+        index_meta_key(id),
+        serde_json::to_vec(&built.meta).context("serialize index meta")?,
+    );
+    debug!(
+        doc_id = id,
+        passages = built.meta.passages.len(),
+        terms = built.postings.len(),
+        "search index built"
+    );
+    Ok(built.meta)
+}
+
+/// Render per-file records into the combined `=== path ===` text that
+/// `get_section`/`search` operate on, computing each file's manifest entry.
+fn render_files(files: &[(String, String)]) -> (String, Vec<FileEntry>) {
+    let mut text = String::new();
+    let mut entries = Vec::with_capacity(files.len());
+    let mut char_offset = 0;
+
+    for (path, body) in files {
+        let section = render_section(path, body);
+        let len = section.chars().count();
+        entries.push(FileEntry {
+            path: path.clone(),
+            size: body.len(),
+            language: ingest::detect_language(path).map(|l| l.to_string()),
+            offset: char_offset,
+            len,
+        });
+        text.push_str(&section);
+        char_offset += len;
+    }
+
+    (text, entries)
+}
+
+/// One file's section of the combined text: its `=== path ===` header and body.
+fn render_section(path: &str, body: &str) -> String {
+    format!("=== {} ===\n{}\n\n", path, body)
+}
+
+/// Length in bytes of a file's section (see `render_section`).
+fn section_bytes(entry: &FileEntry) -> usize {
+    "=== ".len() + entry.path.len() + " ===\n".len() + entry.size + "\n\n".len()
+}
+
+/// Fresh metadata for a document about to be versioned and stored.
+fn new_meta(id: &str, info: &DocInfo<'_>, size: usize) -> DocMeta {
+    DocMeta {
+        id: id.to_string(),
+        name: info.name.to_string(),
+        source: info.source.to_string(),
+        label: info.label.to_string(),
+        scope: info.scope,
+        size,
+        ingested_at: chrono::Utc::now().timestamp(),
+        url_context: info.url_context.map(|s| s.to_string()),
+        commit: info.commit.map(|s| s.to_string()),
+        version: 0,
+        superseded: false,
+    }
+}
+
+/// Where a pre-scoping key lives under the global scope, or `None` if the key
+/// is already scoped (or isn't one of the scoped families).
+fn legacy_scoped_key(key: &str) -> Option<String> {
+    let global = Scope::Global.key();
+    // doc/label/{label}:{id} and doc/source/{label}:{source}
+    for prefix in [LABEL_PREFIX, SOURCE_PREFIX] {
+        if let Some(rest) = key.strip_prefix(&format!("{}/", prefix)) {
+            let (label, _) = rest.split_once(':')?;
+            return (!label.contains('/')).then(|| format!("{}/{}/{}", prefix, global, rest));
+        }
+    }
+    // qa/{topic}/{id}
+    if let Some(rest) = key.strip_prefix(&format!("{}/", QA_PREFIX)) {
+        return (rest.matches('/').count() == 1).then(|| format!("{}/{}/{}", QA_PREFIX, global, rest));
+    }
+    // config/value/{name} and config/audit/{nanos}
+    for family in ["value", "audit"] {
+        if let Some(rest) = key.strip_prefix(&format!("{}/{}/", CONFIG_PREFIX, family)) {
+            return (!rest.contains('/'))
+                .then(|| format!("{}/{}/{}/{}", CONFIG_PREFIX, family, global, rest));
+        }
+    }
+    None
 }
 
 impl DocumentStore {
@@ -41,57 +393,215 @@ impl DocumentStore {
         let prefixes = vec![
             CONTENT_PREFIX.to_string(),
             META_PREFIX.to_string(),
+            FILE_PREFIX.to_string(),
+            MANIFEST_PREFIX.to_string(),
+            INDEX_PREFIX.to_string(),
+            EMBED_PREFIX.to_string(),
             LABEL_PREFIX.to_string(),
+            SOURCE_PREFIX.to_string(),
             QA_PREFIX.to_string(),
+            CONFIG_PREFIX.to_string(),
+            THREAD_PREFIX.to_string(),
         ];
         let storage = Storage::load(data_dir.to_path_buf(), prefixes)
             .await
             .context("Failed to init cnidarium storage")?;
-        Ok(Self {
+        let store = Self {
             storage,
             content_cache: tokio::sync::RwLock::new(std::collections::HashMap::new()),
-        })
+            manifest_cache: tokio::sync::RwLock::new(std::collections::HashMap::new()),
+            index_cache: tokio::sync::RwLock::new(std::collections::HashMap::new()),
+            embed_cache: tokio::sync::RwLock::new(std::collections::HashMap::new()),
+            key_locks: KeyLocks::default(),
+        };
+        store.migrate_scopes().await?;
+        store.migrate_qa_topics().await?;
+        Ok(store)
     }
 
-    /// Store a document. Returns its content-addressed DocId.
-    /// Idempotent: same content = same ID.
-    pub async fn store(
-        &self,
-        content: &[u8],
-        name: &str,
-        source: &str,
-        label: &str,
-        url_context: Option<&str>,
-    ) -> Result<DocId> {
-        let id = blake3::hash(content).to_hex().to_string();
+    /// One-time move of pre-scoping label, source, Q/A and config keys under the
+    /// global scope. Document metadata needs no rewrite: a missing `scope`
+    /// deserializes as `Scope::Global`.
+    async fn migrate_scopes(&self) -> Result<()> {
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        if snapshot.get_raw(SCOPE_MIGRATION_KEY).await?.is_some() {
+            return Ok(());
+        }
 
-        let meta = DocMeta {
-            id: id.clone(),
-            name: name.to_string(),
-            source: source.to_string(),
-            label: label.to_string(),
-            size: content.len(),
-            ingested_at: chrono::Utc::now().timestamp(),
-            url_context: url_context.map(|s| s.to_string()),
+        let mut delta = StateDelta::new(snapshot.clone());
+        let mut moved = 0usize;
+        for prefix in [LABEL_PREFIX, SOURCE_PREFIX, QA_PREFIX, CONFIG_PREFIX] {
+            let mut stream = snapshot.prefix_raw(prefix);
+            while let Some(entry) = stream.next().await {
+                let (key, value) = match entry {
+                    Ok(kv) => kv,
+                    Err(e) => {
+                        warn!("Error reading stream during scope migration: {}", e);
+                        continue;
+                    }
+                };
+                if let Some(new_key) = legacy_scoped_key(&key) {
+                    delta.put_raw(new_key, value);
+                    delta.delete(key);
+                    moved += 1;
+                }
+            }
+        }
+        delta.put_raw(SCOPE_MIGRATION_KEY.to_string(), vec![1]);
+        self.storage.commit(delta).await?;
+        if moved > 0 {
+            info!(keys = moved, "moved pre-scoping keys under the global scope");
+        }
+        Ok(())
+    }
+
+    /// One-time move of Q/A records filed under a raw topic containing `/` or
+    /// `%` to the escaped key `qa_key` now gives them.
+    async fn migrate_qa_topics(&self) -> Result<()> {
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        if snapshot.get_raw(QA_TOPIC_MIGRATION_KEY).await?.is_some() {
+            return Ok(());
+        }
+
+        let mut delta = StateDelta::new(snapshot.clone());
+        let mut moved = 0usize;
+        let mut stream = snapshot.prefix_raw(QA_PREFIX);
+        while let Some(entry) = stream.next().await {
+            let (key, value) = match entry {
+                Ok(kv) => kv,
+                Err(e) => {
+                    warn!("Error reading stream during Q/A key migration: {}", e);
+                    continue;
+                }
+            };
+            let Ok(record) = serde_json::from_slice::<QaRecord>(&value) else {
+                continue;
+            };
+            let new_key = qa_key(record.scope, &record.topic, &record.id);
+            if new_key != key {
+                delta.put_raw(new_key, value);
+                delta.delete(key);
+                moved += 1;
+            }
+        }
+        delta.put_raw(QA_TOPIC_MIGRATION_KEY.to_string(), vec![1]);
+        self.storage.commit(delta).await?;
+        if moved > 0 {
+            info!(
+                records = moved,
+                "moved Q/A records under escaped topic keys"
+            );
+        }
+        Ok(())
+    }
+
+    /// The ID to store `content` under: the first of its candidate IDs that is
+    /// free or already names this content in this topic, so re-ingesting stays
+    /// idempotent. A doc stored before topics were hashed into IDs keeps its ID;
+    /// IDs held by docs relabeled to another topic are skipped.
+    async fn new_doc_id(&self, info: &DocInfo<'_>, content: &[u8]) -> Result<DocId> {
+        let unlabeled = unlabeled_doc_id(info.scope, content);
+        if self.stored_label(&unlabeled).await?.as_deref() == Some(info.label) {
+            return Ok(unlabeled);
+        }
+        let mut rehash = 0;
+        loop {
+            let id = doc_id(info.scope, info.label, content, rehash);
+            match self.stored_label(&id).await? {
+                Some(label) if label != info.label => rehash += 1,
+                _ => return Ok(id),
+            }
+        }
+    }
+
+    /// Topic of the doc stored under `id`, if there is one.
+    async fn stored_label(&self, id: &str) -> Result<Option<String>> {
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        let Some(bytes) = snapshot.get_raw(&meta_key(id)).await? else {
+            return Ok(None);
         };
+        let meta: DocMeta = serde_json::from_slice(&bytes).context("deserialize meta")?;
+        Ok(Some(meta.label))
+    }
+
+    /// Store a document. Returns its content-addressed DocId.
+    /// Idempotent: same content in the same scope and topic = same ID. A new ID for an
+    /// existing (scope, label, source) becomes that source's next version and
+    /// supersedes the old one.
+    pub async fn store(&self, content: &[u8], info: &DocInfo<'_>) -> Result<DocId> {
+        let id = self.new_doc_id(info, content).await?;
+        let mut meta = new_meta(&id, info, content.len());
+        let label = info.label;
+        let name = info.name;
 
+        let _lock = self.key_locks.lock(vec![history_key(&meta)]).await;
         let snapshot = self.storage.latest_snapshot();
         let mut delta = StateDelta::new(snapshot);
+        self.put_version(&mut delta, &mut meta).await?;
 
         delta.put_raw(content_key(&id), content.to_vec());
+        put_index(&mut delta, &id, &String::from_utf8_lossy(content), &[])?;
         delta.put_raw(
             meta_key(&id),
             serde_json::to_vec(&meta).context("serialize meta")?,
         );
         // Label index entry (empty value — presence is the index)
-        delta.put_raw(label_key(label, &id), vec![]);
+        delta.put_raw(label_key(info.scope, label, &id), vec![]);
 
         self.storage.commit(delta).await?;
         debug!(doc_id = %id, name, label, size = content.len(), "document stored");
         Ok(id)
     }
 
-    pub async fn get_content(&self, doc_id: &str) -> Result<Vec<u8>> {
+    /// Store a multi-file document (e.g. a GitHub repo) as per-file records plus a
+    /// file manifest. `files` are `(path, body)` pairs in ingest order.
+    /// The DocId is the hash of the combined text, so it matches what a
+    /// monolithic `store()` of the same content would have produced.
+    pub async fn store_files(&self, files: &[(String, String)], info: &DocInfo<'_>) -> Result<DocId> {
+        let (text, manifest) = render_files(files);
+        let id = self.new_doc_id(info, text.as_bytes()).await?;
+        let mut meta = new_meta(&id, info, text.len());
+        let label = info.label;
+        let name = info.name;
+
+        let _lock = self.key_locks.lock(vec![history_key(&meta)]).await;
+        let snapshot = self.storage.latest_snapshot();
+        let mut delta = StateDelta::new(snapshot);
+        self.put_version(&mut delta, &mut meta).await?;
+
+        for (path, body) in files {
+            delta.put_raw(file_key(&id, path), body.as_bytes().to_vec());
+        }
+        delta.put_raw(
+            manifest_key(&id),
+            serde_json::to_vec(&manifest).context("serialize manifest")?,
+        );
+        put_index(&mut delta, &id, &text, &manifest)?;
+        delta.put_raw(
+            meta_key(&id),
+            serde_json::to_vec(&meta).context("serialize meta")?,
+        );
+        delta.put_raw(label_key(info.scope, label, &id), vec![]);
+
+        self.storage.commit(delta).await?;
+        debug!(
+            doc_id = %id,
+            name,
+            label,
+            file_count = files.len(),
+            size = text.len(),
+            "multi-file document stored"
+        );
+        Ok(id)
+    }
+
+    /// A document's combined text. Multi-file documents are assembled from
+    /// their file records; readers that need only part of one should go
+    /// through `get_section`, `get_file` or `PassageReader` instead.
+    pub async fn get_content(&self, doc_id: &str) -> Result<Arc<str>> {
         // Check cache first
         {
             let cache = self.content_cache.read().await;
@@ -102,10 +612,11 @@ impl DocumentStore {
 
         let snapshot = self.storage.latest_snapshot();
         use cnidarium::StateRead;
-        let content = snapshot
-            .get_raw(&content_key(doc_id))
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("document not found: {}", doc_id))?;
+        let content: Arc<str> = match snapshot.get_raw(&content_key(doc_id)).await? {
+            Some(content) => String::from_utf8_lossy(&content).into(),
+            // Multi-file documents have no blob — reassemble from per-file records
+            None => self.assemble_files(doc_id).await?.into(),
+        };
 
         // Cache for subsequent reads
         {
@@ -116,6 +627,99 @@ impl DocumentStore {
         Ok(content)
     }
 
+    /// Rebuild the combined text of a multi-file document from its file records.
+    async fn assemble_files(&self, doc_id: &str) -> Result<String> {
+        let manifest = self
+            .get_manifest(doc_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("document not found: {}", doc_id))?;
+
+        let mut files = Vec::with_capacity(manifest.len());
+        for entry in &manifest {
+            let body = self.file_record(doc_id, &entry.path).await?;
+            files.push((entry.path.clone(), body));
+        }
+        Ok(render_files(&files).0)
+    }
+
+    /// Read one file's body from a multi-file document's records.
+    async fn file_record(&self, doc_id: &str, path: &str) -> Result<String> {
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        let bytes = snapshot
+            .get_raw(&file_key(doc_id, path))
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("file record missing: {}", path))?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Load the file manifest of a multi-file document (`None` for single-blob docs).
+    async fn get_manifest(&self, doc_id: &str) -> Result<Option<Vec<FileEntry>>> {
+        {
+            let cache = self.manifest_cache.read().await;
+            if let Some(manifest) = cache.get(doc_id) {
+                return Ok(Some(manifest.clone()));
+            }
+        }
+
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        let Some(bytes) = snapshot.get_raw(&manifest_key(doc_id)).await? else {
+            return Ok(None);
+        };
+        let manifest: Vec<FileEntry> =
+            serde_json::from_slice(&bytes).context("deserialize manifest")?;
+
+        {
+            let mut cache = self.manifest_cache.write().await;
+            cache.insert(doc_id.to_string(), manifest.clone());
+        }
+
+        Ok(Some(manifest))
+    }
+
+    /// Whether a document is stored as per-file records, so its files can be
+    /// read one at a time without assembling the whole document.
+    pub async fn has_file_records(&self, doc_id: &str) -> Result<bool> {
+        Ok(self.get_manifest(doc_id).await?.is_some())
+    }
+
+    /// Read one file's body by exact path.
+    /// Falls back to slicing the blob for documents stored before per-file records.
+    pub async fn get_file(&self, doc_id: &str, path: &str) -> Result<String> {
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        if let Some(bytes) = snapshot.get_raw(&file_key(doc_id, path)).await? {
+            return Ok(String::from_utf8_lossy(&bytes).into_owned());
+        }
+
+        let entry = self
+            .list_files(doc_id)
+            .await?
+            .into_iter()
+            .find(|f| f.path == path)
+            .ok_or_else(|| anyhow::anyhow!("file not found: {}", path))?;
+        let section = self.get_section(doc_id, entry.offset, entry.len).await?;
+        // Drop the "=== path ===" header line
+        Ok(section
+            .split_once('\n')
+            .map(|(_, body)| body.trim_end_matches('\n').to_string())
+            .unwrap_or_default())
+    }
+
+    /// Resolve a (possibly partial) filename to a manifest entry.
+    /// Exact path match wins; otherwise the first case-insensitive substring match.
+    pub async fn find_file(&self, doc_id: &str, filename: &str) -> Result<Option<FileEntry>> {
+        let files = self.list_files(doc_id).await?;
+        if let Some(exact) = files.iter().find(|f| f.path == filename) {
+            return Ok(Some(exact.clone()));
+        }
+        let filename_lower = filename.to_lowercase();
+        Ok(files
+            .into_iter()
+            .find(|f| f.path.to_lowercase().contains(&filename_lower)))
+    }
+
     pub async fn get_meta(&self, doc_id: &str) -> Result<DocMeta> {
         let snapshot = self.storage.latest_snapshot();
         use cnidarium::StateRead;
@@ -126,8 +730,8 @@ impl DocumentStore {
         Ok(serde_json::from_slice(&bytes)?)
     }
 
-    /// List all documents, newest first.
-    pub async fn list(&self, limit: usize, _offset: usize) -> Result<Vec<DocMeta>> {
+    /// List documents in the given scopes, newest first.
+    pub async fn list(&self, scopes: &[Scope], limit: usize, _offset: usize) -> Result<Vec<DocMeta>> {
         let snapshot = self.storage.latest_snapshot();
         use cnidarium::StateRead;
         let mut stream = snapshot.prefix_raw(META_PREFIX);
@@ -137,7 +741,9 @@ impl DocumentStore {
             match entry {
                 Ok((_key, value)) => {
                     if let Ok(meta) = serde_json::from_slice::<DocMeta>(&value) {
-                        results.push(meta);
+                        if scopes.contains(&meta.scope) {
+                            results.push(meta);
+                        }
                     }
                 }
                 Err(e) => {
@@ -152,18 +758,28 @@ impl DocumentStore {
         Ok(results)
     }
 
-    /// List documents with a specific label.
-    pub async fn list_by_label(&self, label: &str) -> Result<Vec<DocMeta>> {
+    /// List the documents of a topic visible from a guild: its own, plus the
+    /// global library's when enabled.
+    pub async fn list_topic(&self, view: &ScopeView, label: &str) -> Result<Vec<DocMeta>> {
+        let mut results = Vec::new();
+        for scope in view.scopes() {
+            results.extend(self.list_by_label(scope, label).await?);
+        }
+        Ok(results)
+    }
+
+    /// List documents with a specific label in one scope.
+    pub async fn list_by_label(&self, scope: Scope, label: &str) -> Result<Vec<DocMeta>> {
         let snapshot = self.storage.latest_snapshot();
         use cnidarium::StateRead;
-        let prefix = format!("{}/{}:", LABEL_PREFIX, label);
+        let prefix = format!("{}/{}/{}:", LABEL_PREFIX, scope.key(), label);
         let mut stream = snapshot.prefix_raw(&prefix);
         let mut results = Vec::new();
 
         while let Some(entry) = stream.next().await {
             match entry {
                 Ok((key, _)) => {
-                    // Key format: "doc/label/{label}:{doc_id}"
+                    // Key format: "doc/label/{scope}/{label}:{doc_id}"
                     let key_str = String::from_utf8_lossy(key.as_bytes());
                     if let Some(doc_id) = key_str.strip_prefix(&prefix) {
                         match self.get_meta(doc_id).await {
@@ -181,137 +797,837 @@ impl DocumentStore {
         Ok(results)
     }
 
-    /// Delete a document and its label index.
-    pub async fn delete(&self, doc_id: &str) -> Result<()> {
+    /// Delete a document, its per-file records, its label index entry and the
+    /// Q/A records that cite it. If it was the active version of its source, the
+    /// newest remaining version is reactivated and returned.
+    pub async fn delete(&self, doc_id: &str) -> Result<Option<DocMeta>> {
         // Get meta first for label cleanup
         let meta = self.get_meta(doc_id).await?;
+        let _lock = self.key_locks.lock(vec![history_key(&meta)]).await;
+        let manifest = self.get_manifest(doc_id).await?.unwrap_or_default();
+        let qa_keys: Vec<String> = self
+            .qa_citing(meta.scope, &meta.label, doc_id)
+            .await?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
         let snapshot = self.storage.latest_snapshot();
+
+        use cnidarium::StateRead;
+        let mut index_keys = Vec::new();
+        let mut stream = snapshot.prefix_raw(&format!("{}/{}/", INDEX_PREFIX, doc_id));
+        while let Some(entry) = stream.next().await {
+            match entry {
+                Ok((key, _)) => index_keys.push(key),
+                Err(e) => warn!("Error reading index keys: {}", e),
+            }
+        }
+        drop(stream);
+
         let mut delta = StateDelta::new(snapshot);
+        let restored = self.unlink_version(&mut delta, &meta).await?;
         delta.delete(content_key(doc_id));
+        for key in index_keys {
+            delta.delete(key);
+        }
+        delta.delete(embed_meta_key(doc_id));
+        delta.delete(embed_vectors_key(doc_id));
+        for entry in &manifest {
+            delta.delete(file_key(doc_id, &entry.path));
+        }
+        delta.delete(manifest_key(doc_id));
         delta.delete(meta_key(doc_id));
-        delta.delete(label_key(&meta.label, doc_id));
+        delta.delete(label_key(meta.scope, &meta.label, doc_id));
+        for key in &qa_keys {
+            delta.delete(key.clone());
+        }
         self.storage.commit(delta).await?;
-        debug!(doc_id, "document deleted");
-        Ok(())
+        self.content_cache.write().await.remove(doc_id);
+        self.manifest_cache.write().await.remove(doc_id);
+        self.index_cache.write().await.remove(doc_id);
+        self.embed_cache.write().await.remove(doc_id);
+        debug!(doc_id, qa_records = qa_keys.len(), "document deleted");
+        Ok(restored)
+    }
+
+    /// Move a document to another topic in its scope, along with the Q/A records
+    /// that cite it. It becomes the newest version of its source in the new topic;
+    /// in the old topic the previous version (if any) is reactivated.
+    pub async fn relabel(&self, doc_id: &str, new_label: &str) -> Result<DocMeta> {
+        let mut meta = self.get_meta(doc_id).await?;
+        let old_label = meta.label.clone();
+        if old_label == new_label {
+            return Ok(meta);
+        }
+        let scope = meta.scope;
+        let histories = vec![
+            history_key(&meta),
+            source_key(scope, new_label, &meta.source),
+        ];
+        let _locks = self.key_locks.lock(histories).await;
+        let qa = self.qa_citing(scope, &old_label, doc_id).await?;
+
+        let snapshot = self.storage.latest_snapshot();
+        let mut delta = StateDelta::new(snapshot);
+        self.unlink_version(&mut delta, &meta).await?;
+        delta.delete(label_key(scope, &old_label, doc_id));
+
+        meta.label = new_label.to_string();
+        self.put_version(&mut delta, &mut meta).await?;
+        delta.put_raw(
+            meta_key(doc_id),
+            serde_json::to_vec(&meta).context("serialize meta")?,
+        );
+        delta.put_raw(label_key(scope, new_label, doc_id), vec![]);
+
+        for (key, mut record) in qa {
+            delta.delete(key);
+            record.topic = new_label.to_string();
+            delta.put_raw(
+                qa_key(record.scope, new_label, &record.id),
+                serde_json::to_vec(&record).context("serialize QaRecord")?,
+            );
+        }
+
+        self.storage.commit(delta).await?;
+        info!(doc_id, from = %old_label, to = new_label, "document relabeled");
+        Ok(meta)
+    }
+
+    /// Resolve a full DocId from an ID or unique ID prefix, among docs in `scopes`.
+    pub async fn resolve_id(&self, scopes: &[Scope], id_or_prefix: &str) -> Result<DocId> {
+        let prefix = id_or_prefix.trim();
+        if prefix.len() < 6 {
+            anyhow::bail!("document ID prefix must be at least 6 characters");
+        }
+        let matches: Vec<DocId> = self
+            .list(scopes, usize::MAX, 0)
+            .await?
+            .into_iter()
+            .map(|m| m.id)
+            .filter(|id| id.starts_with(prefix))
+            .collect();
+        match matches.as_slice() {
+            [id] => Ok(id.clone()),
+            [] => anyhow::bail!("no document matches ID '{}'", prefix),
+            _ => anyhow::bail!("ID prefix '{}' matches {} documents", prefix, matches.len()),
+        }
+    }
+
+    /// Q/A records in a topic whose answer drew on `doc_id`, with their keys.
+    /// Global docs can be cited from any guild, so every scope's records are checked.
+    async fn qa_citing(
+        &self,
+        scope: Scope,
+        label: &str,
+        doc_id: &str,
+    ) -> Result<Vec<(String, QaRecord)>> {
+        let records = match scope {
+            Scope::Guild(_) => self.list_qa(scope, label, usize::MAX).await?,
+            Scope::Global => self.list_qa_all_scopes(label).await?,
+        };
+        Ok(records
+            .into_iter()
+            .filter(|r| r.doc_ids.iter().any(|id| id == doc_id))
+            .map(|r| (qa_key(r.scope, &r.topic, &r.id), r))
+            .collect())
     }
 
     /// Read a char-range section from a document. Capped at 100K chars.
+    /// Multi-file documents read only the files the range overlaps.
     pub async fn get_section(
         &self,
         doc_id: &str,
         offset: usize,
         length: usize,
     ) -> Result<String> {
-        let content = self.get_content(doc_id).await?;
-        let text = String::from_utf8_lossy(&content);
-        let chars: Vec<char> = text.chars().collect();
         let len = length.min(100_000);
-        let start = offset.min(chars.len());
-        let end = (start + len).min(chars.len());
-        Ok(chars[start..end].iter().collect())
+        let Some(manifest) = self.get_manifest(doc_id).await? else {
+            let content = self.get_content(doc_id).await?;
+            return Ok(content.chars().skip(offset).take(len).collect());
+        };
+
+        let end = offset.saturating_add(len);
+        let mut text = String::new();
+        let mut text_offset = None;
+        for entry in manifest
+            .iter()
+            .filter(|e| e.offset < end && e.offset + e.len > offset)
+        {
+            text_offset.get_or_insert(entry.offset);
+            let body = self.file_record(doc_id, &entry.path).await?;
+            text.push_str(&render_section(&entry.path, &body));
+        }
+        let skip = offset - text_offset.unwrap_or(offset);
+        Ok(text.chars().skip(skip).take(len).collect())
     }
 
-    /// Keyword search within a document. Splits query into words, matches ANY word (OR logic).
-    /// Returns excerpts with context window around each match.
-    pub async fn search(
+    /// Load a document's index header, building (and persisting) it on first use
+    /// for documents ingested before the index existed.
+    async fn load_index(&self, doc_id: &str) -> Result<Arc<index::IndexMeta>> {
+        {
+            let cache = self.index_cache.read().await;
+            if let Some(meta) = cache.get(doc_id) {
+                return Ok(meta.clone());
+            }
+        }
+
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        let meta = match snapshot.get_raw(&index_meta_key(doc_id)).await? {
+            Some(bytes) => serde_json::from_slice(&bytes).context("deserialize index meta")?,
+            None => {
+                let text = self.get_content(doc_id).await?;
+                let manifest = self.get_manifest(doc_id).await?.unwrap_or_default();
+                let mut delta = StateDelta::new(snapshot);
+                let meta = put_index(&mut delta, doc_id, &text, &manifest)?;
+                self.storage.commit(delta).await?;
+                info!(doc_id, "search index backfilled");
+                meta
+            }
+        };
+
+        let meta = Arc::new(meta);
+        {
+            let mut cache = self.index_cache.write().await;
+            cache.insert(doc_id.to_string(), meta.clone());
+        }
+        Ok(meta)
+    }
+
+    /// Rank a document's passages by BM25, dropping passages that miss a
+    /// `"quoted phrase"`. At most `limit` passages are returned.
+    async fn keyword_rank(
         &self,
         doc_id: &str,
         query: &str,
-        max_results: usize,
-    ) -> Result<Vec<DocExcerpt>> {
-        let content = self.get_content(doc_id).await?;
-        let text = String::from_utf8_lossy(&content);
-        let text_lower = text.to_lowercase();
-
-        // Split query into individual keywords for OR matching
-        let keywords: Vec<String> = query
-            .split_whitespace()
-            .map(|w| w.to_lowercase())
-            .filter(|w| w.len() >= 2)
-            .collect();
-
-        if keywords.is_empty() {
+        limit: usize,
+    ) -> Result<Vec<RankedPassage>> {
+        let query = index::parse_query(query);
+        if query.terms.is_empty() {
             return Ok(vec![]);
         }
 
-        let context_window = 300; // chars of context around match
-        let mut results = Vec::new();
-        let mut seen_offsets = std::collections::HashSet::new();
+        let meta = self.load_index(doc_id).await?;
 
-        for keyword in &keywords {
-            let mut search_from = 0;
-            while results.len() < max_results * 2 {
-                let Some(byte_pos) = text_lower[search_from..].find(keyword.as_str()) else {
-                    break;
-                };
-                let abs_byte_pos = search_from + byte_pos;
-                let char_pos = text_lower[..abs_byte_pos].chars().count();
-
-                // Skip if we already have a match near this offset
-                let nearby = seen_offsets.iter().any(|&o: &usize| char_pos.abs_diff(o) < context_window);
-                if !nearby {
-                    seen_offsets.insert(char_pos);
-                    let chars: Vec<char> = text.chars().collect();
-                    let start = char_pos.saturating_sub(context_window);
-                    let end = (char_pos + keyword.len() + context_window).min(chars.len());
-                    let excerpt: String = chars[start..end].iter().collect();
-
-                    // Count how many keywords appear in this excerpt
-                    let excerpt_lower = excerpt.to_lowercase();
-                    let match_count = keywords.iter().filter(|k| excerpt_lower.contains(k.as_str())).count();
-
-                    results.push(DocExcerpt {
-                        doc_id: doc_id.to_string(),
-                        offset: char_pos,
-                        content: excerpt,
-                        match_count,
-                    });
-                }
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        let mut postings = std::collections::HashMap::new();
+        for term in &query.terms {
+            if let Some(bytes) = snapshot.get_raw(&index_term_key(doc_id, term)).await? {
+                let list: Vec<index::Posting> =
+                    serde_json::from_slice(&bytes).context("deserialize postings")?;
+                postings.insert(term.clone(), list);
+            }
+        }
+
+        let hits = index::score(&meta, &postings, &query);
+        if hits.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut reader = PassageReader::open(self, doc_id).await?;
+        let mut ranked = Vec::new();
 
-                search_from = abs_byte_pos + keyword.len().max(1);
-                if search_from >= text_lower.len() {
-                    break;
+        for hit in hits {
+            if !query.phrases.is_empty() {
+                let passage = &meta.passages[hit.passage];
+                let body = reader.text(passage).await?;
+                if !query
+                    .phrases
+                    .iter()
+                    .all(|p| index::contains_phrase(body, passage.file.as_deref(), p))
+                {
+                    continue;
                 }
             }
+
+            // Anchor the excerpt on the matched term with the shortest posting list
+            let anchor = hit
+                .matched
+                .iter()
+                .min_by_key(|t| postings.get(*t).map(|l| l.len()).unwrap_or(usize::MAX))
+                .cloned();
+            ranked.push(RankedPassage {
+                passage: hit.passage,
+                score: hit.score,
+                match_count: hit.matched.len(),
+                anchor,
+            });
+            if ranked.len() >= limit {
+                break;
+            }
         }
 
-        // Sort by match_count descending (excerpts matching more keywords first)
-        results.sort_by(|a, b| b.match_count.cmp(&a.match_count));
-        results.truncate(max_results);
-        Ok(results)
+        Ok(ranked)
+    }
+
+    /// Turn ranked passages into excerpts (~600 chars centered on each anchor term).
+    async fn excerpts(&self, doc_id: &str, ranked: &[RankedPassage]) -> Result<Vec<DocExcerpt>> {
+        if ranked.is_empty() {
+            return Ok(vec![]);
+        }
+        let meta = self.load_index(doc_id).await?;
+        let mut reader = PassageReader::open(self, doc_id).await?;
+        let context_window = 300; // chars of context around the anchor term
+
+        let mut excerpts = Vec::with_capacity(ranked.len());
+        for r in ranked {
+            let passage = &meta.passages[r.passage];
+            let body = reader.text(passage).await?;
+            let body_lower = body.to_ascii_lowercase();
+            let anchor_char = r
+                .anchor
+                .as_deref()
+                .and_then(|t| body_lower.find(t))
+                .map(|byte_pos| body[..byte_pos].chars().count())
+                .unwrap_or(0);
+
+            let chars: Vec<char> = body.chars().collect();
+            let start = anchor_char.saturating_sub(context_window);
+            let end = (start + 2 * context_window).min(chars.len());
+
+            excerpts.push(DocExcerpt {
+                doc_id: doc_id.to_string(),
+                offset: passage.offset + anchor_char,
+                content: chars[start..end].iter().collect(),
+                match_count: r.match_count,
+                score: r.score,
+                file: passage.file.clone(),
+            });
+        }
+        Ok(excerpts)
+    }
+
+    /// BM25 search within a document. Query words are OR-ed and ranked by BM25
+    /// over passages, with file-path matches boosted above body matches.
+    /// `"quoted phrases"` must appear verbatim. Returns ~600-char excerpts
+    /// centered on the rarest matched term.
+    pub async fn search(
+        &self,
+        doc_id: &str,
+        query: &str,
+        max_results: usize,
+    ) -> Result<Vec<DocExcerpt>> {
+        let ranked = self.keyword_rank(doc_id, query, max_results).await?;
+        self.excerpts(doc_id, &ranked).await
+    }
+
+    /// Semantic search: passages nearest to `query_vector` by cosine similarity.
+    /// Fails if the document has no embeddings from `model` (see `store_embeddings`).
+    pub async fn semantic_search(
+        &self,
+        doc_id: &str,
+        model: &str,
+        query_vector: &[f32],
+        k: usize,
+    ) -> Result<Vec<DocExcerpt>> {
+        let embeddings = self.require_embeddings(doc_id, model, query_vector).await?;
+        let ranked: Vec<RankedPassage> = embeddings
+            .nearest(query_vector, k)
+            .into_iter()
+            .map(|(passage, score)| RankedPassage {
+                passage,
+                score,
+                match_count: 0,
+                anchor: None,
+            })
+            .collect();
+        self.excerpts(doc_id, &ranked).await
+    }
+
+    /// Hybrid search: BM25 and semantic rankings merged with reciprocal-rank fusion.
+    /// `score` on the returned excerpts is the fused RRF score.
+    pub async fn hybrid_search(
+        &self,
+        doc_id: &str,
+        query: &str,
+        model: &str,
+        query_vector: &[f32],
+        k: usize,
+    ) -> Result<Vec<DocExcerpt>> {
+        // Over-fetch both lists so fusion has candidates beyond the top k
+        let depth = (k * 4).max(20);
+        let embeddings = self.require_embeddings(doc_id, model, query_vector).await?;
+        let keyword = self.keyword_rank(doc_id, query, depth).await?;
+        let semantic = embeddings.nearest(query_vector, depth);
+
+        let fused = embed::rrf_fuse(&[
+            keyword.iter().map(|r| r.passage).collect(),
+            semantic.iter().map(|(p, _)| *p).collect(),
+        ]);
+
+        let ranked: Vec<RankedPassage> = fused
+            .into_iter()
+            .take(k)
+            .map(|(passage, score)| {
+                let kw = keyword.iter().find(|r| r.passage == passage);
+                RankedPassage {
+                    passage,
+                    score,
+                    match_count: kw.map(|r| r.match_count).unwrap_or(0),
+                    anchor: kw.and_then(|r| r.anchor.clone()),
+                }
+            })
+            .collect();
+        self.excerpts(doc_id, &ranked).await
+    }
+
+    /// Text of every index passage, in passage order — the unit we embed.
+    pub async fn passage_texts(&self, doc_id: &str) -> Result<Vec<String>> {
+        let meta = self.load_index(doc_id).await?;
+        let mut reader = PassageReader::open(self, doc_id).await?;
+        let mut texts = Vec::with_capacity(meta.passages.len());
+        for p in &meta.passages {
+            let text = reader.text(p).await?;
+            texts.push(match &p.file {
+                // Prefix the path so embeddings capture where the text lives
+                Some(file) => format!("{}\n{}", file, text),
+                None => text.to_string(),
+            });
+        }
+        Ok(texts)
     }
 
-    /// Extract file/section headers from an ingested document.
-    /// Githem-core uses `=== filename ===` as section delimiters.
-    pub async fn list_files(&self, doc_id: &str) -> Result<Vec<(usize, String)>> {
-        let content = self.get_content(doc_id).await?;
-        let text = String::from_utf8_lossy(&content);
-        let mut files = Vec::new();
+    /// Store passage embeddings (one vector per `passage_texts` entry).
+    pub async fn store_embeddings(
+        &self,
+        doc_id: &str,
+        model: &str,
+        vectors: &[Vec<f32>],
+    ) -> Result<()> {
+        let meta = self.load_index(doc_id).await?;
+        if vectors.len() != meta.passages.len() {
+            anyhow::bail!(
+                "expected {} passage embeddings, got {}",
+                meta.passages.len(),
+                vectors.len()
+            );
+        }
+        let dim = vectors.first().map(|v| v.len()).unwrap_or(0);
+        if vectors.iter().any(|v| v.len() != dim) {
+            anyhow::bail!("embedding vectors have inconsistent dimensions");
+        }
+
+        let header = embed::EmbeddingMeta {
+            model: model.to_string(),
+            dim,
+            count: vectors.len(),
+        };
+        let snapshot = self.storage.latest_snapshot();
+        let mut delta = StateDelta::new(snapshot);
+        delta.put_raw(embed_vectors_key(doc_id), embed::encode(vectors));
+        delta.put_raw(
+            embed_meta_key(doc_id),
+            serde_json::to_vec(&header).context("serialize embedding meta")?,
+        );
+        self.storage.commit(delta).await?;
+        self.embed_cache.write().await.remove(doc_id);
+        debug!(doc_id, model, dim, count = vectors.len(), "embeddings stored");
+        Ok(())
+    }
+
+    /// Load a document's passage embeddings, if it has any.
+    pub async fn load_embeddings(&self, doc_id: &str) -> Result<Option<Arc<embed::DocEmbeddings>>> {
+        {
+            let cache = self.embed_cache.read().await;
+            if let Some(e) = cache.get(doc_id) {
+                return Ok(Some(e.clone()));
+            }
+        }
+
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        let Some(header) = snapshot.get_raw(&embed_meta_key(doc_id)).await? else {
+            return Ok(None);
+        };
+        let meta: embed::EmbeddingMeta =
+            serde_json::from_slice(&header).context("deserialize embedding meta")?;
+        let bytes = snapshot
+            .get_raw(&embed_vectors_key(doc_id))
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("embedding vectors missing: {}", doc_id))?;
+
+        let embeddings = Arc::new(embed::DocEmbeddings {
+            meta,
+            vectors: embed::decode(&bytes),
+        });
+        {
+            let mut cache = self.embed_cache.write().await;
+            cache.insert(doc_id.to_string(), embeddings.clone());
+        }
+        Ok(Some(embeddings))
+    }
+
+    async fn require_embeddings(
+        &self,
+        doc_id: &str,
+        model: &str,
+        query_vector: &[f32],
+    ) -> Result<Arc<embed::DocEmbeddings>> {
+        let embeddings = self.load_embeddings(doc_id).await?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "document {} has no embeddings — use search_document() instead",
+                doc_id
+            )
+        })?;
+        if embeddings.meta.model != model || embeddings.meta.dim != query_vector.len() {
+            anyhow::bail!(
+                "document {} was embedded with {} ({} dims), not {} — re-ingest it",
+                doc_id,
+                embeddings.meta.model,
+                embeddings.meta.dim,
+                model
+            );
+        }
+        Ok(embeddings)
+    }
+
+    /// List the files of an ingested document.
+    /// Multi-file documents answer from their manifest; older single-blob documents
+    /// fall back to scanning for githem-core's `=== filename ===` section delimiters.
+    pub async fn list_files(&self, doc_id: &str) -> Result<Vec<FileEntry>> {
+        if let Some(manifest) = self.get_manifest(doc_id).await? {
+            return Ok(manifest);
+        }
+
+        let text = self.get_content(doc_id).await?;
+        // (path, header char offset, body byte offset)
+        let mut headers: Vec<(String, usize, usize)> = Vec::new();
         let mut char_offset = 0;
+        let mut byte_offset = 0;
 
         for line in text.lines() {
+            char_offset += line.chars().count() + 1; // +1 for newline
+            byte_offset += line.len() + 1;
             if line.starts_with("=== ") && line.ends_with(" ===") {
                 let name = line.trim_start_matches("=== ").trim_end_matches(" ===");
-                files.push((char_offset, name.to_string()));
+                let header_chars = line.chars().count() + 1;
+                headers.push((name.to_string(), char_offset - header_chars, byte_offset));
             }
-            char_offset += line.chars().count() + 1; // +1 for newline
+        }
+
+        // Each section runs until the next header (or end of document)
+        let mut files = Vec::with_capacity(headers.len());
+        for (i, (path, offset, body_start)) in headers.iter().enumerate() {
+            let (next_offset, body_end) = headers
+                .get(i + 1)
+                .map(|(next, next_offset, next_body)| {
+                    (*next_offset, next_body - (next.len() + 9)) // "=== " + " ===\n"
+                })
+                .unwrap_or((char_offset, byte_offset));
+            files.push(FileEntry {
+                path: path.clone(),
+                size: body_end.saturating_sub(*body_start),
+                language: ingest::detect_language(path).map(|l| l.to_string()),
+                offset: *offset,
+                len: next_offset - offset,
+            });
         }
 
         Ok(files)
     }
 
-    /// Get unique labels for autocomplete.
-    pub async fn labels(&self) -> Result<Vec<String>> {
+    /// Version history of a source within a topic, if it has been ingested there.
+    pub async fn source_history(
+        &self,
+        scope: Scope,
+        label: &str,
+        source: &str,
+    ) -> Result<Option<SourceHistory>> {
         let snapshot = self.storage.latest_snapshot();
         use cnidarium::StateRead;
-        let mut stream = snapshot.prefix_raw(LABEL_PREFIX);
-        let mut labels = std::collections::BTreeSet::new();
+        match snapshot.get_raw(&source_key(scope, label, source)).await? {
+            Some(bytes) => Ok(Some(
+                serde_json::from_slice(&bytes).context("deserialize source history")?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Version histories of every source in a topic.
+    pub async fn list_sources(&self, scope: Scope, label: &str) -> Result<Vec<SourceHistory>> {
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        let prefix = format!("{}/{}/{}:", SOURCE_PREFIX, scope.key(), label);
+        let mut stream = snapshot.prefix_raw(&prefix);
+        let mut results = Vec::new();
 
         while let Some(entry) = stream.next().await {
-            if let Ok((key, _)) = entry {
-                let key_str = String::from_utf8_lossy(key.as_bytes());
-                // Key format: "doc/label/{label}:{doc_id}"
-                if let Some(rest) = key_str.strip_prefix(&format!("{}/", LABEL_PREFIX)) {
+            match entry {
+                Ok((_key, value)) => {
+                    if let Ok(history) = serde_json::from_slice::<SourceHistory>(&value) {
+                        results.push(history);
+                    }
+                }
+                Err(e) => {
+                    warn!("Error reading source history stream: {}", e);
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Metadata of a specific version of a source.
+    pub async fn get_version(
+        &self,
+        scope: Scope,
+        label: &str,
+        source: &str,
+        version: u32,
+    ) -> Result<DocMeta> {
+        let history = self
+            .source_history(scope, label, source)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("no history for {} in topic '{}'", source, label))?;
+        let entry = history
+            .get(version)
+            .ok_or_else(|| anyhow::anyhow!("{} has no version {}", source, version))?;
+        self.get_meta(&entry.doc_id).await
+    }
+
+    /// Make `version` the active version of a source in its topic, superseding
+    /// the currently active one.
+    pub async fn rollback(
+        &self,
+        scope: Scope,
+        label: &str,
+        source: &str,
+        version: u32,
+    ) -> Result<DocMeta> {
+        let _lock = self
+            .key_locks
+            .lock(vec![source_key(scope, label, source)])
+            .await;
+        let mut history = self
+            .source_history(scope, label, source)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("no history for {} in topic '{}'", source, label))?;
+        let target = history
+            .get(version)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("{} has no version {}", source, version))?;
+        let mut meta = self.get_meta(&target.doc_id).await?;
+
+        let snapshot = self.storage.latest_snapshot();
+        let mut delta = StateDelta::new(snapshot);
+        if let Some(active) = history.active_version() {
+            if active.doc_id != target.doc_id {
+                self.retire(&mut delta, &active.doc_id, scope, label).await?;
+            }
+        }
+
+        meta.version = version;
+        meta.superseded = false;
+        delta.put_raw(
+            meta_key(&meta.id),
+            serde_json::to_vec(&meta).context("serialize meta")?,
+        );
+        delta.put_raw(label_key(scope, label, &meta.id), vec![]);
+        history.active = version;
+        delta.put_raw(
+            source_key(scope, label, source),
+            serde_json::to_vec(&history).context("serialize source history")?,
+        );
+        self.storage.commit(delta).await?;
+
+        info!(label, source, version, doc_id = %meta.id, "source rolled back");
+        Ok(meta)
+    }
+
+    /// Record `meta` as the newest version of its (label, source), retiring the
+    /// previously active version from the topic. Re-storing the active doc keeps
+    /// its version, and re-storing an earlier version's content makes that
+    /// version active again. Sources first seen here adopt any pre-versioning
+    /// docs of the same source as their earlier versions. Callers hold the
+    /// history's key lock until `delta` is committed.
+    async fn put_version(
+        &self,
+        delta: &mut StateDelta<cnidarium::Snapshot>,
+        meta: &mut DocMeta,
+    ) -> Result<()> {
+        let mut history = match self
+            .source_history(meta.scope, &meta.label, &meta.source)
+            .await?
+        {
+            Some(history) => history,
+            None => {
+                self.adopt_legacy_versions(delta, meta.scope, &meta.label, &meta.source)
+                    .await?
+            }
+        };
+
+        if let Some(active) = history.active_version() {
+            if active.doc_id == meta.id {
+                meta.version = active.version;
+                return Ok(());
+            }
+            let previous = active.doc_id.clone();
+            self.retire(delta, &previous, meta.scope, &meta.label).await?;
+        }
+
+        match history.versions.iter().find(|v| v.doc_id == meta.id) {
+            Some(earlier) => meta.version = earlier.version,
+            None => {
+                meta.version = history.next_version();
+                history.versions.push(SourceVersion {
+                    version: meta.version,
+                    doc_id: meta.id.clone(),
+                    ingested_at: meta.ingested_at,
+                    commit: meta.commit.clone(),
+                });
+            }
+        }
+        meta.superseded = false;
+        history.active = meta.version;
+        delta.put_raw(
+            source_key(meta.scope, &meta.label, &meta.source),
+            serde_json::to_vec(&history).context("serialize source history")?,
+        );
+        Ok(())
+    }
+
+    /// Build a history from docs of `source` already in the topic (oldest first),
+    /// retiring all but the newest.
+    async fn adopt_legacy_versions(
+        &self,
+        delta: &mut StateDelta<cnidarium::Snapshot>,
+        scope: Scope,
+        label: &str,
+        source: &str,
+    ) -> Result<SourceHistory> {
+        let mut legacy: Vec<DocMeta> = self
+            .list_by_label(scope, label)
+            .await?
+            .into_iter()
+            .filter(|d| d.source == source)
+            .collect();
+        legacy.sort_by_key(|d| d.ingested_at);
+
+        let mut history = SourceHistory {
+            source: source.to_string(),
+            scope,
+            label: label.to_string(),
+            active: 0,
+            versions: Vec::new(),
+        };
+        for (i, doc) in legacy.iter().enumerate() {
+            let version = history.next_version();
+            history.versions.push(SourceVersion {
+                version,
+                doc_id: doc.id.clone(),
+                ingested_at: doc.ingested_at,
+                commit: doc.commit.clone(),
+            });
+            if i + 1 < legacy.len() {
+                self.retire(delta, &doc.id, scope, label).await?;
+            } else {
+                let mut doc = doc.clone();
+                doc.version = version;
+                delta.put_raw(
+                    meta_key(&doc.id),
+                    serde_json::to_vec(&doc).context("serialize meta")?,
+                );
+                history.active = version;
+            }
+        }
+        if !legacy.is_empty() {
+            info!(label, source, versions = legacy.len(), "adopted pre-versioning documents");
+        }
+        Ok(history)
+    }
+
+    /// Remove a doc from its (label, source) history. If it was the active
+    /// version, the newest remaining version is reactivated and returned.
+    async fn unlink_version(
+        &self,
+        delta: &mut StateDelta<cnidarium::Snapshot>,
+        meta: &DocMeta,
+    ) -> Result<Option<DocMeta>> {
+        let Some(mut history) = self
+            .source_history(meta.scope, &meta.label, &meta.source)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let was_active = history.active_version().map(|v| v.doc_id == meta.id).unwrap_or(false);
+        history.versions.retain(|v| v.doc_id != meta.id);
+
+        let key = source_key(meta.scope, &meta.label, &meta.source);
+        let mut restored = None;
+        if was_active {
+            history.active = 0;
+            if let Some(previous) = history.versions.last() {
+                match self.get_meta(&previous.doc_id).await {
+                    Ok(mut prev) => {
+                        prev.version = previous.version;
+                        prev.superseded = false;
+                        delta.put_raw(
+                            meta_key(&prev.id),
+                            serde_json::to_vec(&prev).context("serialize meta")?,
+                        );
+                        delta.put_raw(label_key(meta.scope, &meta.label, &prev.id), vec![]);
+                        history.active = previous.version;
+                        restored = Some(prev);
+                    }
+                    Err(e) => warn!(doc_id = %previous.doc_id, error = %e, "previous version missing"),
+                }
+            }
+        }
+
+        if history.versions.is_empty() {
+            delta.delete(key);
+        } else {
+            delta.put_raw(
+                key,
+                serde_json::to_vec(&history).context("serialize source history")?,
+            );
+        }
+        Ok(restored)
+    }
+
+    /// Drop a doc from its topic's label index and mark it superseded.
+    async fn retire(
+        &self,
+        delta: &mut StateDelta<cnidarium::Snapshot>,
+        doc_id: &str,
+        scope: Scope,
+        label: &str,
+    ) -> Result<()> {
+        delta.delete(label_key(scope, label, doc_id));
+        // Read through the delta: the doc may have been updated earlier in it
+        use cnidarium::StateRead;
+        let meta = match delta.get_raw(&meta_key(doc_id)).await? {
+            Some(bytes) => serde_json::from_slice::<DocMeta>(&bytes).context("deserialize meta"),
+            None => Err(anyhow::anyhow!("document metadata not found: {}", doc_id)),
+        };
+        match meta {
+            Ok(mut meta) => {
+                meta.superseded = true;
+                delta.put_raw(
+                    meta_key(doc_id),
+                    serde_json::to_vec(&meta).context("serialize meta")?,
+                );
+            }
+            Err(e) => warn!(doc_id, error = %e, "superseded document has no metadata"),
+        }
+        Ok(())
+    }
+
+    /// Get unique labels in the given scopes for autocomplete.
+    pub async fn labels(&self, scopes: &[Scope]) -> Result<Vec<String>> {
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        let mut labels = std::collections::BTreeSet::new();
+
+        for scope in scopes {
+            let prefix = format!("{}/{}/", LABEL_PREFIX, scope.key());
+            let mut stream = snapshot.prefix_raw(&prefix);
+            while let Some(entry) = stream.next().await {
+                let Ok((key, _)) = entry else { continue };
+                // Key format: "doc/label/{scope}/{label}:{doc_id}"
+                if let Some(rest) = key.strip_prefix(&prefix) {
                     if let Some(label) = rest.split(':').next() {
                         labels.insert(label.to_string());
                     }
@@ -327,7 +1643,7 @@ impl DocumentStore {
         let snapshot = self.storage.latest_snapshot();
         let mut delta = StateDelta::new(snapshot);
         delta.put_raw(
-            qa_key(&record.topic, &record.id),
+            qa_key(record.scope, &record.topic, &record.id),
             serde_json::to_vec(record).context("serialize QaRecord")?,
         );
         self.storage.commit(delta).await?;
@@ -335,19 +1651,42 @@ impl DocumentStore {
         Ok(())
     }
 
-    /// List Q/A records for a topic, newest first.
-    pub async fn list_qa(&self, topic: &str, limit: usize) -> Result<Vec<QaRecord>> {
+    /// List a scope's Q/A records for a topic, newest first.
+    pub async fn list_qa(&self, scope: Scope, topic: &str, limit: usize) -> Result<Vec<QaRecord>> {
+        let mut results = self.scan_qa(&qa_prefix(scope, topic), |_| true).await?;
+        results.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        results.truncate(limit);
+        Ok(results)
+    }
+
+    /// A scope's Q/A records on every topic from `since` (unix seconds) on.
+    pub async fn list_qa_since(&self, scope: Scope, since: i64) -> Result<Vec<QaRecord>> {
+        let prefix = format!("{}/{}/", QA_PREFIX, scope.key());
+        self.scan_qa(&prefix, |r| r.timestamp >= since).await
+    }
+
+    /// Q/A records for a topic across every scope.
+    async fn list_qa_all_scopes(&self, topic: &str) -> Result<Vec<QaRecord>> {
+        self.scan_qa(QA_PREFIX, |r| r.topic == topic).await
+    }
+
+    async fn scan_qa(
+        &self,
+        prefix: &str,
+        keep: impl Fn(&QaRecord) -> bool,
+    ) -> Result<Vec<QaRecord>> {
         let snapshot = self.storage.latest_snapshot();
         use cnidarium::StateRead;
-        let prefix = format!("{}/{}/", QA_PREFIX, topic);
-        let mut stream = snapshot.prefix_raw(&prefix);
+        let mut stream = snapshot.prefix_raw(prefix);
         let mut results = Vec::new();
 
         while let Some(entry) = stream.next().await {
             match entry {
                 Ok((_key, value)) => {
                     if let Ok(record) = serde_json::from_slice::<QaRecord>(&value) {
-                        results.push(record);
+                        if keep(&record) {
+                            results.push(record);
+                        }
                     }
                 }
                 Err(e) => {
@@ -355,9 +1694,605 @@ impl DocumentStore {
                 }
             }
         }
+        Ok(results)
+    }
 
-        results.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
-        results.truncate(limit);
+    /// Record an answer posted in a thread, adding its question to the thread's
+    /// index entry.
+    pub async fn record_thread_answer(
+        &self,
+        scope: Scope,
+        topic: &str,
+        thread_id: u64,
+        question: &str,
+        answer: &str,
+        cited_urls: &[String],
+    ) -> Result<()> {
+        let key = thread_key(scope, topic, thread_id);
+        let _lock = self.key_locks.lock(vec![key.clone()]).await;
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        let mut thread = match snapshot.get_raw(&key).await? {
+            Some(bytes) => serde_json::from_slice(&bytes).context("deserialize AnsweredThread")?,
+            None => AnsweredThread {
+                thread_id,
+                scope,
+                topic: topic.to_string(),
+                questions: Vec::new(),
+                answer: String::new(),
+                cited_urls: Vec::new(),
+                updated_at: 0,
+            },
+        };
+        thread.questions.push(question.to_string());
+        thread.answer = answer.to_string();
+        thread.cited_urls = cited_urls.to_vec();
+        thread.updated_at = chrono::Utc::now().timestamp();
+
+        let mut delta = StateDelta::new(snapshot);
+        delta.put_raw(
+            key,
+            serde_json::to_vec(&thread).context("serialize AnsweredThread")?,
+        );
+        self.storage.commit(delta).await?;
+        debug!(thread_id, topic, "Thread answer recorded");
+        Ok(())
+    }
+
+    /// Answered threads of a topic in the given scopes, newest first.
+    pub async fn list_threads(&self, scopes: &[Scope], topic: &str) -> Result<Vec<AnsweredThread>> {
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        let mut results = Vec::new();
+
+        for scope in scopes {
+            let mut stream = snapshot.prefix_raw(&thread_prefix(*scope, topic));
+            while let Some(entry) = stream.next().await {
+                match entry {
+                    Ok((_key, value)) => {
+                        if let Ok(thread) = serde_json::from_slice::<AnsweredThread>(&value) {
+                            results.push(thread);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Error reading thread stream: {}", e);
+                    }
+                }
+            }
+        }
+
+        results.sort_by_key(|t| std::cmp::Reverse(t.updated_at));
+        Ok(results)
+    }
+
+    /// Load a persisted runtime config value from one scope.
+    pub async fn load_config<T: serde::de::DeserializeOwned>(
+        &self,
+        scope: Scope,
+        name: &str,
+    ) -> Result<Option<T>> {
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        match snapshot.get_raw(&config_key(scope, name)).await? {
+            Some(bytes) => Ok(Some(
+                serde_json::from_slice(&bytes)
+                    .with_context(|| format!("deserialize config '{}'", name))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Persist a runtime config value together with its audit entry, in one commit.
+    pub async fn put_config<T: serde::Serialize>(
+        &self,
+        scope: Scope,
+        name: &str,
+        value: &T,
+        change: &ConfigChange,
+    ) -> Result<()> {
+        let snapshot = self.storage.latest_snapshot();
+        let mut delta = StateDelta::new(snapshot);
+        delta.put_raw(
+            config_key(scope, name),
+            serde_json::to_vec(value).context("serialize config")?,
+        );
+        let nanos = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        delta.put_raw(
+            config_audit_key(scope, nanos),
+            serde_json::to_vec(change).context("serialize config change")?,
+        );
+        self.storage.commit(delta).await?;
+        info!(
+            key = name,
+            scope = %scope.key(),
+            user = %change.user_name,
+            summary = %change.summary,
+            "config updated"
+        );
+        Ok(())
+    }
+
+    /// Recent config changes in one scope, newest first. Reads at most `limit`
+    /// entries, plus those kept oldest first before audit keys sorted newest
+    /// first when fewer than `limit` newer ones exist.
+    pub async fn config_audit(&self, scope: Scope, limit: usize) -> Result<Vec<ConfigChange>> {
+        let snapshot = self.storage.latest_snapshot();
+        use cnidarium::StateRead;
+        let prefix = config_audit_prefix(scope);
+        let mut results = Vec::new();
+
+        let newest_first = format!("{}{}", prefix, AUDIT_NEWEST_FIRST);
+        let mut stream = snapshot.prefix_raw(&newest_first).take(limit);
+        while let Some(entry) = stream.next().await {
+            match entry {
+                Ok((_key, value)) => {
+                    if let Ok(change) = serde_json::from_slice::<ConfigChange>(&value) {
+                        results.push(change);
+                    }
+                }
+                Err(e) => {
+                    warn!("Error reading config audit stream: {}", e);
+                }
+            }
+        }
+        if results.len() >= limit {
+            return Ok(results);
+        }
+
+        // The oldest-first entries no longer grow, so reading them all is bounded
+        let mut older = Vec::new();
+        let mut stream = snapshot.prefix_raw(&prefix);
+        while let Some(entry) = stream.next().await {
+            match entry {
+                Ok((key, value)) => {
+                    if key[prefix.len()..].starts_with(AUDIT_NEWEST_FIRST) {
+                        continue;
+                    }
+                    if let Ok(change) = serde_json::from_slice::<ConfigChange>(&value) {
+                        older.push(change);
+                    }
+                }
+                Err(e) => {
+                    warn!("Error reading config audit stream: {}", e);
+                }
+            }
+        }
+        older.sort_by_key(|c| std::cmp::Reverse(c.timestamp));
+        results.extend(older.into_iter().take(limit - results.len()));
         Ok(results)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_scoped_key() {
+        assert_eq!(
+            legacy_scoped_key("doc/label/akash:abc123").as_deref(),
+            Some("doc/label/global/akash:abc123")
+        );
+        assert_eq!(
+            legacy_scoped_key("doc/source/akash:github:o/r").as_deref(),
+            Some("doc/source/global/akash:github:o/r")
+        );
+        assert_eq!(
+            legacy_scoped_key("qa/akash/q1").as_deref(),
+            Some("qa/global/akash/q1")
+        );
+        assert_eq!(
+            legacy_scoped_key("config/value/rlm").as_deref(),
+            Some("config/value/global/rlm")
+        );
+        assert_eq!(
+            legacy_scoped_key("config/audit/00000000000000000005").as_deref(),
+            Some("config/audit/global/00000000000000000005")
+        );
+        // Already scoped
+        assert_eq!(legacy_scoped_key("doc/label/g42/akash:abc123"), None);
+        assert_eq!(legacy_scoped_key("qa/global/akash/q1"), None);
+        assert_eq!(legacy_scoped_key("config/value/g42/rlm"), None);
+        assert_eq!(legacy_scoped_key(SCOPE_MIGRATION_KEY), None);
+    }
+
+    #[test]
+    fn test_doc_id_scoped() {
+        let content = b"same content";
+        assert_eq!(
+            unlabeled_doc_id(Scope::Global, content),
+            blake3::hash(content).to_hex().to_string()
+        );
+        let id = |scope, label| doc_id(scope, label, content, 0);
+        assert_ne!(id(Scope::Guild(1), "a"), id(Scope::Guild(2), "a"));
+        assert_ne!(id(Scope::Guild(1), "a"), id(Scope::Global, "a"));
+        assert_ne!(id(Scope::Guild(1), "a"), id(Scope::Guild(1), "b"));
+        assert_ne!(
+            id(Scope::Guild(1), "a"),
+            doc_id(Scope::Guild(1), "a", content, 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_same_content_in_two_topics() {
+        let store = temp_store("two-topics").await;
+        let other = DocInfo {
+            label: "cosmos",
+            ..info("docs.md")
+        };
+        let akash = store.store(b"shared", &info("docs.md")).await.unwrap();
+        let cosmos = store.store(b"shared", &other).await.unwrap();
+        assert_ne!(akash, cosmos);
+        assert_eq!(store.store(b"shared", &other).await.unwrap(), cosmos);
+        assert_eq!(store.get_meta(&akash).await.unwrap().label, "akash");
+
+        // Deleting one topic's copy leaves the other's intact
+        store.delete(&cosmos).await.unwrap();
+        assert_eq!(listed(&store).await, vec![akash.clone()]);
+        assert!(store
+            .source_history(Scope::Guild(1), "cosmos", "docs.md")
+            .await
+            .unwrap()
+            .map_or(true, |h| h.versions.is_empty()));
+
+        // A doc relabeled away keeps its ID; its old topic gets a new one
+        store.relabel(&akash, "cosmos").await.unwrap();
+        let again = store.store(b"shared", &info("docs.md")).await.unwrap();
+        assert_ne!(again, akash);
+        assert_eq!(store.get_meta(&akash).await.unwrap().label, "cosmos");
+        assert_eq!(listed(&store).await, vec![again]);
+
+        // Docs stored before topics were hashed in keep their IDs
+        let legacy = unlabeled_doc_id(Scope::Guild(1), b"old");
+        let mut meta = store.get_meta(&akash).await.unwrap();
+        meta.id = legacy.clone();
+        meta.label = "akash".to_string();
+        let mut delta = StateDelta::new(store.storage.latest_snapshot());
+        delta.put_raw(meta_key(&legacy), serde_json::to_vec(&meta).unwrap());
+        store.storage.commit(delta).await.unwrap();
+        assert_eq!(store.store(b"old", &info("old.md")).await.unwrap(), legacy);
+    }
+
+    #[tokio::test]
+    async fn test_file_records_are_read_without_assembling() {
+        let store = temp_store("file-reads").await;
+        let files = vec![
+            (
+                "src/lib.rs".to_string(),
+                "pub fn lease() -> u64 { 42 }\n".repeat(100),
+            ),
+            (
+                "README.md".to_string(),
+                "# Provider\nBids are signed — by the provider daemon.".to_string(),
+            ),
+        ];
+        let id = store.store_files(&files, &info("o/r")).await.unwrap();
+        let (text, manifest) = render_files(&files);
+
+        let hits = store.search(&id, "\"provider daemon\"", 5).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].file.as_deref(), Some("README.md"));
+        assert!(hits[0].content.contains("signed — by the provider daemon"));
+
+        let expected: Vec<String> = index::build(&text, &manifest)
+            .meta
+            .passages
+            .iter()
+            .map(|p| format!("{}\n{}", p.file.as_deref().unwrap(), passage_text(&text, p)))
+            .collect();
+        assert_eq!(store.passage_texts(&id).await.unwrap(), expected);
+
+        // A range spanning both files
+        let offset = manifest[1].offset - 10;
+        let section = store.get_section(&id, offset, 40).await.unwrap();
+        let expected: String = text.chars().skip(offset).take(40).collect();
+        assert_eq!(section, expected);
+
+        assert!(store.content_cache.read().await.is_empty());
+        assert_eq!(&*store.get_content(&id).await.unwrap(), text);
+    }
+
+    #[tokio::test]
+    async fn test_qa_topics_are_escaped() {
+        let store = temp_store("qa-topics").await;
+        let scope = Scope::Guild(1);
+        let record = |topic| {
+            QaRecord::unanswered(
+                scope,
+                topic,
+                "q",
+                7,
+                types::QueryOutcome::Failed,
+                Default::default(),
+                0,
+            )
+        };
+        let topics = |records: Vec<QaRecord>| -> Vec<String> {
+            records.into_iter().map(|r| r.topic).collect()
+        };
+        store.store_qa(&record("akash")).await.unwrap();
+        store.store_qa(&record("akash/v2")).await.unwrap();
+        let akash = store.list_qa(scope, "akash", 10).await.unwrap();
+        assert_eq!(topics(akash), vec!["akash"]);
+        let v2 = store.list_qa(scope, "akash/v2", 10).await.unwrap();
+        assert_eq!(topics(v2), vec!["akash/v2"]);
+
+        // Records filed under the raw topic move to the escaped key
+        let legacy = record("akash/v3");
+        let mut delta = StateDelta::new(store.storage.latest_snapshot());
+        delta.put_raw(
+            format!("{}/{}/akash/v3/{}", QA_PREFIX, scope.key(), legacy.id),
+            serde_json::to_vec(&legacy).unwrap(),
+        );
+        delta.delete(QA_TOPIC_MIGRATION_KEY.to_string());
+        store.storage.commit(delta).await.unwrap();
+        let v3 = store.list_qa(scope, "akash/v3", 10).await.unwrap();
+        assert!(v3.is_empty());
+        store.migrate_qa_topics().await.unwrap();
+        let v3 = store.list_qa(scope, "akash/v3", 10).await.unwrap();
+        assert_eq!(topics(v3), vec!["akash/v3"]);
+        assert_eq!(store.list_qa_since(scope, 0).await.unwrap().len(), 3);
+    }
+
+    fn info<'a>(source: &'a str) -> DocInfo<'a> {
+        DocInfo {
+            scope: Scope::Guild(1),
+            name: source,
+            source,
+            label: "akash",
+            url_context: None,
+            commit: None,
+        }
+    }
+
+    async fn temp_store(name: &str) -> DocumentStore {
+        let dir = std::env::temp_dir().join(format!("edgar-store-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        DocumentStore::new(&dir).await.unwrap()
+    }
+
+    /// IDs of the documents listed under the test topic.
+    async fn listed(store: &DocumentStore) -> Vec<DocId> {
+        let mut ids: Vec<DocId> = store
+            .list_by_label(Scope::Guild(1), "akash")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|d| d.id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Make `doc_id` look stored before versioning: listed under its topic,
+    /// unversioned, and without a source history.
+    async fn make_legacy(store: &DocumentStore, doc_id: &str, ingested_at: i64) {
+        let mut meta = store.get_meta(doc_id).await.unwrap();
+        meta.version = 0;
+        meta.superseded = false;
+        meta.ingested_at = ingested_at;
+        let mut delta = StateDelta::new(store.storage.latest_snapshot());
+        delta.put_raw(meta_key(doc_id), serde_json::to_vec(&meta).unwrap());
+        delta.put_raw(label_key(meta.scope, &meta.label, doc_id), vec![]);
+        delta.delete(source_key(meta.scope, &meta.label, &meta.source));
+        store.storage.commit(delta).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_versions_and_rollback() {
+        let store = temp_store("versions").await;
+        let v1 = store.store(b"first", &info("docs.md")).await.unwrap();
+        let v2 = store.store(b"second", &info("docs.md")).await.unwrap();
+        // Storing the active content again changes nothing
+        assert_eq!(store.store(b"second", &info("docs.md")).await.unwrap(), v2);
+
+        let history = store
+            .source_history(Scope::Guild(1), "akash", "docs.md")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(history.versions.len(), 2);
+        assert_eq!(history.active, 2);
+        assert_eq!(listed(&store).await, vec![v2.clone()]);
+        assert!(store.get_meta(&v1).await.unwrap().superseded);
+
+        let meta = store
+            .rollback(Scope::Guild(1), "akash", "docs.md", 1)
+            .await
+            .unwrap();
+        assert_eq!((meta.id.as_str(), meta.version), (v1.as_str(), 1));
+        assert_eq!(listed(&store).await, vec![v1.clone()]);
+        assert!(store.get_meta(&v2).await.unwrap().superseded);
+        assert!(store
+            .rollback(Scope::Guild(1), "akash", "docs.md", 3)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reingesting_an_earlier_version_reactivates_it() {
+        let store = temp_store("reingest").await;
+        let v1 = store.store(b"first", &info("docs.md")).await.unwrap();
+        let v2 = store.store(b"second", &info("docs.md")).await.unwrap();
+        assert_eq!(store.store(b"first", &info("docs.md")).await.unwrap(), v1);
+
+        let history = store
+            .source_history(Scope::Guild(1), "akash", "docs.md")
+            .await
+            .unwrap()
+            .unwrap();
+        let versions: Vec<(u32, &str)> = history
+            .versions
+            .iter()
+            .map(|v| (v.version, v.doc_id.as_str()))
+            .collect();
+        assert_eq!(versions, vec![(1, v1.as_str()), (2, v2.as_str())]);
+        assert_eq!(history.active, 1);
+        let meta = store.get_meta(&v1).await.unwrap();
+        assert_eq!((meta.version, meta.superseded), (1, false));
+        assert!(store.get_meta(&v2).await.unwrap().superseded);
+        assert_eq!(listed(&store).await, vec![v1]);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_ingests_keep_every_version() {
+        let store = temp_store("concurrent-ingest").await;
+        let contents: Vec<String> = (0..6).map(|i| format!("content {i}")).collect();
+        let ids: Vec<DocId> = futures::future::join_all(
+            contents
+                .iter()
+                .map(|content| store.store(content.as_bytes(), &info("docs.md"))),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<_>>()
+        .unwrap();
+
+        let history = store
+            .source_history(Scope::Guild(1), "akash", "docs.md")
+            .await
+            .unwrap()
+            .unwrap();
+        let mut versions: Vec<u32> = history.versions.iter().map(|v| v.version).collect();
+        versions.sort();
+        assert_eq!(versions, vec![1, 2, 3, 4, 5, 6]);
+        let mut recorded: Vec<DocId> = history.versions.iter().map(|v| v.doc_id.clone()).collect();
+        recorded.sort();
+        let mut expected = ids.clone();
+        expected.sort();
+        assert_eq!(recorded, expected);
+
+        // Exactly the active version stays in the topic
+        let active = history.active_version().unwrap().doc_id.clone();
+        assert_eq!(listed(&store).await, vec![active.clone()]);
+        for id in &ids {
+            let meta = store.get_meta(id).await.unwrap();
+            assert_eq!(meta.superseded, *id != active, "{id}");
+        }
+        assert!(store.key_locks.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_legacy_documents_are_adopted() {
+        let store = temp_store("legacy").await;
+        let old = store.store(b"old", &info("docs.md")).await.unwrap();
+        let newer = store.store(b"newer", &info("docs.md")).await.unwrap();
+        make_legacy(&store, &old, 100).await;
+        make_legacy(&store, &newer, 200).await;
+        let other = store.store(b"other", &info("other.md")).await.unwrap();
+
+        let latest = store.store(b"latest", &info("docs.md")).await.unwrap();
+        let history = store
+            .source_history(Scope::Guild(1), "akash", "docs.md")
+            .await
+            .unwrap()
+            .unwrap();
+        let versions: Vec<&str> = history.versions.iter().map(|v| v.doc_id.as_str()).collect();
+        assert_eq!(
+            versions,
+            vec![old.as_str(), newer.as_str(), latest.as_str()]
+        );
+        assert_eq!(history.active, 3);
+        assert!(store.get_meta(&old).await.unwrap().superseded);
+        assert_eq!(store.get_meta(&newer).await.unwrap().version, 2);
+        // Other sources of the topic are left alone
+        let mut expected = vec![latest, other];
+        expected.sort();
+        assert_eq!(listed(&store).await, expected);
+    }
+
+    #[tokio::test]
+    async fn test_config_and_audit() {
+        let store = temp_store("config").await;
+        let scope = Scope::Guild(1);
+        let change = |summary: String, timestamp| ConfigChange {
+            key: "rlm".to_string(),
+            scope,
+            user_id: 7,
+            user_name: "ann".to_string(),
+            summary,
+            timestamp,
+        };
+        for i in 1..=5u32 {
+            store
+                .put_config(scope, "rlm", &i, &change(format!("set {i}"), i as i64))
+                .await
+                .unwrap();
+        }
+        let loaded = store.load_config::<u32>(scope, "rlm").await.unwrap();
+        assert_eq!(loaded, Some(5));
+        let other = store.load_config::<u32>(Scope::Guild(2), "rlm").await;
+        assert_eq!(other.unwrap(), None);
+
+        let summaries = |changes: Vec<ConfigChange>| -> Vec<String> {
+            changes.into_iter().map(|c| c.summary).collect()
+        };
+        let audit = store.config_audit(scope, 3).await.unwrap();
+        assert_eq!(summaries(audit), vec!["set 5", "set 4", "set 3"]);
+        assert!(store
+            .config_audit(Scope::Guild(2), 3)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Entries keyed oldest first still come last
+        let mut delta = StateDelta::new(store.storage.latest_snapshot());
+        let legacy = format!("{}{:020}", config_audit_prefix(scope), 1_000);
+        let value = serde_json::to_vec(&change("legacy".to_string(), 0)).unwrap();
+        delta.put_raw(legacy, value);
+        store.storage.commit(delta).await.unwrap();
+        let audit = summaries(store.config_audit(scope, 10).await.unwrap());
+        assert_eq!(audit.len(), 6);
+        assert_eq!(audit[0], "set 5");
+        assert_eq!(audit[5], "legacy");
+        assert_eq!(store.config_audit(scope, 5).await.unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_thread_answers() {
+        let store = temp_store("threads").await;
+        let scope = Scope::Guild(1);
+        let urls = vec!["https://example.com".to_string()];
+
+        // Concurrent answers in one thread each keep their question
+        let questions: Vec<String> = (0..8).map(|i| format!("question {i}")).collect();
+        futures::future::join_all(questions.iter().map(|question| {
+            store.record_thread_answer(scope, "akash", 10, question, "answer", &urls)
+        }))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<()>>>()
+        .unwrap();
+        let threads = store.list_threads(&[scope], "akash").await.unwrap();
+        assert_eq!(threads.len(), 1);
+        let mut recorded = threads[0].questions.clone();
+        recorded.sort();
+        assert_eq!(recorded, questions);
+        assert_eq!(threads[0].cited_urls, urls);
+        assert!(store.key_locks.0.lock().unwrap().is_empty());
+
+        // A topic isn't listed under another that prefixes it
+        store
+            .record_thread_answer(scope, "akash/v2", 11, "q", "a", &[])
+            .await
+            .unwrap();
+        store
+            .record_thread_answer(scope, "akash%2Fv2", 12, "q", "a", &[])
+            .await
+            .unwrap();
+        let ids = |threads: Vec<AnsweredThread>| -> Vec<u64> {
+            threads.into_iter().map(|t| t.thread_id).collect()
+        };
+        let akash = store.list_threads(&[scope], "akash").await.unwrap();
+        assert_eq!(ids(akash), vec![10]);
+        let v2 = store.list_threads(&[scope], "akash/v2").await.unwrap();
+        assert_eq!(ids(v2), vec![11]);
+        let escaped = store.list_threads(&[scope], "akash%2Fv2").await.unwrap();
+        assert_eq!(ids(escaped), vec![12]);
+        let other = store
+            .list_threads(&[Scope::Guild(2)], "akash")
+            .await
+            .unwrap();
+        assert!(other.is_empty());
+    }
+}
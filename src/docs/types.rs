@@ -1,8 +1,62 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
-/// Content-addressed document ID (blake3 hex hash).
+/// Content-addressed document ID (blake3 hex hash, keyed by guild and topic).
 pub type DocId = String;
 
+/// Namespace for topics, documents, Q/A records and settings: one per guild,
+/// plus a global library that guilds can opt into.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Scope {
+    #[default]
+    Global,
+    Guild(u64),
+}
+
+impl Scope {
+    /// Key segment, e.g. "global" or "g123456789".
+    pub fn key(&self) -> String {
+        match self {
+            Scope::Global => "global".to_string(),
+            Scope::Guild(id) => format!("g{}", id),
+        }
+    }
+}
+
+/// The scopes visible from one guild: its own, plus the global library if enabled.
+#[derive(Debug, Clone, Copy)]
+pub struct ScopeView {
+    pub guild: u64,
+    pub include_global: bool,
+}
+
+impl ScopeView {
+    /// The guild's own scope — where its documents and Q/A records live.
+    pub fn home(&self) -> Scope {
+        Scope::Guild(self.guild)
+    }
+
+    pub fn scopes(&self) -> Vec<Scope> {
+        let mut scopes = vec![self.home()];
+        if self.include_global {
+            scopes.push(Scope::Global);
+        }
+        scopes
+    }
+}
+
+/// Descriptive fields for a document being stored.
+#[derive(Debug, Clone, Copy)]
+pub struct DocInfo<'a> {
+    pub scope: Scope,
+    pub name: &'a str,
+    pub source: &'a str,
+    pub label: &'a str,
+    pub url_context: Option<&'a str>,
+    pub commit: Option<&'a str>,
+}
+
 /// Document metadata stored alongside content in cnidarium.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DocMeta {
@@ -12,18 +66,102 @@ pub struct DocMeta {
     pub source: String,
     /// Topic label for /edgar ask scoping
     pub label: String,
+    /// Owning guild, or the global library (also where pre-scoping docs live)
+    #[serde(default)]
+    pub scope: Scope,
     pub size: usize,
     pub ingested_at: i64,
     /// Admin-provided URL attribution context for citation routing.
     /// e.g. "files in docs/ map to https://akash.network/docs"
     #[serde(default)]
     pub url_context: Option<String>,
+    /// Resolved git commit SHA for repository sources.
+    #[serde(default)]
+    pub commit: Option<String>,
+    /// Version number within this doc's (label, source) history; 0 for docs
+    /// ingested before versioning.
+    #[serde(default)]
+    pub version: u32,
+    /// Replaced by a newer version: kept for rollback but no longer in its topic.
+    #[serde(default)]
+    pub superseded: bool,
+}
+
+/// One ingest of a source under a topic.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceVersion {
+    pub version: u32,
+    pub doc_id: DocId,
+    pub ingested_at: i64,
+    #[serde(default)]
+    pub commit: Option<String>,
+}
+
+/// Version history of one source (e.g. "github:owner/repo") within a topic.
+/// Only the active version is in the topic's label index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceHistory {
+    pub source: String,
+    #[serde(default)]
+    pub scope: Scope,
+    pub label: String,
+    pub active: u32,
+    /// Oldest first
+    pub versions: Vec<SourceVersion>,
+}
+
+impl SourceHistory {
+    pub fn get(&self, version: u32) -> Option<&SourceVersion> {
+        self.versions.iter().find(|v| v.version == version)
+    }
+
+    pub fn active_version(&self) -> Option<&SourceVersion> {
+        self.get(self.active)
+    }
+
+    pub fn next_version(&self) -> u32 {
+        self.versions.last().map(|v| v.version + 1).unwrap_or(1)
+    }
+}
+
+/// Manifest entry for one file of a multi-file document (e.g. a GitHub repo).
+/// The file body is stored under its own key; `offset`/`len` locate the file's
+/// section (header + body) within the combined document text.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// Repo-relative path, e.g. "src/main.rs"
+    pub path: String,
+    /// File body size in bytes
+    pub size: usize,
+    /// Language guessed from the file extension, e.g. "rust"
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Char offset of the `=== path ===` header in the combined text
+    pub offset: usize,
+    /// Section length in chars (header + body)
+    pub len: usize,
+}
+
+/// Audit entry for a runtime configuration change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigChange {
+    /// Config key, e.g. "rlm" or "admin_roles"
+    pub key: String,
+    #[serde(default)]
+    pub scope: Scope,
+    pub user_id: u64,
+    pub user_name: String,
+    /// Human-readable summary, e.g. "`max_iterations` 15 -> 20"
+    pub summary: String,
+    pub timestamp: i64,
 }
 
 /// A stored Q/A record for dataset curation.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QaRecord {
     pub id: String,
+    #[serde(default)]
+    pub scope: Scope,
     pub topic: String,
     pub question: String,
     pub answer: String,
@@ -32,6 +170,127 @@ pub struct QaRecord {
     pub evidence: Vec<String>,
     pub iterations: u32,
     pub timestamp: i64,
+    /// Discord user who asked (0 for records from before usage tracking)
+    #[serde(default)]
+    pub user_id: u64,
+    /// LLM usage by phase ("decompose", "iteration", "llm_query", ...)
+    #[serde(default)]
+    pub usage: BTreeMap<String, PhaseUsage>,
+    /// Wall-clock time the query took
+    #[serde(default)]
+    pub duration_ms: u64,
+    /// Whether the query was answered; cancelled and failed queries are kept
+    /// for their usage only
+    #[serde(default)]
+    pub outcome: QueryOutcome,
+}
+
+/// How a query ended.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryOutcome {
+    #[default]
+    Answered,
+    Cancelled,
+    Failed,
+}
+
+impl QaRecord {
+    /// A record's ID. One record per query, so repeated questions are each
+    /// accounted for.
+    pub fn new_id(topic: &str, question: &str, timestamp: i64) -> String {
+        blake3::hash(format!("{}{}{}", topic, question, timestamp).as_bytes())
+            .to_hex()
+            .to_string()
+    }
+
+    /// A record of a query that ended without an answer, kept for its usage.
+    pub fn unanswered(
+        scope: Scope,
+        topic: &str,
+        question: &str,
+        user_id: u64,
+        outcome: QueryOutcome,
+        usage: BTreeMap<String, PhaseUsage>,
+        duration_ms: u64,
+    ) -> QaRecord {
+        let timestamp = chrono::Utc::now().timestamp();
+        QaRecord {
+            id: QaRecord::new_id(topic, question, timestamp),
+            scope,
+            topic: topic.to_string(),
+            question: question.to_string(),
+            answer: String::new(),
+            cited_urls: vec![],
+            doc_ids: vec![],
+            evidence: vec![],
+            iterations: 0,
+            timestamp,
+            user_id,
+            usage,
+            duration_ms,
+            outcome,
+        }
+    }
+
+    /// LLM usage summed over every phase.
+    pub fn total_usage(&self) -> PhaseUsage {
+        PhaseUsage::sum(self.usage.values())
+    }
+}
+
+/// LLM calls of one query phase, summed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseUsage {
+    pub calls: u32,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Time spent waiting on the calls
+    pub latency_ms: u64,
+}
+
+impl PhaseUsage {
+    pub fn sum<'a>(phases: impl IntoIterator<Item = &'a PhaseUsage>) -> PhaseUsage {
+        let mut total = PhaseUsage::default();
+        for usage in phases {
+            total.add(usage);
+        }
+        total
+    }
+
+    pub fn add(&mut self, other: &PhaseUsage) {
+        self.calls += other.calls;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.latency_ms += other.latency_ms;
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    /// Completion tokens generated per second of call latency.
+    pub fn tokens_per_sec(&self) -> f64 {
+        if self.latency_ms == 0 {
+            return 0.0;
+        }
+        self.completion_tokens as f64 * 1000.0 / self.latency_ms as f64
+    }
+}
+
+/// A Discord thread Edgar answered questions in, indexed per topic so later
+/// questions can draw on earlier answers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnsweredThread {
+    pub thread_id: u64,
+    pub scope: Scope,
+    pub topic: String,
+    /// Questions answered in the thread, oldest first
+    pub questions: Vec<String>,
+    /// The most recent answer
+    pub answer: String,
+    pub cited_urls: Vec<String>,
+    pub updated_at: i64,
 }
 
 /// A search result excerpt from a document.
@@ -40,5 +299,12 @@ pub struct DocExcerpt {
     pub doc_id: DocId,
     pub offset: usize,
     pub content: String,
+    /// Distinct query terms found in the matching passage
     pub match_count: usize,
+    /// BM25 relevance score (higher is better)
+    #[serde(default)]
+    pub score: f32,
+    /// File containing the excerpt (multi-file documents only)
+    #[serde(default)]
+    pub file: Option<String>,
 }
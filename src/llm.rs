@@ -1,29 +1,360 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
+use crate::rlm::usage::Phase;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: String,
     pub content: String,
+    /// Calls the assistant made (native tool calling)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// The call a `tool` message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl Message {
+    fn new(role: &str, content: impl Into<String>) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new("system", content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new("user", content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new("assistant", content)
+    }
+
+    /// An assistant turn that made `call`.
+    pub fn tool_call(content: impl Into<String>, call: ToolCall) -> Self {
+        Self {
+            tool_calls: vec![call],
+            ..Self::new("assistant", content)
+        }
+    }
+
+    /// The result of tool call `call_id`.
+    pub fn tool(call_id: &str, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(call_id.to_string()),
+            ..Self::new("tool", content)
+        }
+    }
+}
+
+/// A function the model can call, sent in the request's `tools` list.
+#[derive(Debug, Clone)]
+pub struct ToolSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// JSON Schema of the arguments object
+    pub parameters: serde_json::Value,
+}
+
+impl ToolSpec {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "function",
+            "function": {
+                "name": self.name,
+                "description": self.description,
+                "parameters": self.parameters,
+            },
+        })
+    }
+}
+
+/// A function call requested by the model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// Arguments as a JSON object string
+    pub arguments: String,
+}
+
+impl ToolCall {
+    pub fn new(id: impl Into<String>, name: &str, arguments: &serde_json::Value) -> Self {
+        Self {
+            id: id.into(),
+            kind: "function".to_string(),
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments: arguments.to_string(),
+            },
+        }
+    }
+
+    /// Parse one entry of a response's `tool_calls`. Some servers send the
+    /// arguments as an object rather than a string, or leave out the ID.
+    fn from_json(index: usize, value: &serde_json::Value) -> Option<Self> {
+        let function = &value["function"];
+        let name = function["name"].as_str()?.to_string();
+        let arguments = match &function["arguments"] {
+            serde_json::Value::String(s) => s.clone(),
+            serde_json::Value::Null => "{}".to_string(),
+            other => other.to_string(),
+        };
+        let id = value["id"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("call_{}", index));
+        Some(Self {
+            id,
+            kind: "function".to_string(),
+            function: FunctionCall { name, arguments },
+        })
+    }
+}
+
+/// Completion tokens requested per chat call when `LLM_MAX_TOKENS` is unset.
+const DEFAULT_MAX_TOKENS: usize = 2048;
+/// Sampling temperature when `LLM_TEMPERATURE` is unset.
+const DEFAULT_TEMPERATURE: f32 = 0.3;
+/// Context window assumed when `LLM_CONTEXT_TOKENS` is unset.
+const DEFAULT_CONTEXT_TOKENS: usize = 32_768;
+/// Chat requests in flight at once when `LLM_MAX_CONCURRENT` is unset.
+const DEFAULT_MAX_CONCURRENT: usize = 8;
+
+/// Model, sampling and endpoint of one phase's chat calls.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub model: String,
+    pub temperature: f32,
+    /// Completion tokens requested
+    pub max_tokens: usize,
+    /// OpenAI-compatible base URL, in any form `LLM_BASE_URL` accepts
+    pub base_url: String,
+    api_key: Option<String>,
+}
+
+impl Default for Route {
+    fn default() -> Self {
+        Self {
+            model: "qwen/qwen3-8b".to_string(),
+            temperature: DEFAULT_TEMPERATURE,
+            max_tokens: DEFAULT_MAX_TOKENS,
+            base_url: "http://localhost:1234/v1".to_string(),
+            api_key: None,
+        }
+    }
+}
+
+/// Settings a routes file entry or env prefix changes; the rest are inherited.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RouteOverride {
+    model: Option<String>,
+    temperature: Option<f32>,
+    max_tokens: Option<usize>,
+    base_url: Option<String>,
+    api_key: Option<String>,
+}
+
+impl RouteOverride {
+    /// `<prefix>_MODEL`, `_TEMPERATURE`, `_MAX_TOKENS`, `_BASE_URL` and
+    /// `_API_KEY`. Empty or invalid values are ignored.
+    fn from_env(prefix: &str) -> Self {
+        let var = |name: &str| {
+            dotenv::var(format!("{}_{}", prefix, name))
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        Self {
+            model: var("MODEL"),
+            temperature: var("TEMPERATURE").and_then(|v| v.parse().ok()),
+            max_tokens: var("MAX_TOKENS").and_then(|v| v.parse().ok()),
+            base_url: var("BASE_URL"),
+            api_key: var("API_KEY"),
+        }
+    }
+
+    fn apply(self, route: &mut Route) {
+        if let Some(model) = self.model {
+            route.model = model;
+        }
+        if let Some(temperature) = self.temperature.filter(|t| (0.0..=2.0).contains(t)) {
+            route.temperature = temperature;
+        }
+        if let Some(max_tokens) = self.max_tokens.filter(|n| *n > 0) {
+            route.max_tokens = max_tokens;
+        }
+        if let Some(base_url) = self.base_url {
+            // A key belongs to its endpoint: don't send the inherited one elsewhere
+            route.base_url = base_url;
+            route.api_key = None;
+        }
+        if let Some(api_key) = self.api_key {
+            route.api_key = Some(api_key);
+        }
+    }
+}
+
+/// The default route and each phase's route. The more specific setting wins,
+/// and env beats the routes file at the same level: built-in defaults, then
+/// the file's `default` entry, `LLM_*` (plus `LLM_SUB_MODEL` for
+/// `llm_query`), the file's entry for the phase, and `LLM_ROUTE_<PHASE>_*`.
+fn build_routes(
+    mut file: BTreeMap<String, RouteOverride>,
+    env: impl Fn(&str) -> RouteOverride,
+    sub_model: Option<String>,
+) -> Result<(Route, Vec<(Phase, Route)>)> {
+    let mut base = Route::default();
+    file.remove("default").unwrap_or_default().apply(&mut base);
+    env("LLM").apply(&mut base);
+    if let Some(unknown) = file.keys().find(|name| Phase::parse(name).is_none()) {
+        let names: Vec<&str> = Phase::ALL.iter().map(Phase::as_str).collect();
+        anyhow::bail!(
+            "Unknown phase '{}' in LLM_ROUTES_FILE (expected default, {})",
+            unknown,
+            names.join(", ")
+        );
+    }
+
+    let routes = Phase::ALL
+        .into_iter()
+        .map(|phase| {
+            let mut route = base.clone();
+            if phase == Phase::LlmQuery {
+                if let Some(model) = &sub_model {
+                    route.model = model.clone();
+                }
+            }
+            if let Some(entry) = file.remove(phase.as_str()) {
+                entry.apply(&mut route);
+            }
+            env(&format!("LLM_ROUTE_{}", phase.as_str().to_uppercase())).apply(&mut route);
+            (phase, route)
+        })
+        .collect();
+    Ok((base, routes))
+}
+
+/// Entries of the JSON routes file at `path`, keyed by phase name.
+fn read_routes_file(path: &str) -> Result<BTreeMap<String, RouteOverride>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read LLM_ROUTES_FILE {}", path))?;
+    serde_json::from_str(&text).with_context(|| format!("Failed to parse LLM_ROUTES_FILE {}", path))
+}
+
+/// Token counts and wall-clock time of one completion. Token counts are 0
+/// when the server doesn't report usage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub latency_ms: u64,
+}
+
+impl Usage {
+    /// Completion tokens generated per second.
+    pub fn tokens_per_sec(&self) -> f64 {
+        if self.latency_ms == 0 {
+            return 0.0;
+        }
+        self.completion_tokens as f64 * 1000.0 / self.latency_ms as f64
+    }
+}
+
+/// A chat completion's text, tool calls and what it cost.
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub content: String,
+    /// Empty unless tools were offered and the model called them
+    pub tool_calls: Vec<ToolCall>,
+    pub usage: Usage,
 }
 
 pub struct LlmClient {
     client: reqwest::Client,
+    /// Endpoint and key of the default route, also used for embeddings
     base_url: String,
-    model: String,
-    sub_model: String,
     api_key: Option<String>,
+    /// Model, sampling and endpoint of each phase's calls
+    routes: Vec<(Phase, Route)>,
+    /// Context window of the exploration model, in tokens (prompt plus
+    /// completion)
+    context_tokens: usize,
+    /// Caps chat requests in flight across every query (`LLM_MAX_CONCURRENT`)
+    permits: tokio::sync::Semaphore,
+    /// Models that get native tool calling (`LLM_TOOL_CALLING_MODELS` globs)
+    tool_calling_models: Vec<glob::Pattern>,
+    /// Model for the `/embeddings` endpoint. Semantic search is disabled when unset.
+    embedding_model: Option<String>,
+    /// Separate base URL for embeddings (defaults to `base_url`).
+    embedding_base_url: Option<String>,
+    /// Key for `embedding_base_url`. The default route's key is only sent to
+    /// its own endpoint.
+    embedding_api_key: Option<String>,
 }
 
 impl LlmClient {
     pub fn from_env() -> Result<Self> {
-        let base_url = dotenv::var("LLM_BASE_URL")
-            .unwrap_or_else(|_| "http://localhost:1234/v1".to_string());
-        let model =
-            dotenv::var("LLM_MODEL").unwrap_or_else(|_| "qwen/qwen3-8b".to_string());
-        let sub_model =
-            dotenv::var("LLM_SUB_MODEL").unwrap_or_else(|_| model.clone());
-        let api_key = dotenv::var("LLM_API_KEY").ok().filter(|k| !k.is_empty());
+        let file = match dotenv::var("LLM_ROUTES_FILE") {
+            Ok(path) if !path.is_empty() => read_routes_file(&path)?,
+            _ => BTreeMap::new(),
+        };
+        let sub_model = dotenv::var("LLM_SUB_MODEL").ok().filter(|m| !m.is_empty());
+        let (default_route, routes) = build_routes(file, RouteOverride::from_env, sub_model)?;
+        let context_tokens = dotenv::var("LLM_CONTEXT_TOKENS")
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_CONTEXT_TOKENS);
+        let max_concurrent = dotenv::var("LLM_MAX_CONCURRENT")
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_MAX_CONCURRENT);
+        let tool_calling_models = dotenv::var("LLM_TOOL_CALLING_MODELS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .filter_map(|p| match glob::Pattern::new(p) {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    tracing::warn!(
+                        pattern = p,
+                        error = %e,
+                        "Ignoring invalid LLM_TOOL_CALLING_MODELS entry"
+                    );
+                    None
+                }
+            })
+            .collect();
+        let embedding_model = dotenv::var("LLM_EMBEDDING_MODEL")
+            .ok()
+            .filter(|m| !m.is_empty());
+        let embedding_base_url = dotenv::var("LLM_EMBEDDING_BASE_URL")
+            .ok()
+            .filter(|u| !u.is_empty());
+        let embedding_api_key = dotenv::var("LLM_EMBEDDING_API_KEY")
+            .ok()
+            .filter(|k| !k.is_empty());
 
         let client = reqwest::Client::builder()
             .timeout(std::time::Duration::from_secs(120))
@@ -32,16 +363,54 @@ impl LlmClient {
 
         Ok(Self {
             client,
-            base_url,
-            model,
-            sub_model,
-            api_key,
+            base_url: default_route.base_url,
+            api_key: default_route.api_key,
+            routes,
+            context_tokens,
+            permits: tokio::sync::Semaphore::new(max_concurrent),
+            tool_calling_models,
+            embedding_model,
+            embedding_base_url,
+            embedding_api_key,
         })
     }
 
-    /// Resolve the chat completions endpoint from the base URL.
-    fn endpoint(&self) -> String {
-        let base = self.base_url.trim_end_matches('/');
+    /// Context window of the exploration model, in tokens.
+    pub fn context_tokens(&self) -> usize {
+        self.context_tokens
+    }
+
+    /// Model, sampling and endpoint of `phase`'s calls.
+    pub fn route(&self, phase: Phase) -> &Route {
+        self.routes
+            .iter()
+            .find(|(p, _)| *p == phase)
+            .map(|(_, route)| route)
+            .expect("every phase has a route")
+    }
+
+    /// Every phase's route, in query order.
+    pub fn routes(&self) -> &[(Phase, Route)] {
+        &self.routes
+    }
+
+    /// Whether `phase`'s model is driven through native tool calls rather
+    /// than code fences.
+    pub fn tool_calling(&self, phase: Phase) -> bool {
+        let model = &self.route(phase).model;
+        self.tool_calling_models
+            .iter()
+            .any(|pattern| pattern.matches(model))
+    }
+
+    /// The configured embedding model, if semantic search is enabled.
+    pub fn embedding_model(&self) -> Option<&str> {
+        self.embedding_model.as_deref()
+    }
+
+    /// Resolve the chat completions endpoint from a base URL.
+    fn endpoint(base_url: &str) -> String {
+        let base = base_url.trim_end_matches('/');
         if base.ends_with("/chat/completions") {
             base.to_string()
         } else if base.ends_with("/v1") {
@@ -51,46 +420,473 @@ impl LlmClient {
         }
     }
 
-    /// Non-streaming chat completion.
-    pub async fn chat(
-        &self,
-        messages: &[Message],
-        model_override: Option<&str>,
-    ) -> Result<String> {
-        let model = model_override.unwrap_or(&self.model);
+    /// Resolve the embeddings endpoint from the embedding (or chat) base URL.
+    fn embeddings_endpoint(&self) -> String {
+        let base = self
+            .embedding_base_url
+            .as_deref()
+            .unwrap_or(&self.base_url)
+            .trim_end_matches('/');
+        if base.ends_with("/embeddings") {
+            base.to_string()
+        } else if let Some(root) = base.strip_suffix("/chat/completions") {
+            format!("{}/embeddings", root)
+        } else if base.ends_with("/v1") {
+            format!("{}/embeddings", base)
+        } else {
+            format!("{}/v1/embeddings", base)
+        }
+    }
+
+    /// Key to send with embedding requests: `LLM_EMBEDDING_API_KEY`, or the
+    /// default route's key when embeddings go to the default endpoint.
+    fn embeddings_api_key(&self) -> Option<&str> {
+        match (&self.embedding_api_key, &self.embedding_base_url) {
+            (Some(key), _) => Some(key.as_str()),
+            // A key belongs to its endpoint: don't send the default one elsewhere
+            (None, Some(_)) => None,
+            (None, None) => self.api_key.as_deref(),
+        }
+    }
+
+    /// Embed a batch of texts via the OpenAI-compatible `/embeddings` endpoint.
+    /// Returns one vector per input, in input order.
+    pub async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        let model = self
+            .embedding_model
+            .as_deref()
+            .context("No embedding model configured (set LLM_EMBEDDING_MODEL)")?;
+        if inputs.is_empty() {
+            return Ok(Vec::new());
+        }
         let body = serde_json::json!({
             "model": model,
+            "input": inputs,
+        });
+
+        let mut req = self.client.post(self.embeddings_endpoint()).json(&body);
+        if let Some(key) = self.embeddings_api_key() {
+            req = req.header("Authorization", format!("Bearer {}", key));
+        }
+
+        let resp = req.send().await.context("Embedding request failed")?;
+        let text = resp
+            .text()
+            .await
+            .context("Failed to read embedding response")?;
+        let json: serde_json::Value =
+            serde_json::from_str(&text).context("Failed to parse embedding JSON")?;
+
+        let data = json["data"]
+            .as_array()
+            .with_context(|| format!("Embedding response has no data: {}", text))?;
+        let mut indexed: Vec<(usize, Vec<f32>)> = data
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let index = item["index"].as_u64().map(|n| n as usize).unwrap_or(i);
+                let vector = item["embedding"]
+                    .as_array()
+                    .map(|v| v.iter().filter_map(|x| x.as_f64()).map(|x| x as f32).collect())
+                    .unwrap_or_default();
+                (index, vector)
+            })
+            .collect();
+        indexed.sort_by_key(|(i, _)| *i);
+
+        if indexed.len() != inputs.len() {
+            anyhow::bail!(
+                "Embedding endpoint returned {} vectors for {} inputs",
+                indexed.len(),
+                inputs.len()
+            );
+        }
+        Ok(indexed.into_iter().map(|(_, v)| v).collect())
+    }
+
+    /// Non-streaming chat completion on `phase`'s route, with the call's
+    /// token usage and latency.
+    pub async fn chat(&self, messages: &[Message], phase: Phase) -> Result<Completion> {
+        self.chat_with_tools(messages, &[], phase).await
+    }
+
+    /// Chat completion offering `tools` (OpenAI `tools`/`tool_calls`
+    /// protocol); the model may answer with text, tool calls, or both.
+    pub async fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolSpec],
+        phase: Phase,
+    ) -> Result<Completion> {
+        let route = self.route(phase);
+        let mut body = serde_json::json!({
+            "model": route.model,
             "messages": messages,
-            "temperature": 0.3,
-            "max_tokens": 2048,
+            "temperature": route.temperature,
+            "max_tokens": route.max_tokens,
         });
+        if !tools.is_empty() {
+            body["tools"] = tools.iter().map(ToolSpec::to_json).collect();
+            body["tool_choice"] = "auto".into();
+        }
 
-        let mut req = self.client.post(self.endpoint()).json(&body);
-        if let Some(key) = &self.api_key {
+        let mut req = self
+            .client
+            .post(Self::endpoint(&route.base_url))
+            .json(&body);
+        if let Some(key) = &route.api_key {
             req = req.header("Authorization", format!("Bearer {}", key));
         }
 
+        // Queueing for a permit doesn't count toward the call's latency
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .context("LLM request limiter closed")?;
+        let started = Instant::now();
         let resp = req.send().await.context("LLM request failed")?;
         let text = resp.text().await.context("Failed to read LLM response")?;
+        let latency_ms = started.elapsed().as_millis() as u64;
         let json: serde_json::Value =
             serde_json::from_str(&text).context("Failed to parse LLM JSON")?;
 
         // Extract content from choices[0].message.content (handle null)
-        let content = json["choices"]
-            .get(0)
-            .and_then(|c| c["message"]["content"].as_str())
-            .unwrap_or("")
-            .to_string();
-
-        Ok(content)
-    }
-
-    /// Sub-LLM query using the sub_model.
-    pub async fn sub_query(&self, prompt: &str) -> Result<String> {
-        let messages = vec![Message {
-            role: "user".to_string(),
-            content: prompt.to_string(),
-        }];
-        self.chat(&messages, Some(&self.sub_model.clone())).await
+        let message = &json["choices"][0]["message"];
+        let content = message["content"].as_str().unwrap_or("").to_string();
+        let tool_calls = message["tool_calls"]
+            .as_array()
+            .map(|calls| {
+                calls
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, call)| ToolCall::from_json(i, call))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let usage = Usage {
+            prompt_tokens: json["usage"]["prompt_tokens"].as_u64().unwrap_or(0),
+            completion_tokens: json["usage"]["completion_tokens"].as_u64().unwrap_or(0),
+            latency_ms,
+        };
+
+        Ok(Completion {
+            content,
+            tool_calls,
+            usage,
+        })
+    }
+
+    /// Sub-LLM query on the `llm_query` route.
+    pub async fn sub_query(&self, prompt: &str) -> Result<Completion> {
+        let messages = vec![Message::user(prompt)];
+        self.chat(&messages, Phase::LlmQuery).await
+    }
+}
+
+/// A local chat-completions endpoint and a client for it, for tests here and
+/// in the modules that call the LLM.
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use serde_json::Value;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    /// A client with every phase routed to `base_url`.
+    pub fn test_client(base_url: String) -> LlmClient {
+        let route = Route {
+            model: "test".to_string(),
+            base_url: base_url.clone(),
+            ..Route::default()
+        };
+        LlmClient {
+            client: reqwest::Client::new(),
+            base_url,
+            api_key: None,
+            routes: Phase::ALL.map(|phase| (phase, route.clone())).to_vec(),
+            context_tokens: DEFAULT_CONTEXT_TOKENS,
+            permits: tokio::sync::Semaphore::new(DEFAULT_MAX_CONCURRENT),
+            tool_calling_models: vec![glob::Pattern::new("gpt-*").unwrap()],
+            embedding_model: Some("test-embed".to_string()),
+            embedding_base_url: None,
+            embedding_api_key: None,
+        }
+    }
+
+    /// Let `client` have at most `permits` requests in flight, as
+    /// `LLM_MAX_CONCURRENT` does.
+    pub fn limit_concurrency(client: &mut LlmClient, permits: usize) {
+        client.permits = tokio::sync::Semaphore::new(permits);
+    }
+
+    /// Serve one canned HTTP response on a local port; returns the base URL.
+    pub async fn serve_once(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut sock, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 64 * 1024];
+            let _ = sock.read(&mut buf).await;
+            respond(&mut sock, body).await;
+        });
+        format!("http://{}/v1", addr)
+    }
+
+    /// Requests a mock endpoint is answering, and the most it answered at once.
+    #[derive(Default)]
+    pub struct Load {
+        current: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    impl Load {
+        pub fn peak(&self) -> usize {
+            self.peak.load(Ordering::SeqCst)
+        }
+    }
+
+    /// Serve chat completions on a local port until the test ends; returns the
+    /// base URL. Each reply's content is `reply(prompt)`, `prompt` being the
+    /// content of the request's last message.
+    pub async fn serve<F>(reply: F) -> String
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        serve_slowly(|_| Duration::ZERO, reply).await.0
+    }
+
+    /// `serve`, taking `delay(prompt)` to answer each request; also returns
+    /// the endpoint's load.
+    pub async fn serve_slowly<D, F>(delay: D, reply: F) -> (String, Arc<Load>)
+    where
+        D: Fn(&str) -> Duration + Send + Sync + 'static,
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let delay = Arc::new(delay);
+        let reply = Arc::new(reply);
+        let load = Arc::new(Load::default());
+        let served = load.clone();
+        tokio::spawn(async move {
+            while let Ok((mut sock, _)) = listener.accept().await {
+                let delay = delay.clone();
+                let reply = reply.clone();
+                let load = served.clone();
+                tokio::spawn(async move {
+                    let request = read_request(&mut sock).await;
+                    let prompt = request["messages"]
+                        .as_array()
+                        .and_then(|messages| messages.last())
+                        .and_then(|message| message["content"].as_str())
+                        .unwrap_or_default();
+
+                    let current = load.current.fetch_add(1, Ordering::SeqCst) + 1;
+                    load.peak.fetch_max(current, Ordering::SeqCst);
+                    tokio::time::sleep(delay(prompt)).await;
+                    load.current.fetch_sub(1, Ordering::SeqCst);
+                    let body = serde_json::json!({
+                        "choices": [{"message": {"content": reply(prompt)}}],
+                        "usage": {"prompt_tokens": 10, "completion_tokens": 5},
+                    });
+                    respond(&mut sock, &body.to_string()).await;
+                });
+            }
+        });
+        (format!("http://{}/v1", addr), load)
+    }
+
+    /// The JSON body of the request on `sock`, read up to its content-length.
+    async fn read_request(sock: &mut TcpStream) -> Value {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 16 * 1024];
+        loop {
+            match sock.read(&mut chunk).await {
+                Ok(0) | Err(_) => return Value::Null,
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            }
+            let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+                continue;
+            };
+            let head = String::from_utf8_lossy(&buf[..end]).to_lowercase();
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(0);
+            let body = end + 4;
+            if buf.len() >= body + length {
+                return serde_json::from_slice(&buf[body..body + length]).unwrap_or_default();
+            }
+        }
+    }
+
+    async fn respond(sock: &mut TcpStream, body: &str) {
+        let resp = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let _ = sock.write_all(resp.as_bytes()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::{serve_once, test_client};
+    use super::*;
+
+    #[test]
+    fn test_embeddings_endpoint() {
+        let mut client = test_client("http://localhost:1234/v1".to_string());
+        assert_eq!(client.embeddings_endpoint(), "http://localhost:1234/v1/embeddings");
+        client.base_url = "http://host/v1/chat/completions".to_string();
+        assert_eq!(client.embeddings_endpoint(), "http://host/v1/embeddings");
+        client.embedding_base_url = Some("http://embed:8080".to_string());
+        assert_eq!(client.embeddings_endpoint(), "http://embed:8080/v1/embeddings");
+    }
+
+    #[test]
+    fn test_embeddings_api_key() {
+        let mut client = test_client("http://host/v1".to_string());
+        client.api_key = Some("chat-key".to_string());
+        assert_eq!(client.embeddings_api_key(), Some("chat-key"));
+        // Another host never gets the chat provider's key
+        client.embedding_base_url = Some("http://embed:8080".to_string());
+        assert_eq!(client.embeddings_api_key(), None);
+        client.embedding_api_key = Some("embed-key".to_string());
+        assert_eq!(client.embeddings_api_key(), Some("embed-key"));
+    }
+
+    #[tokio::test]
+    async fn test_embed_orders_by_index() {
+        let base = serve_once(
+            r#"{"data":[{"index":1,"embedding":[0.0,1.0]},{"index":0,"embedding":[1.0,0.0]}]}"#,
+        )
+        .await;
+        let client = test_client(base);
+        let vectors = client
+            .embed(&["a".to_string(), "b".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(vectors, vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+    }
+
+    #[tokio::test]
+    async fn test_chat_reports_usage() {
+        let base = serve_once(
+            r#"{"choices":[{"message":{"content":"hi"}}],"usage":{"prompt_tokens":12,"completion_tokens":3,"total_tokens":15}}"#,
+        )
+        .await;
+        let client = test_client(base);
+        let completion = client.chat(&[], Phase::Iteration).await.unwrap();
+        assert_eq!(completion.content, "hi");
+        assert_eq!(completion.usage.prompt_tokens, 12);
+        assert_eq!(completion.usage.completion_tokens, 3);
+
+        // Servers that don't report usage count as zero tokens
+        let base = serve_once(r#"{"choices":[{"message":{"content":null}}]}"#).await;
+        let completion = test_client(base).chat(&[], Phase::Iteration).await.unwrap();
+        assert_eq!(completion.content, "");
+        assert_eq!(completion.usage.prompt_tokens, 0);
+    }
+
+    #[tokio::test]
+    async fn test_chat_parses_tool_calls() {
+        let base = serve_once(
+            r#"{"choices":[{"message":{"content":null,"tool_calls":[
+                {"id":"call_a","type":"function","function":{"name":"run_python","arguments":"{\"code\": \"print(1)\"}"}},
+                {"type":"function","function":{"name":"final_answer","arguments":{"answer":"done"}}}
+            ]}}]}"#,
+        )
+        .await;
+        let completion = test_client(base).chat(&[], Phase::Iteration).await.unwrap();
+        assert_eq!(completion.content, "");
+        assert_eq!(completion.tool_calls.len(), 2);
+        assert_eq!(completion.tool_calls[0].id, "call_a");
+        assert_eq!(completion.tool_calls[0].function.arguments, r#"{"code": "print(1)"}"#);
+        assert_eq!(completion.tool_calls[1].id, "call_1");
+        assert_eq!(completion.tool_calls[1].function.arguments, r#"{"answer":"done"}"#);
+    }
+
+    #[test]
+    fn test_tool_messages_serialize() {
+        let plain = serde_json::to_value(Message::user("hi")).unwrap();
+        assert_eq!(plain, serde_json::json!({"role": "user", "content": "hi"}));
+        let reply = serde_json::to_value(Message::tool("call_a", "ok")).unwrap();
+        assert_eq!(reply["tool_call_id"], "call_a");
+
+        let mut client = test_client("http://localhost:1234/v1".to_string());
+        let exploring = |client: &mut LlmClient, model: &str| {
+            for (phase, route) in &mut client.routes {
+                if *phase == Phase::Iteration {
+                    route.model = model.to_string();
+                }
+            }
+        };
+        exploring(&mut client, "gpt-4o");
+        assert!(client.tool_calling(Phase::Iteration));
+        // Each phase goes by its own model
+        assert!(!client.tool_calling(Phase::Synthesis));
+        exploring(&mut client, "qwen/qwen3-8b");
+        assert!(!client.tool_calling(Phase::Iteration));
+    }
+
+    #[test]
+    fn test_build_routes_precedence() {
+        let file: BTreeMap<String, RouteOverride> = serde_json::from_str(
+            r#"{
+                "default": {"model": "file-default", "temperature": 0.5},
+                "decompose": {"model": "tiny", "max_tokens": 256, "base_url": "http://cpu:8000/v1", "api_key": "cpu-key"},
+                "synthesis": {"model": "big", "max_tokens": 8192, "base_url": "http://gpu:8000/v1"}
+            }"#,
+        )
+        .unwrap();
+        let env = |prefix: &str| match prefix {
+            "LLM" => RouteOverride {
+                model: Some("env-default".to_string()),
+                api_key: Some("key".to_string()),
+                ..RouteOverride::default()
+            },
+            "LLM_ROUTE_SYNTHESIS" => RouteOverride {
+                temperature: Some(0.1),
+                max_tokens: Some(0),
+                ..RouteOverride::default()
+            },
+            _ => RouteOverride::default(),
+        };
+        let (default_route, routes) = build_routes(file, env, Some("sub".to_string())).unwrap();
+        let route = |phase| &routes.iter().find(|(p, _)| *p == phase).unwrap().1;
+
+        // LLM_* beats the file's default entry; unset fields keep the file's
+        assert_eq!(default_route.model, "env-default");
+        assert_eq!(default_route.temperature, 0.5);
+        assert_eq!(default_route.max_tokens, DEFAULT_MAX_TOKENS);
+        assert_eq!(route(Phase::Iteration), &default_route);
+        assert_eq!(route(Phase::LlmQuery).model, "sub");
+        assert_eq!(route(Phase::Decompose).model, "tiny");
+        assert_eq!(route(Phase::Decompose).max_tokens, 256);
+        assert_eq!(route(Phase::Decompose).api_key.as_deref(), Some("cpu-key"));
+
+        // Per-phase env beats the file; invalid values are ignored
+        let synthesis = route(Phase::Synthesis);
+        assert_eq!(synthesis.model, "big");
+        assert_eq!(synthesis.temperature, 0.1);
+        assert_eq!(synthesis.max_tokens, 8192);
+        assert_eq!(synthesis.base_url, "http://gpu:8000/v1");
+        // Another endpoint doesn't get the default key
+        assert_eq!(synthesis.api_key, None);
+        assert_eq!(default_route.api_key.as_deref(), Some("key"));
+
+        let file = serde_json::from_str(r#"{"synthesise": {"model": "big"}}"#).unwrap();
+        let err = build_routes(file, |_| RouteOverride::default(), None).unwrap_err();
+        assert!(err.to_string().contains("Unknown phase 'synthesise'"));
     }
 }
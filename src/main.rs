@@ -4,7 +4,7 @@ mod llm;
 mod rlm;
 mod state;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use poise::serenity_prelude as serenity;
@@ -14,8 +14,12 @@ use tracing::{error, info, Level};
 
 use docs::DocumentStore;
 use llm::LlmClient;
+use rlm::conversation::Conversations;
+use rlm::exec::ExecLimits;
+use rlm::jobs::Jobs;
+use rlm::scheduler::{Scheduler, SchedulerConfig};
 use rlm::RlmEngine;
-use state::{AppState, RlmConfig};
+use state::AppState;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -39,6 +43,16 @@ async fn main() -> anyhow::Result<()> {
     // Init LLM client
     let llm_client = Arc::new(LlmClient::from_env()?);
     info!("LLM client initialized");
+    for (phase, route) in llm_client.routes() {
+        info!(
+            phase = phase.as_str(),
+            model = %route.model,
+            temperature = route.temperature,
+            max_tokens = route.max_tokens,
+            base_url = %route.base_url,
+            "LLM route"
+        );
+    }
 
     // Parse admin user IDs from env
     let admin_ids: HashSet<u64> = dotenv::var("ADMIN_USER_IDS")
@@ -50,29 +64,34 @@ async fn main() -> anyhow::Result<()> {
         info!(count = admin_ids.len(), "Admin users configured");
     }
 
-    // Parse admin role IDs from env
-    let admin_role_ids: HashSet<u64> = dotenv::var("ADMIN_ROLE_IDS")
+    // Admin roles: each guild's persisted set (managed via /edgar config roles-*)
+    // wins; ADMIN_ROLE_IDS only applies to guilds that haven't persisted one.
+    let default_admin_roles: HashSet<u64> = dotenv::var("ADMIN_ROLE_IDS")
         .unwrap_or_default()
         .split(',')
         .filter_map(|s| s.trim().parse::<u64>().ok())
         .collect();
-    if !admin_role_ids.is_empty() {
-        info!(count = admin_role_ids.len(), "Admin roles configured");
+    if !default_admin_roles.is_empty() {
+        info!(count = default_admin_roles.len(), "Default admin roles configured");
     }
-    let admin_role_ids = Arc::new(RwLock::new(admin_role_ids));
-
-    let rlm_config = Arc::new(RwLock::new(RlmConfig::default()));
 
     // Init RLM engine
-    let rlm = Arc::new(RlmEngine::new(llm_client.clone(), store.clone()));
+    let limits = ExecLimits::from_env();
+    info!(?limits, "REPL execution limits");
+    let rlm = Arc::new(RlmEngine::new(llm_client.clone(), store.clone(), limits));
+    let scheduler_config = SchedulerConfig::from_env();
+    info!(?scheduler_config, "Query scheduler limits");
 
     let app_state = AppState {
         store,
         llm: llm_client,
         rlm,
         admin_ids,
-        admin_role_ids,
-        rlm_config,
+        default_admin_roles,
+        guilds: Arc::new(RwLock::new(HashMap::new())),
+        conversations: Arc::new(Conversations::default()),
+        jobs: Arc::new(Jobs::default()),
+        scheduler: Arc::new(Scheduler::new(scheduler_config)),
     };
 
     let intents =
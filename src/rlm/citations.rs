@@ -175,11 +175,15 @@ mod tests {
             name: "test-repo".to_string(),
             source: "github:owner/repo".to_string(),
             label: "test".to_string(),
+            scope: Default::default(),
             size: 1000,
             ingested_at: 0,
             url_context: Some(
                 "https://github.com/owner/repo/blob/main/{filepath}".to_string(),
             ),
+            commit: None,
+            version: 0,
+            superseded: false,
         }];
 
         let accessed = vec![
@@ -207,11 +211,15 @@ mod tests {
             name: "test-repo".to_string(),
             source: "github:owner/repo".to_string(),
             label: "test".to_string(),
+            scope: Default::default(),
             size: 1000,
             ingested_at: 0,
             url_context: Some(
                 "https://github.com/owner/repo/blob/main/{filepath}".to_string(),
             ),
+            commit: None,
+            version: 0,
+            superseded: false,
         }];
 
         let accessed = vec![
@@ -238,11 +246,15 @@ mod tests {
             name: "test-repo".to_string(),
             source: "github:owner/repo".to_string(),
             label: "test".to_string(),
+            scope: Default::default(),
             size: 1000,
             ingested_at: 0,
             url_context: Some(
                 "https://github.com/owner/repo/blob/main/{filepath}".to_string(),
             ),
+            commit: None,
+            version: 0,
+            superseded: false,
         }];
 
         // Same file accessed twice
@@ -262,9 +274,13 @@ mod tests {
             name: "test-repo".to_string(),
             source: "github:owner/repo".to_string(),
             label: "test".to_string(),
+            scope: Default::default(),
             size: 1000,
             ingested_at: 0,
             url_context: None,
+            commit: None,
+            version: 0,
+            superseded: false,
         }];
 
         let accessed = vec![("abc123".to_string(), "src/main.rs".to_string())];
@@ -279,11 +295,15 @@ mod tests {
             name: "test-repo".to_string(),
             source: "github:owner/repo".to_string(),
             label: "test".to_string(),
+            scope: Default::default(),
             size: 1000,
             ingested_at: 0,
             url_context: Some(
                 "https://github.com/owner/repo/blob/main/{filepath}".to_string(),
             ),
+            commit: None,
+            version: 0,
+            superseded: false,
         }];
 
         // doc_id doesn't match any doc
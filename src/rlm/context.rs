@@ -0,0 +1,250 @@
+//! Keeping an exploration loop's conversation inside the model's context
+//! window: token estimates, UTF-8-safe truncation, and compaction of older
+//! turns.
+
+use tracing::{debug, warn};
+
+use crate::llm::Message;
+
+/// Header of a user message carrying REPL output.
+pub const REPL_OUTPUT_HEADER: &str = "[REPL Output]";
+
+/// Chars per token assumed by estimates. Kept low (code and markdown
+/// tokenize densely) so estimates err on the high side.
+const CHARS_PER_TOKEN: usize = 3;
+/// Tokens a chat template adds around each message.
+const MESSAGE_OVERHEAD: usize = 4;
+/// The most recent messages (the last two exchanges) are never compacted.
+const KEEP_RECENT: usize = 4;
+/// Chars of a compacted message kept as its summary.
+const SUMMARY_CHARS: usize = 300;
+
+/// Longest prefix of `text` within `max_bytes`, cut on a char boundary.
+pub fn truncate(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Tokens of one message, tool call arguments included.
+fn estimate_message(message: &Message) -> usize {
+    let arguments: usize = message
+        .tool_calls
+        .iter()
+        .map(|call| estimate_tokens(&call.function.arguments))
+        .sum();
+    estimate_tokens(&message.content) + arguments + MESSAGE_OVERHEAD
+}
+
+pub fn estimate_messages(messages: &[Message]) -> usize {
+    messages.iter().map(estimate_message).sum()
+}
+
+/// Tokens a prompt may use: the model's context window less room for the
+/// completion.
+#[derive(Debug, Clone, Copy)]
+pub struct ContextBudget {
+    pub prompt_tokens: usize,
+}
+
+impl ContextBudget {
+    pub fn new(context_tokens: usize, max_completion_tokens: usize) -> Self {
+        Self {
+            prompt_tokens: context_tokens.saturating_sub(max_completion_tokens),
+        }
+    }
+
+    /// Chars of new content that fit after `messages`, leaving `reserve`
+    /// tokens spare.
+    pub fn room_after(&self, messages: &[Message], reserve: usize) -> usize {
+        self.prompt_tokens
+            .saturating_sub(estimate_messages(messages) + reserve)
+            * CHARS_PER_TOKEN
+    }
+
+    /// Compact `messages` until they fit the budget. The system prompt (first
+    /// message), `pinned` messages and the last few exchanges are kept as
+    /// they are. Older REPL outputs are summarized first, oldest first; if
+    /// that isn't enough, other long older messages are trimmed. Returns the
+    /// number of messages compacted.
+    pub fn fit(&self, messages: &mut [Message], pinned: &[usize]) -> usize {
+        let mut total = estimate_messages(messages);
+        if total <= self.prompt_tokens {
+            return 0;
+        }
+
+        let recent = messages.len().saturating_sub(KEEP_RECENT);
+        let candidates: Vec<usize> = (1..recent).filter(|i| !pinned.contains(i)).collect();
+        let mut compacted = 0;
+        for outputs_only in [true, false] {
+            for &i in &candidates {
+                if total <= self.prompt_tokens {
+                    break;
+                }
+                let message = &mut messages[i];
+                if message.content.starts_with(REPL_OUTPUT_HEADER) != outputs_only {
+                    continue;
+                }
+                let before = estimate_message(message);
+                let mut changed = false;
+                if let Some(summary) = compact(&message.content) {
+                    message.content = summary;
+                    changed = true;
+                }
+                for call in &mut message.tool_calls {
+                    if let Some(arguments) = compact_arguments(&call.function.arguments) {
+                        call.function.arguments = arguments;
+                        changed = true;
+                    }
+                }
+                if changed {
+                    total = total - before + estimate_message(message);
+                    compacted += 1;
+                }
+            }
+        }
+
+        if total > self.prompt_tokens {
+            warn!(
+                estimated_tokens = total,
+                budget = self.prompt_tokens,
+                "Prompt still over the context budget after compaction"
+            );
+        } else if compacted > 0 {
+            debug!(
+                compacted,
+                estimated_tokens = total,
+                budget = self.prompt_tokens,
+                "Compacted older messages to fit the context window"
+            );
+        }
+        compacted
+    }
+}
+
+/// A short stand-in for a long message, or None if it is already short.
+fn compact(content: &str) -> Option<String> {
+    if content.len() <= SUMMARY_CHARS * 2 {
+        return None;
+    }
+    let chars = content.chars().count();
+    Some(match content.strip_prefix(REPL_OUTPUT_HEADER) {
+        Some(output) => format!(
+            "{} (compacted to save context; was {} chars)\n{}...\n\
+             [Re-run the code or re-read the file if you need this again — \
+             variables you assigned are still in the session]",
+            REPL_OUTPUT_HEADER,
+            chars,
+            truncate(output.trim_start(), SUMMARY_CHARS)
+        ),
+        None => format!(
+            "{}...\n[trimmed to save context; was {} chars]",
+            truncate(content, SUMMARY_CHARS),
+            chars
+        ),
+    })
+}
+
+/// Tool call arguments with long string values shortened, still valid JSON;
+/// None if nothing needed shortening.
+fn compact_arguments(arguments: &str) -> Option<String> {
+    let mut args: serde_json::Value = serde_json::from_str(arguments).ok()?;
+    let mut changed = false;
+    for value in args.as_object_mut()?.values_mut() {
+        let summary = value.as_str().and_then(compact);
+        if let Some(summary) = summary {
+            *value = summary.into();
+            changed = true;
+        }
+    }
+    changed.then(|| args.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::ToolCall;
+
+    #[test]
+    fn test_truncate_is_char_safe() {
+        assert_eq!(truncate("héllo", 2), "h");
+        assert_eq!(truncate("héllo", 3), "hé");
+        assert_eq!(truncate("hi", 10), "hi");
+    }
+
+    #[test]
+    fn test_fit_compacts_old_outputs_first() {
+        let big = "x".repeat(3000);
+        let output = |text: &str| Message::user(format!("{}\n{}", REPL_OUTPUT_HEADER, text));
+        let mut messages = vec![
+            Message::system("s".repeat(600)),
+            Message::assistant(format!("```repl\n{}\n```", big)),
+            output(&big),
+            Message::user(format!("Question: {}", big)),
+            Message::assistant(big.clone()),
+            output(&big),
+            Message::assistant(big.clone()),
+            output(&big),
+            Message::assistant("step"),
+            output(&big),
+        ];
+        // Room for everything but two of the large messages
+        let budget = ContextBudget::new(estimate_messages(&messages) - 1500, 0);
+        let compacted = budget.fit(&mut messages, &[3]);
+
+        assert_eq!(compacted, 2);
+        assert!(estimate_messages(&messages) <= budget.prompt_tokens);
+        assert!(messages[2].content.contains("compacted to save context"));
+        assert!(messages[5].content.contains("compacted to save context"));
+        // Assistant turns and pinned messages are untouched while outputs suffice
+        assert_eq!(messages[1].content.len(), big.len() + 12);
+        assert_eq!(messages[3].content.len(), big.len() + 10);
+        // The recent tail is kept
+        assert_eq!(
+            messages[7].content.len(),
+            big.len() + REPL_OUTPUT_HEADER.len() + 1
+        );
+
+        // A tighter budget trims older assistant turns too, but never the
+        // system prompt or pinned messages
+        let budget = ContextBudget::new(estimate_messages(&messages) - 1500, 0);
+        budget.fit(&mut messages, &[3]);
+        assert!(messages[1].content.contains("trimmed to save context"));
+        assert_eq!(messages[0].content.len(), 600);
+        assert_eq!(messages[3].content.len(), big.len() + 10);
+    }
+
+    #[test]
+    fn test_fit_compacts_tool_call_arguments() {
+        let big = "x".repeat(3000);
+        let code = serde_json::json!({ "code": big });
+        let mut messages = vec![
+            Message::system("s"),
+            Message::tool_call("", ToolCall::new("call_0", "run_python", &code)),
+            Message::tool("call_0", format!("{}\n{}", REPL_OUTPUT_HEADER, big)),
+            Message::user("question"),
+        ];
+        messages.extend((0..KEEP_RECENT).map(|_| Message::assistant("step")));
+        assert!(estimate_messages(&messages) > 2000);
+
+        let budget = ContextBudget::new(500, 0);
+        assert_eq!(budget.fit(&mut messages, &[3]), 2);
+        assert!(estimate_messages(&messages) <= budget.prompt_tokens);
+        let arguments: serde_json::Value =
+            serde_json::from_str(&messages[1].tool_calls[0].function.arguments).unwrap();
+        assert!(arguments["code"]
+            .as_str()
+            .unwrap()
+            .contains("trimmed to save context"));
+        assert_eq!(messages[2].tool_call_id.as_deref(), Some("call_0"));
+    }
+}
@@ -0,0 +1,361 @@
+//! Conversation memory per Discord thread: prior questions, answers and files
+//! read, plus the REPL session a follow-up can continue in. Also ranks other
+//! answered threads of a topic as extra context for new questions.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+use super::exec::PersistentSession;
+use super::RlmEngine;
+use crate::docs::types::AnsweredThread;
+
+/// Conversations idle for longer than this are dropped (with their sessions).
+const IDLE_TTL: Duration = Duration::from_secs(60 * 60);
+/// Turns kept per conversation.
+const MAX_TURNS: usize = 10;
+/// Turns rendered into prompts.
+const PROMPT_TURNS: usize = 4;
+/// Answer excerpt per turn in prompts, in chars.
+const PROMPT_ANSWER_CHARS: usize = 1200;
+/// Files listed per turn in prompts.
+const PROMPT_FILES: usize = 8;
+/// Answered threads offered as context per question.
+pub const RELATED_THREADS: usize = 3;
+/// Answer excerpt per related thread in prompts, in chars.
+const RELATED_ANSWER_CHARS: usize = 800;
+
+/// One answered question.
+#[derive(Debug, Clone)]
+pub struct Turn {
+    /// As asked
+    pub question: String,
+    /// Rewritten to stand alone, if it was a follow-up
+    pub standalone: Option<String>,
+    pub answer: String,
+    /// (doc_id, path) of files read while answering
+    pub files_read: Vec<(String, String)>,
+}
+
+impl Turn {
+    /// The question as it was researched.
+    pub fn researched(&self) -> &str {
+        self.standalone.as_deref().unwrap_or(&self.question)
+    }
+}
+
+/// Prior turns of a conversation and the REPL session to continue in.
+#[derive(Default)]
+pub struct FollowUp {
+    pub history: Vec<Turn>,
+    pub session: Option<Arc<PersistentSession>>,
+    /// Other threads that answered similar questions on the topic
+    pub related: Vec<AnsweredThread>,
+    /// Which conversation the turn belongs to; hand it back to `finish`
+    pub epoch: u64,
+}
+
+struct Conversation {
+    topic: String,
+    /// Set when the conversation starts; turns begun in an earlier (cleared
+    /// or replaced) conversation of the thread carry a different one
+    epoch: u64,
+    turns: Vec<Turn>,
+    /// REPL session of the last turn; taken while a turn runs
+    session: Option<Arc<PersistentSession>>,
+    last_active: Instant,
+}
+
+/// Conversations by thread (channel) ID. In memory only: a restart forgets them.
+#[derive(Default)]
+pub struct Conversations {
+    threads: Mutex<HashMap<u64, Conversation>>,
+    /// Last epoch handed out
+    epochs: AtomicU64,
+}
+
+impl Conversations {
+    /// Start a turn in `thread`: the conversation's history and its session,
+    /// which is taken so a concurrent turn can't share it. Asking about a
+    /// different topic starts the conversation over.
+    pub async fn begin(&self, thread: u64, topic: &str) -> FollowUp {
+        let mut threads = self.threads.lock().await;
+        prune(&mut threads);
+        if let Some(c) = threads.get_mut(&thread).filter(|c| c.topic == topic) {
+            c.last_active = Instant::now();
+            return FollowUp {
+                history: c.turns.clone(),
+                session: c.session.take().filter(|s| s.is_usable()),
+                related: Vec::new(),
+                epoch: c.epoch,
+            };
+        }
+        let epoch = self.epochs.fetch_add(1, Ordering::Relaxed) + 1;
+        threads.insert(
+            thread,
+            Conversation {
+                topic: topic.to_string(),
+                epoch,
+                turns: Vec::new(),
+                session: None,
+                last_active: Instant::now(),
+            },
+        );
+        FollowUp {
+            epoch,
+            ..FollowUp::default()
+        }
+    }
+
+    /// Record a finished turn and the session the next one may reuse. Dropped
+    /// if the conversation the turn began in (`epoch`) was cleared or replaced
+    /// while it ran.
+    pub async fn finish(
+        &self,
+        thread: u64,
+        epoch: u64,
+        turn: Turn,
+        session: Option<Arc<PersistentSession>>,
+    ) {
+        let mut threads = self.threads.lock().await;
+        let Some(conversation) = threads.get_mut(&thread).filter(|c| c.epoch == epoch) else {
+            return;
+        };
+        conversation.turns.push(turn);
+        if conversation.turns.len() > MAX_TURNS {
+            conversation.turns.remove(0);
+        }
+        if session.is_some() {
+            conversation.session = session;
+        }
+        conversation.last_active = Instant::now();
+    }
+
+    /// End a turn that produced no answer (it failed or was cancelled). A
+    /// conversation the turn itself started is forgotten; an earlier one keeps
+    /// its turns and starts its next turn in a fresh session.
+    pub async fn abandon(&self, thread: u64, epoch: u64) {
+        let mut threads = self.threads.lock().await;
+        let Some(conversation) = threads.get_mut(&thread).filter(|c| c.epoch == epoch) else {
+            return;
+        };
+        if conversation.turns.is_empty() {
+            threads.remove(&thread);
+        } else {
+            conversation.last_active = Instant::now();
+        }
+    }
+
+    /// Forget a thread's conversation and end its session. Returns the number
+    /// of turns forgotten.
+    pub async fn clear(&self, thread: u64) -> usize {
+        self.threads
+            .lock()
+            .await
+            .remove(&thread)
+            .map(|c| c.turns.len())
+            .unwrap_or(0)
+    }
+}
+
+fn prune(threads: &mut HashMap<u64, Conversation>) {
+    threads.retain(|_, c| c.last_active.elapsed() < IDLE_TTL);
+}
+
+/// Recent turns as prompt context, oldest first.
+pub fn render_history(turns: &[Turn]) -> String {
+    let start = turns.len().saturating_sub(PROMPT_TURNS);
+    turns[start..]
+        .iter()
+        .enumerate()
+        .map(|(i, turn)| {
+            let mut answer: String = turn.answer.chars().take(PROMPT_ANSWER_CHARS).collect();
+            if answer.len() < turn.answer.len() {
+                answer.push_str(" [...]");
+            }
+            let mut out = format!("Q{}: {}\nA{}: {}", i + 1, turn.researched(), i + 1, answer);
+            if !turn.files_read.is_empty() {
+                let files: Vec<&str> = turn
+                    .files_read
+                    .iter()
+                    .take(PROMPT_FILES)
+                    .map(|(_, path)| path.as_str())
+                    .collect();
+                out.push_str(&format!("\nFiles read: {}", files.join(", ")));
+            }
+            out
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// The `limit` threads whose questions share the most keywords with
+/// `question`, best first. Threads sharing fewer than two keywords are dropped.
+pub fn rank_related(
+    threads: Vec<AnsweredThread>,
+    question: &str,
+    limit: usize,
+) -> Vec<AnsweredThread> {
+    let keywords = RlmEngine::extract_keywords(question);
+    let mut scored: Vec<(usize, AnsweredThread)> = threads
+        .into_iter()
+        .filter_map(|thread| {
+            let asked = thread.questions.join(" ").to_lowercase();
+            let shared = keywords
+                .iter()
+                .filter(|k| asked.contains(k.as_str()))
+                .count();
+            (shared >= 2).then_some((shared, thread))
+        })
+        .collect();
+    // Stable: among equal scores the newer thread (listed first) wins
+    scored.sort_by_key(|(shared, _)| std::cmp::Reverse(*shared));
+    scored.into_iter().take(limit).map(|(_, t)| t).collect()
+}
+
+/// Related threads as prompt context, with their Discord mentions so an
+/// answer can link them.
+pub fn render_related(threads: &[AnsweredThread]) -> String {
+    threads
+        .iter()
+        .map(|thread| {
+            let mut answer: String = thread.answer.chars().take(RELATED_ANSWER_CHARS).collect();
+            if answer.len() < thread.answer.len() {
+                answer.push_str(" [...]");
+            }
+            format!(
+                "Thread <#{}> — asked: {}\nAnswer: {}",
+                thread.thread_id,
+                thread.questions.join(" / "),
+                answer
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn(q: &str, a: &str) -> Turn {
+        Turn {
+            question: q.to_string(),
+            standalone: None,
+            answer: a.to_string(),
+            files_read: vec![("d".to_string(), "docs/gpu.md".to_string())],
+        }
+    }
+
+    #[test]
+    fn test_render_history_keeps_recent_turns() {
+        let turns: Vec<Turn> = (1..=6).map(|i| turn(&format!("q{i}"), "a")).collect();
+        let history = render_history(&turns);
+        assert!(history.starts_with("Q1: q3\nA1: a\nFiles read: docs/gpu.md"));
+        assert!(history.ends_with("Q4: q6\nA4: a\nFiles read: docs/gpu.md"));
+    }
+
+    #[test]
+    fn test_render_history_truncates_on_char_boundary() {
+        let long = "é".repeat(PROMPT_ANSWER_CHARS + 5);
+        let history = render_history(&[turn("q", &long)]);
+        assert!(history.contains(" [...]"));
+    }
+
+    #[test]
+    fn test_rank_related_by_shared_keywords() {
+        let thread = |id, question: &str| AnsweredThread {
+            thread_id: id,
+            scope: crate::docs::types::Scope::Guild(1),
+            topic: "akash".to_string(),
+            questions: vec![question.to_string()],
+            answer: "a".to_string(),
+            cited_urls: vec![],
+            updated_at: 0,
+        };
+        let threads = vec![
+            thread(1, "How do I deploy a GPU workload?"),
+            thread(2, "What are the provider GPU hardware requirements?"),
+            thread(3, "How does staking work?"),
+        ];
+        let related = rank_related(threads, "Which GPU hardware does a provider need?", 3);
+        let ids: Vec<u64> = related.iter().map(|t| t.thread_id).collect();
+        assert_eq!(ids, vec![2]);
+        assert!(render_related(&related).starts_with("Thread <#2> — asked: What are the provider"));
+    }
+
+    #[tokio::test]
+    async fn test_topic_change_and_clear_reset() {
+        let conversations = Conversations::default();
+        let first = conversations.begin(1, "akash").await;
+        assert!(first.history.is_empty());
+        conversations
+            .finish(1, first.epoch, turn("q", "a"), None)
+            .await;
+        let other = conversations.begin(2, "akash").await;
+        conversations
+            .finish(2, other.epoch, turn("q", "a"), None)
+            .await;
+        assert_eq!(conversations.begin(1, "akash").await.history.len(), 1);
+
+        assert!(conversations.begin(1, "cosmos").await.history.is_empty());
+        assert!(conversations.begin(1, "akash").await.history.is_empty());
+
+        assert_eq!(conversations.clear(2).await, 1);
+        assert!(conversations.begin(2, "akash").await.history.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_finish_after_clear_or_topic_switch_is_dropped() {
+        let conversations = Conversations::default();
+        let running = conversations.begin(1, "akash").await;
+        assert_eq!(conversations.clear(1).await, 0);
+        conversations
+            .finish(1, running.epoch, turn("q", "a"), None)
+            .await;
+        assert_eq!(conversations.clear(1).await, 0);
+        assert!(conversations.begin(1, "akash").await.history.is_empty());
+
+        // A turn on the old topic finishing after a switch doesn't displace
+        // the new topic's turn
+        let old = conversations.begin(2, "akash").await;
+        let new = conversations.begin(2, "cosmos").await;
+        conversations
+            .finish(2, old.epoch, turn("old", "a"), None)
+            .await;
+        conversations
+            .finish(2, new.epoch, turn("new", "a"), None)
+            .await;
+        let history = conversations.begin(2, "cosmos").await.history;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].question, "new");
+    }
+
+    #[tokio::test]
+    async fn test_abandon_keeps_earlier_turns() {
+        let conversations = Conversations::default();
+        let failed = conversations.begin(1, "akash").await;
+        conversations.abandon(1, failed.epoch).await;
+        assert!(conversations.threads.lock().await.is_empty());
+
+        let first = conversations.begin(1, "akash").await;
+        conversations
+            .finish(1, first.epoch, turn("q", "a"), None)
+            .await;
+        let failed = conversations.begin(1, "akash").await;
+        conversations.abandon(1, failed.epoch).await;
+        assert_eq!(conversations.begin(1, "akash").await.history.len(), 1);
+
+        // A stale turn doesn't touch the conversation that replaced it
+        let stale = conversations.begin(1, "cosmos").await;
+        let current = conversations.begin(1, "akash").await;
+        conversations.abandon(1, stale.epoch).await;
+        conversations
+            .finish(1, current.epoch, turn("q", "a"), None)
+            .await;
+        assert_eq!(conversations.clear(1).await, 1);
+    }
+}
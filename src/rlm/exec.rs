@@ -1,18 +1,36 @@
-use std::sync::Arc;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
-use anyhow::Result;
-use pyo3::prelude::*;
-use pyo3::types::{PyCFunction, PyDict, PyList};
-use tokio::runtime::Handle;
+use anyhow::{Context, Result};
+use serde_json::{json, Map, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
 use tracing::{debug, warn};
 
 use crate::docs::types::DocMeta;
 use crate::docs::DocumentStore;
 use crate::llm::LlmClient;
 
+use super::jobs::CancelToken;
+use super::tools::{self, ToolEnv};
+use super::usage::UsageMeter;
+use super::SubRlm;
+
 /// Thread-safe tracker for files accessed via `read_file()` during a session.
 pub type FileTracker = Arc<std::sync::Mutex<Vec<(String, String)>>>;
 
+/// Meter of the query currently using a session, which `llm_query()` calls
+/// are billed to. Swapped when a follow-up continues in the session.
+pub type UsageSlot = Arc<std::sync::Mutex<UsageMeter>>;
+
+/// Cancellation of the query currently using a session, which its long tools
+/// stop on. Swapped along with the `UsageSlot`.
+pub type CancelSlot = Arc<std::sync::Mutex<CancelToken>>;
+
 pub const BLOCKED: &[&str] = &[
     "__import__",
     "eval",
@@ -49,10 +67,7 @@ pub const ALLOWED: &[&str] = &[
     "sum",
     "abs",
     "round",
-    "type",
     "isinstance",
-    "hasattr",
-    "getattr",
     "repr",
     "format",
     "True",
@@ -65,437 +80,968 @@ pub const ALLOWED: &[&str] = &[
     "ord",
 ];
 
-/// Request to execute code on the persistent Python thread.
-struct ExecRequest {
-    code: String,
-    reply: std::sync::mpsc::Sender<Result<String>>,
+/// How long past its timeout a worker gets to report before it is killed.
+const INTERRUPT_GRACE: Duration = Duration::from_secs(2);
+/// Longest message a worker may send, e.g. a tool call's arguments.
+const MAX_MESSAGE_BYTES: u64 = 32 * 1024 * 1024;
+
+/// Signal a worker gets when it runs out of CPU time (Linux).
+const SIGXCPU: i32 = 24;
+
+const CAUSE_TIMEOUT: u8 = 1;
+const CAUSE_MEMORY: u8 = 2;
+/// Reported by the parent, never by the worker.
+const CAUSE_RUN_LIMIT: u8 = 3;
+
+/// The interpreter each session runs in, as `python3 -c WORKER`. It talks
+/// JSON lines over stdin/stdout: an init message (tools, `documents`, limits,
+/// builtins), then `run`/`read` requests, each answered with `done` after any
+/// number of `call` messages the parent answers with a tool's reply.
+///
+/// - `check_code`: AST-level policy. Rejects imports, dunder names, access to
+///   underscore-prefixed or frame/code attributes — also inside format-string
+///   fields — and `.format` on anything but a string literal: the hops every
+///   known escape from restricted builtins needs (`().__class__.__mro__`,
+///   `gen.gi_frame.f_globals`, `"{0.__class__}".format(x)`, `case
+///   str(__class__=c)`). Syntax errors pass through so execution reports them.
+/// - Limits: SIGALRM raises `Timeout` in the model's code when a run's time is
+///   up (again every 0.1s if it is swallowed), and RLIMIT_CPU kills the
+///   process soon after if it is stuck in C; RLIMIT_AS turns memory growth
+///   past the budget into `MemoryError`. Files, writes, forks and core dumps
+///   are ruled out with rlimits once the worker is set up.
+const WORKER: &str = r#"
+import ast
+import builtins
+import json
+import os
+import re
+import resource
+import signal
+import sys
+import time
+
+PIPE_IN = sys.stdin
+PIPE_OUT = sys.stdout
+REPL_FILE = '<repl>'
+UNSUPPORTED = '$unsupported'
+EXCEPTIONS = {'TypeError': TypeError, 'ValueError': ValueError}
+
+BLOCKED_ATTRS = frozenset({
+    'gi_frame', 'gi_code', 'gi_yieldfrom', 'cr_frame', 'cr_code', 'cr_await',
+    'ag_frame', 'ag_code', 'ag_await', 'f_globals', 'f_locals', 'f_builtins',
+    'f_back', 'f_code', 'tb_frame', 'tb_next', 'co_code', 'co_consts', 'mro',
+    'format_map',
+})
+FORMAT_FIELD = re.compile(r'\{([^{}]*)\}')
+FIELD_ATTR = re.compile(r'\.\s*([A-Za-z_]\w*)')
+MATCH_CLASS = getattr(ast, 'MatchClass', ())  # Python < 3.10 has no match
+
+
+def blocked_attr(name):
+    return name.startswith('_') or name in BLOCKED_ATTRS
+
+
+def is_str_literal(node):
+    return isinstance(node, ast.Constant) and isinstance(node.value, str)
+
+
+def check_code(source):
+    try:
+        tree = ast.parse(source)
+    except (SyntaxError, ValueError):
+        return None
+    for node in ast.walk(tree):
+        if isinstance(node, (ast.Import, ast.ImportFrom)):
+            return 'import is not allowed'
+        if isinstance(node, ast.Attribute):
+            if blocked_attr(node.attr):
+                return f"attribute '{node.attr}' is not allowed"
+            # A format string built at runtime can't be checked here
+            if node.attr == 'format' and not is_str_literal(node.value):
+                return '.format() is only allowed on string literals'
+        elif isinstance(node, ast.Name) and node.id.startswith('__'):
+            return f"name '{node.id}' is not allowed"
+        elif isinstance(node, MATCH_CLASS):
+            for attr in node.kwd_attrs:
+                if blocked_attr(attr):
+                    return f"attribute '{attr}' is not allowed"
+        elif is_str_literal(node):
+            for field in FORMAT_FIELD.findall(node.value):
+                for attr in FIELD_ATTR.findall(field):
+                    if blocked_attr(attr):
+                        return f"attribute '{attr}' is not allowed in format fields"
+    return None
+
+
+class CappedOutput:
+    def __init__(self, limit):
+        self.limit = limit
+        self.parts = []
+        self.size = 0
+        self.dropped = 0
+
+    def write(self, s):
+        room = self.limit - self.size
+        if room >= len(s):
+            self.parts.append(s)
+            self.size += len(s)
+        else:
+            if room > 0:
+                self.parts.append(s[:room])
+                self.size += room
+            self.dropped += len(s) - max(room, 0)
+        return len(s)
+
+    def flush(self):
+        pass
+
+    def getvalue(self):
+        return ''.join(self.parts)
+
+
+class Timeout(BaseException):
+    pass
+
+
+clock = {'armed': False}
+
+
+def on_alarm(signum, frame):
+    # Only the model's code is interrupted, never the worker's own bookkeeping
+    if clock['armed'] and frame is not None and frame.f_code.co_filename == REPL_FILE:
+        raise Timeout()
+
+
+def arm(seconds):
+    clock['armed'] = True
+    signal.setitimer(signal.ITIMER_REAL, max(seconds, 0.001), 0.1)
+
+
+def disarm():
+    clock['armed'] = False
+    return signal.setitimer(signal.ITIMER_REAL, 0)[0]
+
+
+def set_limit(kind, soft, hard=None):
+    try:
+        if hard is None:
+            hard = resource.getrlimit(kind)[1]
+        resource.setrlimit(kind, (soft, hard))
+    except (ValueError, OSError):
+        pass
+
+
+def cpu_budget(seconds):
+    # For calls into C the alarm can't interrupt: SIGXCPU ends the process
+    usage = resource.getrusage(resource.RUSAGE_SELF)
+    set_limit(resource.RLIMIT_CPU, int(usage.ru_utime + usage.ru_stime + seconds) + 2)
+
+
+def vm_size():
+    try:
+        with open('/proc/self/status') as status:
+            for line in status:
+                if line.startswith('VmSize:'):
+                    return int(line.split()[1]) * 1024
+    except (OSError, ValueError):
+        pass
+    return None
+
+
+def send(message):
+    PIPE_OUT.write(json.dumps(message) + '\n')
+    PIPE_OUT.flush()
+
+
+def receive():
+    line = PIPE_IN.readline()
+    if not line:
+        os._exit(0)
+    return json.loads(line)
+
+
+def unsupported(value):
+    return {UNSUPPORTED: type(value).__name__}
+
+
+def make_tool(name, long, timeout):
+    def tool(*args, **kwargs):
+        try:
+            call = json.dumps({'op': 'call', 'tool': name, 'args': args, 'kwargs': kwargs},
+                              default=unsupported, allow_nan=False)
+        except (TypeError, ValueError) as e:
+            raise TypeError(f'{name}() got an argument it cannot take ({e})') from None
+        # The clock stops while the parent runs the tool
+        remaining = disarm()
+        started = time.monotonic()
+        try:
+            PIPE_OUT.write(call + '\n')
+            PIPE_OUT.flush()
+            reply = receive()
+        finally:
+            if long:
+                cpu_budget(timeout)
+            arm(timeout if long else remaining - (time.monotonic() - started))
+        if 'error' in reply:
+            raise EXCEPTIONS.get(reply['error'], RuntimeError)(reply['message'])
+        return reply['value']
+
+    tool.__name__ = tool.__qualname__ = name
+    return tool
+
+
+def make_help(helps, overview):
+    def help(target=None):
+        if target is None:
+            text = overview
+        else:
+            name = target if isinstance(target, str) else getattr(target, '__name__', None) or str(target)
+            text = helps.get(name) or f'No help for {name}. help() lists the tools.\n'
+        sys.stdout.write(text)
+
+    return help
+
+
+def make_namespace(init):
+    restricted = {name: getattr(builtins, name) for name in init['allowed'] if hasattr(builtins, name)}
+    restricted.update(dict.fromkeys(init['blocked']))
+    namespace = {'__builtins__': restricted, 'documents': init['documents']}
+    timeout = init['limits']['timeout_secs']
+    for tool in init['tools']:
+        namespace[tool['name']] = make_tool(tool['name'], tool['long'], timeout)
+    helps = {tool['name']: tool['help'] for tool in init['tools']}
+    namespace['help'] = make_help(helps, init['overview'])
+    return namespace
+
+
+def run(code, namespace, limits):
+    reason = check_code(code)
+    if reason is not None:
+        return {'output': 'Error: blocked by sandbox: ' + reason, 'error': reason}
+    capture = CappedOutput(limits['max_output_chars'])
+    error = None
+    sys.stdout = capture
+    cpu_budget(limits['timeout_secs'])
+    try:
+        arm(limits['timeout_secs'])
+        exec(compile(code, REPL_FILE, 'exec'), namespace)
+    except Timeout:
+        error = limits['timeout_error']
+    except MemoryError:
+        error = limits['memory_error']
+    except BaseException as e:
+        error = f'{type(e).__name__}: {e}'
+    finally:
+        disarm()
+        sys.stdout = PIPE_OUT
+    output = capture.getvalue()
+    if capture.dropped:
+        output += f'\n[output truncated: {capture.dropped} more chars]'
+    if error is not None:
+        output += '\nError: ' + error
+    # Lone surrogates can't cross the pipe
+    return {'output': output.encode('utf-8', 'replace').decode('utf-8'), 'error': error}
+
+
+def read(name, namespace):
+    # Values other than strings are refused rather than converted
+    if name not in namespace:
+        return {'error': f"name '{name}' is not defined"}
+    value = namespace[name]
+    if not isinstance(value, str):
+        return {'error': f"'{name}' is a {type(value).__name__}, not a string"}
+    return {'value': value}
+
+
+def main():
+    init = receive()
+    limits = init['limits']
+    namespace = make_namespace(init)
+    base = vm_size()
+    if base is not None:
+        memory = base + limits['max_memory_bytes']
+        set_limit(resource.RLIMIT_AS, memory, memory)
+    # The pipes are all the worker needs: nothing to open, write, fork or dump
+    for kind in (resource.RLIMIT_NOFILE, resource.RLIMIT_FSIZE, resource.RLIMIT_NPROC, resource.RLIMIT_CORE):
+        value = 3 if kind == resource.RLIMIT_NOFILE else 0
+        set_limit(kind, value, value)
+    signal.signal(signal.SIGALRM, on_alarm)
+    while True:
+        request = receive()
+        if request['op'] == 'run':
+            reply = run(request['code'], namespace, limits)
+        else:
+            reply = read(request['name'], namespace)
+        reply['op'] = 'done'
+        send(reply)
+
+
+main()
+"#;
+
+/// Resource limits applied to every `execute()` call.
+#[derive(Debug, Clone, Copy)]
+pub struct ExecLimits {
+    /// Wall-clock budget per execution; the code is interrupted when it runs
+    /// out, and the session's interpreter killed if it doesn't stop
+    pub timeout: Duration,
+    /// Wall-clock budget per execution including the tools it calls, which
+    /// `timeout` doesn't count. Tool calls past it fail, and code that keeps
+    /// running anyway is stopped `timeout` later
+    pub max_run: Duration,
+    /// Captured stdout beyond this many chars is dropped
+    pub max_output_chars: usize,
+    /// Memory a session's interpreter may allocate beyond what it starts
+    /// with; allocations past it raise `MemoryError`
+    pub max_memory_bytes: u64,
+}
+
+impl Default for ExecLimits {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            max_run: Duration::from_secs(600),
+            max_output_chars: 50_000,
+            max_memory_bytes: 512 * 1024 * 1024,
+        }
+    }
+}
+
+impl ExecLimits {
+    /// Read `REPL_TIMEOUT_SECS`, `REPL_MAX_RUN_SECS`, `REPL_MAX_OUTPUT_CHARS`
+    /// and `REPL_MAX_MEMORY_MB`, keeping defaults for unset or invalid values.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| {
+            dotenv::var(name)
+                .ok()
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .filter(|v| *v > 0)
+        };
+        Self {
+            timeout: var("REPL_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.timeout),
+            max_run: var("REPL_MAX_RUN_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.max_run),
+            max_output_chars: var("REPL_MAX_OUTPUT_CHARS")
+                .map(|v| v as usize)
+                .unwrap_or(defaults.max_output_chars),
+            max_memory_bytes: var("REPL_MAX_MEMORY_MB")
+                .map(|mb| mb * 1024 * 1024)
+                .unwrap_or(defaults.max_memory_bytes),
+        }
+    }
+
+    fn describe(&self, cause: u8) -> String {
+        match cause {
+            CAUSE_MEMORY => format!(
+                "memory limit exceeded ({} MB)",
+                self.max_memory_bytes / (1024 * 1024)
+            ),
+            CAUSE_RUN_LIMIT => format!(
+                "execution time limit exceeded ({}s including tool calls)",
+                self.max_run.as_secs()
+            ),
+            _ => format!("timeout (execution exceeded {}s)", self.timeout.as_secs()),
+        }
+    }
 }
 
-/// A persistent Python execution session that maintains globals across code blocks.
-/// Runs on a dedicated OS thread. Uses std::sync channels to avoid nested block_on.
+/// A session's interpreter process and the pipes to it.
+struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    /// Sent ahead of the first request
+    init: Option<Value>,
+    /// A request is mid-exchange. Still set when the next one starts means
+    /// its caller gave up (e.g. a cancelled query), leaving the pipes out of step.
+    busy: bool,
+}
+
+impl Worker {
+    /// Start `REPL_PYTHON` (default `python3`) in isolated mode with an empty
+    /// environment.
+    fn start(init: Value) -> Result<Self> {
+        let python = dotenv::var("REPL_PYTHON").unwrap_or_else(|_| "python3".to_string());
+        let mut child = Command::new(&python)
+            .args(["-I", "-S", "-X", "utf8", "-c", WORKER])
+            .env_clear()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("failed to start `{}`", python))?;
+        let stdin = child.stdin.take().context("worker stdin not piped")?;
+        let stdout = child.stdout.take().context("worker stdout not piped")?;
+        Ok(Self {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+            init: Some(init),
+            busy: false,
+        })
+    }
+
+    async fn send(&mut self, message: &Value) -> Result<()> {
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+        self.stdin.write_all(&line).await?;
+        self.stdin.flush().await?;
+        Ok(())
+    }
+
+    /// The worker's next message, or None once it has exited.
+    async fn receive(&mut self) -> Result<Option<Value>> {
+        let mut line = Vec::new();
+        (&mut self.stdout)
+            .take(MAX_MESSAGE_BYTES)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if line.is_empty() {
+            return Ok(None);
+        }
+        if line.last() != Some(&b'\n') {
+            anyhow::bail!("message cut off or over {} bytes", MAX_MESSAGE_BYTES);
+        }
+        Ok(Some(serde_json::from_slice(&line)?))
+    }
+
+    /// Why the worker exited mid-request.
+    async fn exit_reason(&mut self, limits: &ExecLimits) -> String {
+        match tokio::time::timeout(INTERRUPT_GRACE, self.child.wait()).await {
+            Ok(Ok(status)) if status.signal() == Some(SIGXCPU) => limits.describe(CAUSE_TIMEOUT),
+            Ok(Ok(status)) => format!("Python worker exited ({})", status),
+            Ok(Err(e)) => format!("Python worker exited ({})", e),
+            Err(_) => limits.describe(CAUSE_TIMEOUT),
+        }
+    }
+}
+
+/// A persistent Python session that keeps its globals across code blocks.
+/// Each session runs in its own interpreter process, so its limits, crashes
+/// and memory are its own; tools run here, in the calling task, when the
+/// worker asks for them.
 pub struct PersistentSession {
-    tx: std::sync::mpsc::Sender<ExecRequest>,
-    accessed_files: FileTracker,
+    worker: Mutex<Option<Worker>>,
+    env: ToolEnv,
+    limits: ExecLimits,
+    /// Why the session can no longer run code, once it can't
+    ended: OnceLock<String>,
 }
 
 impl PersistentSession {
     /// Spawn a new persistent session. Python globals survive across execute() calls.
-    pub fn spawn(store: Arc<DocumentStore>, llm: Arc<LlmClient>, docs: Vec<DocMeta>) -> Self {
-        let (tx, rx) = std::sync::mpsc::channel::<ExecRequest>();
-        let accessed_files: FileTracker = Arc::new(std::sync::Mutex::new(Vec::new()));
-        let tracker = accessed_files.clone();
-
-        std::thread::spawn(move || {
-            // Build a runtime for async bridging inside PyO3 closures.
-            // CRITICAL: we do NOT call block_on for the recv loop — we use
-            // std::sync::mpsc::recv() which is a plain OS block, keeping the
-            // thread outside of any tokio runtime context. This lets the PyO3
-            // closures safely call rt_handle.block_on() without nesting.
-            let bridge_rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .expect("Failed to create bridge runtime");
-            let rt_handle = bridge_rt.handle().clone();
-
-            Python::with_gil(|py| {
-                let globals = PyDict::new(py);
-
-                if let Err(e) = setup_restricted_builtins(py, &globals) {
-                    warn!("Failed to setup builtins: {}", e);
-                    return;
-                }
-                if let Err(e) = inject_doc_functions(py, &globals, store, llm, rt_handle, &docs, tracker) {
-                    warn!("Failed to inject doc functions: {}", e);
-                    return;
-                }
-
-                debug!("Persistent Python session initialized");
-                // Plain OS-level blocking recv — NOT inside a tokio runtime context
-                while let Ok(req) = rx.recv() {
-                    let result = execute_in_globals(py, &globals, &req.code);
-                    let _ = req.reply.send(result);
-                }
-                debug!("Persistent Python session shutting down");
-            });
+    pub fn spawn(
+        store: Arc<DocumentStore>,
+        llm: Arc<LlmClient>,
+        docs: Vec<DocMeta>,
+        limits: ExecLimits,
+        sub_rlm: Option<SubRlm>,
+    ) -> Self {
+        let mut init = tools::manifest(&docs);
+        init["allowed"] = json!(ALLOWED);
+        init["blocked"] = json!(BLOCKED);
+        init["limits"] = json!({
+            "timeout_secs": limits.timeout.as_secs_f64(),
+            "max_output_chars": limits.max_output_chars,
+            "max_memory_bytes": limits.max_memory_bytes,
+            "timeout_error": limits.describe(CAUSE_TIMEOUT),
+            "memory_error": limits.describe(CAUSE_MEMORY),
         });
 
-        Self { tx, accessed_files }
+        let ended = OnceLock::new();
+        let worker = match Worker::start(init) {
+            Ok(worker) => {
+                debug!("Python worker started");
+                Some(worker)
+            }
+            Err(e) => {
+                warn!("Failed to start Python worker: {:#}", e);
+                let _ = ended.set(format!("{:#}", e));
+                None
+            }
+        };
+        let env = ToolEnv {
+            store,
+            llm,
+            docs: Arc::new(docs),
+            file_tracker: FileTracker::default(),
+            usage: UsageSlot::default(),
+            cancel: CancelSlot::default(),
+            sub_rlm,
+        };
+        Self {
+            worker: Mutex::new(worker),
+            env,
+            limits,
+            ended,
+        }
+    }
+
+    /// Whether the session can still run code (its interpreter is killed when
+    /// an execution can't be stopped).
+    pub fn is_usable(&self) -> bool {
+        self.ended.get().is_none()
+    }
+
+    /// IDs of the documents the session was started on.
+    pub fn doc_ids(&self) -> impl Iterator<Item = &str> {
+        self.env.docs.iter().map(|d| d.id.as_str())
+    }
+
+    /// Record the LLM usage of the session's tools to `meter` from now on.
+    pub fn set_usage_meter(&self, meter: UsageMeter) {
+        *self
+            .env
+            .usage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = meter;
+    }
+
+    /// Stop the session's long tools when `cancel` fires, from now on.
+    pub fn set_cancel(&self, cancel: CancelToken) {
+        *self
+            .env
+            .cancel
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = cancel;
     }
 
     /// Get all files accessed via `read_file()` during this session.
     pub fn accessed_files(&self) -> Vec<(String, String)> {
-        match self.accessed_files.lock() {
+        match self.env.file_tracker.lock() {
             Ok(guard) => guard.clone(),
             Err(poisoned) => poisoned.into_inner().clone(),
         }
     }
 
     /// Execute code in the persistent session. Variables from previous calls are available.
+    ///
+    /// Bounded by the session's `ExecLimits`: a run that exceeds its time or
+    /// memory budget is interrupted and reports `Error: timeout ...` (or
+    /// `Error: memory limit exceeded ...`) in its output. If the interpreter
+    /// doesn't stop within a short grace period, it is killed and later calls
+    /// fail fast.
     pub async fn execute(&self, code: &str) -> Result<String> {
-        let (reply_tx, reply_rx) = std::sync::mpsc::channel();
-        self.tx
-            .send(ExecRequest {
-                code: code.to_string(),
-                reply: reply_tx,
-            })
-            .map_err(|_| anyhow::anyhow!("Python session thread died"))?;
-
-        // Await reply without blocking the tokio runtime
-        tokio::task::spawn_blocking(move || {
-            reply_rx
-                .recv()
-                .map_err(|_| anyhow::anyhow!("Python session reply channel closed"))?
-        })
-        .await?
+        match self.request(json!({"op": "run", "code": code})).await {
+            Ok(reply) => {
+                match reply["error"].as_str() {
+                    Some(error) => warn!(error, "Python execution error"),
+                    None => debug!("Python executed successfully"),
+                }
+                Ok(reply["output"].as_str().unwrap_or_default().to_string())
+            }
+            Err(reason) => Ok(format!("Error: {}", reason)),
+        }
     }
-}
-
-/// Execute code within existing globals, capturing stdout.
-fn execute_in_globals(py: Python<'_>, globals: &Bound<'_, PyDict>, code: &str) -> Result<String> {
-    let io_module = py.import("io")?;
-    let string_io = io_module.getattr("StringIO")?.call0()?;
-    let sys = py.import("sys")?;
-    let old_stdout = sys.getattr("stdout")?;
-    sys.setattr("stdout", &string_io)?;
-
-    let code_cstr = std::ffi::CString::new(code.as_bytes())
-        .map_err(|e| anyhow::anyhow!("Invalid code string: {}", e))?;
-    let result = py.run(&code_cstr, Some(globals), None);
 
-    sys.setattr("stdout", old_stdout)?;
+    /// The text of string variable `name` in the session, e.g. an answer the
+    /// model built up for `FINAL_VAR(name)`. Errors if it is unset or not a
+    /// string.
+    pub async fn read_variable(&self, name: &str) -> Result<String> {
+        let reply = self
+            .request(json!({"op": "read", "name": name}))
+            .await
+            .map_err(anyhow::Error::msg)?;
+        match (reply["value"].as_str(), reply["error"].as_str()) {
+            (Some(text), _) => Ok(text.to_string()),
+            (None, error) => anyhow::bail!("{}", error.unwrap_or("no value")),
+        }
+    }
 
-    let output: String = string_io.call_method0("getvalue")?.extract()?;
+    /// Send `request` to the worker and serve its tool calls until it
+    /// replies. Errs with the reason if the worker had to be stopped, which
+    /// ends the session.
+    async fn request(&self, request: Value) -> Result<Value, String> {
+        let mut slot = self.worker.lock().await;
+        let worker = match (slot.as_mut(), self.ended.get()) {
+            (Some(worker), None) => worker,
+            (_, ended) => {
+                return Err(format!(
+                    "session unavailable ({})",
+                    ended.map_or("no interpreter", String::as_str)
+                ))
+            }
+        };
+        match self.exchange(worker, &request).await {
+            Ok(reply) => Ok(reply),
+            Err(reason) => {
+                warn!(%reason, "Stopping Python worker");
+                // Dropping the worker kills its process
+                *slot = None;
+                let _ = self
+                    .ended
+                    .set(format!("an earlier execution was stopped: {}", reason));
+                Err(reason)
+            }
+        }
+    }
 
-    match result {
-        Ok(_) => {
-            debug!(output_len = output.len(), "Python executed successfully");
-            Ok(output)
+    async fn exchange(&self, worker: &mut Worker, request: &Value) -> Result<Value, String> {
+        if worker.busy {
+            return Err("an earlier execution was abandoned mid-run".to_string());
         }
-        Err(e) => {
-            warn!("Python execution error: {}", e);
-            Ok(format!("{}\nError: {}", output, e))
+        worker.busy = true;
+        let broken = |e: anyhow::Error| format!("Python worker failed: {:#}", e);
+        if let Some(init) = worker.init.take() {
+            worker.send(&init).await.map_err(broken)?;
+        }
+        worker.send(request).await.map_err(broken)?;
+
+        // The worker times its own code; this catches code it can't
+        // interrupt, such as a long call into C. Its clock stops while tools
+        // run, so the whole execution is held to `max_run` here: tools called
+        // past it fail, and code still running a timeout later is stopped.
+        let patience = self.limits.timeout + INTERRUPT_GRACE;
+        let deadline = Instant::now() + self.limits.max_run;
+        let hard_stop = deadline + patience;
+        let empty = Map::new();
+        loop {
+            let wait = (Instant::now() + patience).min(hard_stop);
+            let message = match tokio::time::timeout_at(wait, worker.receive()).await {
+                Err(_) if wait == hard_stop => return Err(self.limits.describe(CAUSE_RUN_LIMIT)),
+                Err(_) => return Err(self.limits.describe(CAUSE_TIMEOUT)),
+                Ok(Ok(Some(message))) => message,
+                Ok(Ok(None)) => return Err(worker.exit_reason(&self.limits).await),
+                Ok(Err(e)) => return Err(broken(e)),
+            };
+            match message["op"].as_str() {
+                Some("call") => {
+                    let name = message["tool"].as_str().unwrap_or_default();
+                    let args = message["args"].as_array().map_or(&[][..], Vec::as_slice);
+                    let kwargs = message["kwargs"].as_object().unwrap_or(&empty);
+                    let call = tools::call(&self.env, name, args, kwargs);
+                    let reply = match tokio::time::timeout_at(deadline, call).await {
+                        Ok(reply) => reply,
+                        Err(_) => json!({
+                            "error": "RuntimeError",
+                            "message": self.limits.describe(CAUSE_RUN_LIMIT),
+                        }),
+                    };
+                    worker.send(&reply).await.map_err(broken)?;
+                }
+                Some("done") => {
+                    worker.busy = false;
+                    return Ok(message);
+                }
+                _ => return Err(broken(anyhow::anyhow!("unexpected message {}", message))),
+            }
         }
     }
 }
 
-/// Set up restricted builtins — whitelist approach.
-fn setup_restricted_builtins(py: Python<'_>, globals: &Bound<'_, PyDict>) -> PyResult<()> {
-    let builtins = py.import("builtins")?;
-    let restricted = PyDict::new(py);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn session(name: &str, limits: ExecLimits) -> PersistentSession {
+        session_on(name, limits, LlmClient::from_env().unwrap()).await
+    }
+
+    /// A session whose `llm_query()` calls go to `llm`.
+    async fn session_on(name: &str, limits: ExecLimits, llm: LlmClient) -> PersistentSession {
+        let dir = std::env::temp_dir().join(format!("edgar-exec-{}-{}", name, std::process::id()));
+        let store = Arc::new(DocumentStore::new(&dir).await.unwrap());
+        PersistentSession::spawn(store, Arc::new(llm), Vec::new(), limits, None)
+    }
 
-    for name in ALLOWED {
-        if let Ok(obj) = builtins.getattr(*name) {
-            restricted.set_item(*name, obj)?;
+    fn limits(timeout_ms: u64) -> ExecLimits {
+        ExecLimits {
+            timeout: Duration::from_millis(timeout_ms),
+            ..ExecLimits::default()
         }
     }
 
-    for name in BLOCKED {
-        restricted.set_item(*name, py.None())?;
+    #[tokio::test]
+    async fn test_infinite_loop_times_out() {
+        let session = session("timeout", limits(300)).await;
+        session.execute("x = 41").await.unwrap();
+        let output = session.execute("while True:\n    pass").await.unwrap();
+        assert!(output.contains("Error: timeout"), "{output}");
+        // The session survives and keeps its globals
+        let output = session.execute("print(x + 1)").await.unwrap();
+        assert_eq!(output.trim(), "42");
     }
 
-    globals.set_item("__builtins__", restricted)?;
-    Ok(())
-}
+    #[tokio::test]
+    async fn test_tool_loop_stops_at_run_limit() {
+        use crate::llm::mock::{serve, test_client};
 
-/// Inject document access functions and variables into Python globals.
-fn inject_doc_functions(
-    py: Python<'_>,
-    globals: &Bound<'_, PyDict>,
-    store: Arc<DocumentStore>,
-    llm: Arc<LlmClient>,
-    rt: Handle,
-    docs: &[DocMeta],
-    file_tracker: FileTracker,
-) -> PyResult<()> {
-    // Inject `documents` variable
-    let doc_list = PyList::empty(py);
-    for doc in docs {
-        let d = PyDict::new(py);
-        d.set_item("doc_id", &doc.id)?;
-        d.set_item("name", &doc.name)?;
-        d.set_item("source", &doc.source)?;
-        d.set_item("size", doc.size)?;
-        doc_list.append(d)?;
-    }
-    globals.set_item("documents", doc_list)?;
-
-    // list_documents()
-    let docs_clone: Vec<DocMeta> = docs.to_vec();
-    let list_documents = PyCFunction::new_closure(
-        py,
-        Some(c"list_documents"),
-        None,
-        move |_args: &Bound<'_, pyo3::types::PyTuple>,
-              _kwargs: Option<&Bound<'_, PyDict>>|
-              -> PyResult<PyObject> {
-            Python::with_gil(|py| {
-                let result = PyList::empty(py);
-                for doc in &docs_clone {
-                    let d = PyDict::new(py);
-                    d.set_item("doc_id", &doc.id)?;
-                    d.set_item("name", &doc.name)?;
-                    d.set_item("source", &doc.source)?;
-                    d.set_item("size", doc.size)?;
-                    result.append(d)?;
-                }
-                Ok(result.into_any().unbind())
-            })
-        },
-    )?;
-    globals.set_item("list_documents", list_documents)?;
-
-    // get_section(doc_id, offset, length)
-    let store_gs = store.clone();
-    let rt_gs = rt.clone();
-    let get_section = PyCFunction::new_closure(
-        py,
-        Some(c"get_section"),
-        None,
-        move |args: &Bound<'_, pyo3::types::PyTuple>,
-              _kwargs: Option<&Bound<'_, PyDict>>|
-              -> PyResult<String> {
-            let doc_id: String = args.get_item(0)?.extract()?;
-            let offset: usize = args.get_item(1)?.extract()?;
-            let length: usize = args.get_item(2)?.extract()?;
-            tracing::debug!(doc_id = %doc_id, offset, length, "PyO3: get_section");
-            rt_gs
-                .block_on(store_gs.get_section(&doc_id, offset, length))
-                .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))
-        },
-    )?;
-    globals.set_item("get_section", get_section)?;
-
-    // search_document(doc_id, query, max_results=5)
-    let store_sd = store.clone();
-    let rt_sd = rt.clone();
-    let search_document = PyCFunction::new_closure(
-        py,
-        Some(c"search_document"),
-        None,
-        move |args: &Bound<'_, pyo3::types::PyTuple>,
-              _kwargs: Option<&Bound<'_, PyDict>>|
-              -> PyResult<PyObject> {
-            let doc_id: String = args.get_item(0)?.extract()?;
-            let query: String = args.get_item(1)?.extract()?;
-            let max_results: usize = if args.len() > 2 {
-                args.get_item(2)?.extract().unwrap_or(5)
-            } else {
-                5
-            };
-            tracing::debug!(doc_id = %doc_id, query = %query, max_results, "PyO3: search_document");
-            let excerpts = rt_sd
-                .block_on(store_sd.search(&doc_id, &query, max_results))
-                .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
-            tracing::debug!(results = excerpts.len(), "PyO3: search_document result");
-            Python::with_gil(|py| {
-                let result = PyList::empty(py);
-                for ex in &excerpts {
-                    let d = PyDict::new(py);
-                    d.set_item("doc_id", &ex.doc_id)?;
-                    d.set_item("offset", ex.offset)?;
-                    d.set_item("content", &ex.content)?;
-                    d.set_item("match_count", ex.match_count)?;
-                    result.append(d)?;
-                }
-                Ok(result.into_any().unbind())
-            })
-        },
-    )?;
-    globals.set_item("search_document", search_document)?;
-
-    // grep(doc_id, pattern, context=3, max_results=10) — search with context lines
-    let store_gr = store.clone();
-    let rt_gr = rt.clone();
-    let grep = PyCFunction::new_closure(
-        py,
-        Some(c"grep"),
-        None,
-        move |args: &Bound<'_, pyo3::types::PyTuple>,
-              _kwargs: Option<&Bound<'_, PyDict>>|
-              -> PyResult<PyObject> {
-            let doc_id: String = args.get_item(0)?.extract()?;
-            let pattern: String = args.get_item(1)?.extract()?;
-            let context_lines: usize = if args.len() > 2 {
-                args.get_item(2)?.extract().unwrap_or(3)
-            } else {
-                3
-            };
-            let max_results: usize = if args.len() > 3 {
-                args.get_item(3)?.extract().unwrap_or(10)
-            } else {
-                10
-            };
-            tracing::debug!(doc_id = %doc_id, pattern = %pattern, context_lines, "PyO3: grep");
-
-            let content = rt_gr
-                .block_on(store_gr.get_content(&doc_id))
-                .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
-            let text = String::from_utf8_lossy(&content);
-            let lines: Vec<&str> = text.lines().collect();
-
-            // Match lines containing the pattern (case-insensitive)
-            let pattern_lower = pattern.to_lowercase();
-            let mut matches: Vec<(usize, String)> = Vec::new();
-            let mut last_end: usize = 0; // track to avoid overlapping contexts
-
-            for (idx, line) in lines.iter().enumerate() {
-                if line.to_lowercase().contains(&pattern_lower) {
-                    let start = idx.saturating_sub(context_lines).max(last_end);
-                    let end = (idx + context_lines + 1).min(lines.len());
-
-                    let context_block: String = lines[start..end]
-                        .iter()
-                        .enumerate()
-                        .map(|(i, l)| {
-                            let ln = start + i + 1;
-                            if start + i == idx {
-                                format!(">> L{}: {}", ln, l)
-                            } else {
-                                format!("   L{}: {}", ln, l)
-                            }
-                        })
-                        .collect::<Vec<_>>()
-                        .join("\n");
-
-                    matches.push((idx + 1, context_block));
-                    last_end = end;
-
-                    if matches.len() >= max_results {
-                        break;
-                    }
-                }
-            }
+        let base = serve(|_| "ok".to_string()).await;
+        let limits = ExecLimits {
+            timeout: Duration::from_secs(1),
+            max_run: Duration::from_millis(500),
+            ..ExecLimits::default()
+        };
+        let session = session_on("run-limit", limits, test_client(base)).await;
 
-            tracing::debug!(results = matches.len(), "PyO3: grep result");
-            Python::with_gil(|py| {
-                let result = PyList::empty(py);
-                for (line_num, context) in &matches {
-                    let d = PyDict::new(py);
-                    d.set_item("line", *line_num)?;
-                    d.set_item("context", context)?;
-                    result.append(d)?;
-                }
-                Ok(result.into_any().unbind())
-            })
-        },
-    )?;
-    globals.set_item("grep", grep)?;
-
-    // list_files(doc_id) — extract file/section headers from ingested document
-    let store_lf = store.clone();
-    let rt_lf = rt.clone();
-    let list_files = PyCFunction::new_closure(
-        py,
-        Some(c"list_files"),
-        None,
-        move |args: &Bound<'_, pyo3::types::PyTuple>,
-              _kwargs: Option<&Bound<'_, PyDict>>|
-              -> PyResult<PyObject> {
-            let doc_id: String = args.get_item(0)?.extract()?;
-            tracing::debug!(doc_id = %doc_id, "PyO3: list_files");
-
-            let files = rt_lf
-                .block_on(store_lf.list_files(&doc_id))
-                .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
-
-            tracing::debug!(file_count = files.len(), "PyO3: list_files result");
-            Python::with_gil(|py| {
-                let result = PyList::empty(py);
-                for (offset, name) in &files {
-                    let d = PyDict::new(py);
-                    d.set_item("offset", *offset)?;
-                    d.set_item("name", name)?;
-                    result.append(d)?;
-                }
-                Ok(result.into_any().unbind())
-            })
-        },
-    )?;
-    globals.set_item("list_files", list_files)?;
-
-    // read_file(doc_id, filename) — read a specific file/section by name
-    let store_rf = store.clone();
-    let rt_rf = rt.clone();
-    let tracker_rf = file_tracker;
-    let read_file = PyCFunction::new_closure(
-        py,
-        Some(c"read_file"),
-        None,
-        move |args: &Bound<'_, pyo3::types::PyTuple>,
-              _kwargs: Option<&Bound<'_, PyDict>>|
-              -> PyResult<String> {
-            let doc_id: String = args.get_item(0)?.extract()?;
-            let filename: String = args.get_item(1)?.extract()?;
-            tracing::debug!(doc_id = %doc_id, filename = %filename, "PyO3: read_file");
-
-            let files = rt_rf
-                .block_on(store_rf.list_files(&doc_id))
-                .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
-
-            // Find this file's offset and the next file's offset
-            let filename_lower = filename.to_lowercase();
-            let mut target_offset = None;
-            let mut next_offset = None;
-            let mut matched_name = None;
-
-            for (i, (offset, name)) in files.iter().enumerate() {
-                if name.to_lowercase().contains(&filename_lower) {
-                    target_offset = Some(*offset);
-                    matched_name = Some(name.clone());
-                    if i + 1 < files.len() {
-                        next_offset = Some(files[i + 1].0);
-                    }
-                    break;
-                }
-            }
+        // Time spent in llm_query() doesn't count against `timeout`
+        let started = std::time::Instant::now();
+        let output = session
+            .execute("calls = 0\nwhile True:\n    llm_query('x')\n    calls += 1")
+            .await
+            .unwrap();
+        assert!(
+            output.contains("RuntimeError: execution time limit exceeded"),
+            "{output}"
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let output = session.execute("print(calls > 0)").await.unwrap();
+        assert_eq!(output.trim(), "True");
 
-            let Some(start) = target_offset else {
-                return Err(pyo3::exceptions::PyValueError::new_err(format!(
-                    "File '{}' not found. Use list_files() to see available files.",
-                    filename
-                )));
-            };
+        // Code that swallows the refusals is stopped, with its session
+        let code = "while True:\n    try:\n        llm_query_batch(['x'])\n    except Exception:\n        pass";
+        let output = session.execute(code).await.unwrap();
+        assert!(
+            output.contains("Error: execution time limit exceeded"),
+            "{output}"
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(!session.is_usable());
+    }
 
-            // Record successful file access for citation tracking
-            if let Some(name) = matched_name {
-                if let Ok(mut tracker) = tracker_rf.lock() {
-                    tracker.push((doc_id.clone(), name));
-                }
-            }
+    #[tokio::test]
+    async fn test_read_variable() {
+        let session = session("read", ExecLimits::default()).await;
+        session
+            .execute("report = 'line 1\\n' + 'line 2'\ncount = 2")
+            .await
+            .unwrap();
+        assert_eq!(
+            session.read_variable("report").await.unwrap(),
+            "line 1\nline 2"
+        );
+        let missing = session.read_variable("missing").await.unwrap_err();
+        assert!(missing.to_string().contains("not defined"), "{missing}");
+        let number = session.read_variable("count").await.unwrap_err();
+        assert!(number.to_string().contains("int, not a string"), "{number}");
+    }
 
-            // Read until next file header or max 20K chars
-            let max_len = 20_000;
-            let length = if let Some(end) = next_offset {
-                (end - start).min(max_len)
-            } else {
-                max_len
-            };
+    #[tokio::test]
+    async fn test_rlm_query_needs_nesting() {
+        let session = session("nesting", ExecLimits::default()).await;
+        let output = session
+            .execute("print(rlm_query('what is staking?'))")
+            .await
+            .unwrap();
+        assert!(output.contains("ValueError"), "{output}");
+        assert!(output.contains("nesting limit reached"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn test_tools_reject_doc_ids_outside_the_session() {
+        let session = session("doc-ids", ExecLimits::default()).await;
+        let output = session
+            .execute("print(read_file('other-guild-doc', 'README.md'))")
+            .await
+            .unwrap();
+        assert!(output.contains("ValueError"), "{output}");
+        assert!(output.contains("unknown doc_id 'other-guild-doc'"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn test_llm_query_batch_limits() {
+        let session = session("batch", ExecLimits::default()).await;
+        let output = session.execute("print(llm_query_batch([]))").await.unwrap();
+        assert_eq!(output.trim(), "[]");
+        let output = session
+            .execute("print(llm_query_batch(['x'] * 51, max_concurrency=8))")
+            .await
+            .unwrap();
+        assert!(output.contains("ValueError"), "{output}");
+        assert!(output.contains("at most 50 prompts (51 given)"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn test_swallowed_interrupt_still_times_out() {
+        let session = session("swallow", limits(300)).await;
+        let code = "while True:\n    try:\n        while True:\n            pass\n    except BaseException:\n        pass";
+        let output = session.execute(code).await.unwrap();
+        assert!(output.contains("Error: timeout"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn test_output_is_capped() {
+        let session = session(
+            "output",
+            ExecLimits {
+                max_output_chars: 100,
+                ..ExecLimits::default()
+            },
+        )
+        .await;
+        let output = session.execute("print('a' * 1000)").await.unwrap();
+        assert!(output.starts_with(&"a".repeat(100)), "{output}");
+        assert!(output.contains("[output truncated: 901 more chars]"), "{output}");
+    }
+
+    /// Known ways out of restricted builtins. None may reach a class, frame,
+    /// module or the real builtins.
+    const ESCAPES: &[&str] = &[
+        "print(().__class__.__mro__[1].__subclasses__())",
+        "print(getattr((), '__class__'))",
+        "print(type(()).mro())",
+        "print(hasattr((), '__class__'))",
+        "print(__builtins__)",
+        "print(__import__('os'))",
+        "import os\nprint(os)",
+        "from os import system\nprint(system)",
+        "print(open('/etc/hostname').read())",
+        "print(eval('1 + 1'))",
+        "exec('print(1)')",
+        "print(compile('1', 'x', 'eval'))",
+        "print(globals())",
+        "print(vars())",
+        "print((lambda: 0).__globals__)",
+        "print(print.__self__)",
+        "print(list_documents.__self__)",
+        "g = (x for x in [1])\nprint(g.gi_frame.f_globals)",
+        "g = (x for x in [1])\nprint(g.gi_frame.f_back)",
+        "print(f'{().__class__}')",
+        "print('{0.__class__.__mro__}'.format(()))",
+        "print(('{0.' + '_' * 2 + 'globals__}').format(lambda: 0))",
+        "spec = '{0.' + '_' * 2 + 'class__}'\nprint(spec.format(()))",
+        "print(str.format('{0.' + '_' * 2 + 'class__}', ()))",
+        "g = (x for x in [1])\nprint('{0.gi_frame.f_globals}'.format(g))",
+        "print('{0.__class__}'.format_map({'0': ()}))",
+        "match ():\n    case tuple(__class__=c):\n        print(c)",
+        "print(str.mro())",
+        "breakpoint()",
+    ];
+
+    #[tokio::test]
+    async fn test_escape_payloads_fail() {
+        let session = session("escapes", ExecLimits::default()).await;
+        for payload in ESCAPES {
+            let output = session.execute(payload).await.unwrap();
+            assert!(output.contains("Error"), "escaped: {payload:?} -> {output}");
+            assert!(
+                !output.contains("<class") && !output.contains("<module") && !output.contains("<built-in"),
+                "leaked: {payload:?} -> {output}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_policy_allows_ordinary_code() {
+        let session = session("policy", ExecLimits::default()).await;
+        let code = "files = [{'name': 'src/__init__.py', 'size': 3}, {'name': 'a.md', 'size': 9}]\n\
+                    files.sort(key=lambda f: -f['size'])\n\
+                    _ = len(files)\n\
+                    print(f\"{files[0]['name']} {'{} files'.format(_)}\")";
+        let output = session.execute(code).await.unwrap();
+        assert_eq!(output.trim(), "a.md 2 files");
+    }
+
+    #[tokio::test]
+    async fn test_grep_regex_and_kwargs() {
+        use crate::docs::types::{DocInfo, Scope};
+
+        let dir = std::env::temp_dir().join(format!("edgar-exec-grep-{}", std::process::id()));
+        let store = Arc::new(DocumentStore::new(&dir).await.unwrap());
+        let files = vec![
+            ("README.md".to_string(), "Staking rewards\nfee table".to_string()),
+            ("docs/fees.md".to_string(), "The fee is\ncharged per block".to_string()),
+        ];
+        let info = DocInfo {
+            scope: Scope::Global,
+            name: "o/r",
+            source: "github:o/r",
+            label: "t",
+            url_context: None,
+            commit: None,
+        };
+        let id = store.store_files(&files, &info).await.unwrap();
+        let docs = vec![store.get_meta(&id).await.unwrap()];
+        let llm = Arc::new(LlmClient::from_env().unwrap());
+        let session = PersistentSession::spawn(store, llm, docs, ExecLimits::default(), None);
+
+        let run = |code: String| {
+            let session = &session;
+            async move { session.execute(&code).await.unwrap() }
+        };
+        let output = run(format!(
+            r"print([(h['file'], h['line'], h['match']) for h in grep('{id}', r'(?i)staking|FEE\b')])"
+        ))
+        .await;
+        assert_eq!(output.trim(), "[('README.md', 1, 'Staking'), ('README.md', 2, 'fee'), ('docs/fees.md', 1, 'fee')]");
+
+        let output = run(format!(
+            "print([h['file'] for h in grep('{id}', 'fee', context=0, files='docs/*.md')])"
+        ))
+        .await;
+        assert_eq!(output.trim(), "['docs/fees.md']");
 
-            rt_rf
-                .block_on(store_rf.get_section(&doc_id, start, length))
-                .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))
-        },
-    )?;
-    globals.set_item("read_file", read_file)?;
-
-    // llm_query(prompt)
-    let llm_q = llm.clone();
-    let rt_lq = rt.clone();
-    let llm_query = PyCFunction::new_closure(
-        py,
-        Some(c"llm_query"),
-        None,
-        move |args: &Bound<'_, pyo3::types::PyTuple>,
-              _kwargs: Option<&Bound<'_, PyDict>>|
-              -> PyResult<String> {
-            let prompt: String = args.get_item(0)?.extract()?;
-            tracing::debug!(prompt_len = prompt.len(), "PyO3: llm_query");
-            rt_lq
-                .block_on(llm_q.sub_query(&prompt))
-                .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))
-        },
-    )?;
-    globals.set_item("llm_query", llm_query)?;
-
-    Ok(())
+        let output = run(format!(
+            r"print(len(grep('{id}', r'is\s+charged', multiline=True)))"
+        ))
+        .await;
+        assert_eq!(output.trim(), "1");
+
+        let output = run("help(grep)".to_string()).await;
+        assert!(output.starts_with("grep(doc_id, pattern, context=3"), "{output}");
+        assert!(output.contains("multiline: bool = False"), "{output}");
+
+        let output = run(format!("grep('{id}', '(unclosed')")).await;
+        assert!(output.contains("invalid regex"), "{output}");
+        let output = run(format!("grep('{id}', 'fee', contxt=2)")).await;
+        assert!(output.contains("unexpected keyword argument 'contxt'"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn test_memory_growth_is_interrupted() {
+        let session = session(
+            "memory",
+            ExecLimits {
+                timeout: Duration::from_secs(20),
+                max_memory_bytes: 64 * 1024 * 1024,
+                ..ExecLimits::default()
+            },
+        )
+        .await;
+        let code = "chunks = []\nwhile True:\n    chunks.append('x' * 1000000)";
+        let output = session.execute(code).await.unwrap();
+        assert!(output.contains("Error: memory limit exceeded"), "{output}");
+        // The limit is the session's own: it recovers once the memory is freed
+        let output = session
+            .execute("chunks = None\nprint(len('x' * 1000000))")
+            .await
+            .unwrap();
+        assert_eq!(output.trim(), "1000000");
+    }
+
+    #[tokio::test]
+    async fn test_uninterruptible_call_only_stops_its_session() {
+        let stuck = session(
+            "stuck",
+            ExecLimits {
+                timeout: Duration::from_millis(500),
+                max_memory_bytes: 4 * 1024 * 1024 * 1024,
+                ..ExecLimits::default()
+            },
+        )
+        .await;
+        let other = session("unstuck", ExecLimits::default()).await;
+
+        // `sum` over a range runs in C, where the alarm can't interrupt it
+        let started = std::time::Instant::now();
+        let (stuck_output, other_output) =
+            tokio::join!(stuck.execute("print(sum(range(10**12)))"), async {
+                let output = other.execute("print(6 * 7)").await.unwrap();
+                (output, started.elapsed())
+            });
+        let stuck_output = stuck_output.unwrap();
+        assert!(stuck_output.contains("Error: timeout"), "{stuck_output}");
+        assert!(!stuck.is_usable());
+        let output = stuck.execute("print(1)").await.unwrap();
+        assert!(output.contains("session unavailable"), "{output}");
+
+        let (output, elapsed) = other_output;
+        assert_eq!(output.trim(), "42");
+        assert!(elapsed < Duration::from_millis(500), "{elapsed:?}");
+        assert!(other.is_usable());
+    }
 }
@@ -0,0 +1,376 @@
+//! Regex search over a document's files or combined text, backing the REPL
+//! `grep()` tool.
+
+use anyhow::Result;
+use regex::{Regex, RegexBuilder};
+
+use crate::docs::types::FileEntry;
+
+/// Longest `match` text returned per hit.
+const MAX_MATCH_CHARS: usize = 200;
+
+/// Options for one `grep()` call.
+#[derive(Debug, Clone)]
+pub struct GrepOptions {
+    /// Lines of context before and after each match
+    pub context: usize,
+    pub max_results: usize,
+    /// Glob filters, e.g. `*.md` or `docs/**/*.mdx`; a file is searched if it
+    /// matches any. Patterns without a `/` match the file name only.
+    pub files: Vec<String>,
+    /// Search whole files instead of single lines, so a pattern can span line
+    /// breaks (`\n`, `\s+`); `^`/`$` still anchor at line boundaries
+    pub multiline: bool,
+    pub ignore_case: bool,
+}
+
+impl Default for GrepOptions {
+    fn default() -> Self {
+        Self {
+            context: 3,
+            max_results: 10,
+            files: Vec::new(),
+            multiline: false,
+            ignore_case: false,
+        }
+    }
+}
+
+/// One match with its surrounding lines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrepHit {
+    /// 1-based line of the match start, within its file for multi-file documents
+    pub line: usize,
+    pub file: Option<String>,
+    /// The matched text (first 200 chars)
+    pub matched: String,
+    /// Numbered context lines; matched lines are marked `>>`
+    pub context: String,
+}
+
+/// Compile `pattern`, reporting syntax errors with the regex crate's
+/// pointer-to-the-problem message.
+pub fn compile(pattern: &str, opts: &GrepOptions) -> Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(opts.ignore_case)
+        .multi_line(opts.multiline)
+        .build()
+        .map_err(|e| anyhow::anyhow!("invalid regex {:?}: {}", pattern, e))
+}
+
+/// Compiled `files` globs: which of a document's files a search covers.
+pub struct FileFilter {
+    /// Each glob and whether it matches the full path (it contains a `/`)
+    globs: Vec<(glob::Pattern, bool)>,
+}
+
+impl FileFilter {
+    pub fn new(globs: &[String]) -> Result<FileFilter> {
+        let globs: Vec<(glob::Pattern, bool)> = globs
+            .iter()
+            .map(|g| {
+                let pattern = glob::Pattern::new(g)
+                    .map_err(|e| anyhow::anyhow!("invalid file glob {:?}: {}", g, e))?;
+                Ok((pattern, g.contains('/')))
+            })
+            .collect::<Result<_>>()?;
+        Ok(FileFilter { globs })
+    }
+
+    /// Whether the file at `path` is searched. Every file is without globs.
+    pub fn includes(&self, path: &str) -> bool {
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        self.globs.is_empty()
+            || self.globs.iter().any(|(g, full_path)| {
+                let name = if *full_path {
+                    path
+                } else {
+                    path.rsplit('/').next().unwrap_or(path)
+                };
+                g.matches_with(name, options)
+            })
+    }
+}
+
+/// Search one file's `body` for `re`, ignoring `opts.files`. Hits are
+/// attributed to `path`.
+pub fn search_file(path: &str, body: &str, re: &Regex, opts: &GrepOptions) -> Vec<GrepHit> {
+    let mut hits = scan(body, &[], &[], re, opts);
+    for hit in &mut hits {
+        hit.file = Some(path.to_string());
+    }
+    hits
+}
+
+/// Search `text` (a document's combined `=== path ===` text, with `files` its
+/// manifest) for `re`.
+pub fn search(
+    text: &str,
+    files: &[FileEntry],
+    re: &Regex,
+    opts: &GrepOptions,
+) -> Result<Vec<GrepHit>> {
+    let filter = FileFilter::new(&opts.files)?;
+    let included: Vec<bool> = files.iter().map(|f| filter.includes(&f.path)).collect();
+    Ok(scan(text, files, &included, re, opts))
+}
+
+/// Search `text`, skipping lines of files not `included`.
+fn scan(
+    text: &str,
+    files: &[FileEntry],
+    included: &[bool],
+    re: &Regex,
+    opts: &GrepOptions,
+) -> Vec<GrepHit> {
+    // Split into lines, remembering each line's byte start
+    let mut lines: Vec<&str> = Vec::new();
+    let mut starts: Vec<usize> = Vec::new();
+    let mut byte = 0;
+    for segment in text.split_inclusive('\n') {
+        starts.push(byte);
+        byte += segment.len();
+        lines.push(segment.trim_end_matches('\n').trim_end_matches('\r'));
+    }
+
+    // Attribute each line to (file index, line within file) via manifest offsets.
+    // File header lines and any preamble map to None.
+    let mut owners: Vec<Option<(usize, usize)>> = Vec::with_capacity(lines.len());
+    let mut current: Option<(usize, usize)> = None;
+    let mut next_file = 0;
+    let mut char_offset = 0;
+    for i in 0..lines.len() {
+        if next_file < files.len() && char_offset >= files[next_file].offset {
+            current = Some((next_file, 0));
+            next_file += 1;
+            owners.push(None);
+        } else {
+            if let Some((_, n)) = current.as_mut() {
+                *n += 1;
+            }
+            owners.push(current);
+        }
+        let end = starts.get(i + 1).copied().unwrap_or(text.len());
+        char_offset += text[starts[i]..end].chars().count();
+    }
+    let file_of = |idx: usize| owners[idx].map(|(f, _)| f);
+    let line_no = |idx: usize| owners[idx].map(|(_, n)| n).unwrap_or(idx + 1);
+    let searchable = |idx: usize| match file_of(idx) {
+        Some(f) => included[f],
+        None => files.is_empty(),
+    };
+    let line_at = |byte: usize| starts.partition_point(|&s| s <= byte).saturating_sub(1);
+
+    // Matches as (first line, last line, matched text)
+    let mut spans: Vec<(usize, usize, &str)> = Vec::new();
+    if opts.multiline {
+        for m in re.find_iter(text) {
+            let first = line_at(m.start());
+            let last = line_at(m.end().saturating_sub(1).max(m.start()));
+            // Skip matches that straddle a file boundary or start in a header
+            if searchable(first) && file_of(first) == file_of(last) {
+                spans.push((first, last, m.as_str()));
+            }
+        }
+    } else {
+        for (idx, line) in lines.iter().enumerate() {
+            if !searchable(idx) {
+                continue;
+            }
+            if let Some(m) = re.find(line) {
+                spans.push((idx, idx, m.as_str()));
+            }
+        }
+    }
+
+    let mut hits = Vec::new();
+    let mut last_end: usize = 0; // avoid overlapping contexts
+    for (first, last, matched) in spans {
+        if hits.len() >= opts.max_results {
+            break;
+        }
+        // Keep context within the matched lines' file
+        let mut start = first.saturating_sub(opts.context).max(last_end).min(first);
+        while start < first && file_of(start) != file_of(first) {
+            start += 1;
+        }
+        let mut end = (last + opts.context + 1).min(lines.len());
+        while end > last + 1 && file_of(end - 1) != file_of(last) {
+            end -= 1;
+        }
+
+        let context = (start..end)
+            .map(|i| {
+                let marker = if (first..=last).contains(&i) {
+                    ">>"
+                } else {
+                    "  "
+                };
+                format!("{} L{}: {}", marker, line_no(i), lines[i])
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        hits.push(GrepHit {
+            line: line_no(first),
+            file: file_of(first).map(|f| files[f].path.clone()),
+            matched: matched.chars().take(MAX_MATCH_CHARS).collect(),
+            context,
+        });
+        last_end = end;
+    }
+
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Combined text and manifest for two files, as `render_files` builds them.
+    fn doc() -> (String, Vec<FileEntry>) {
+        let files = [
+            ("README.md", "intro\nStaking rewards are paid daily.\nend"),
+            ("docs/fees.mdx", "Fees\nthe fee is\ncharged per block\nfoo"),
+        ];
+        let mut text = String::new();
+        let mut entries = Vec::new();
+        for (path, body) in files {
+            let section = format!("=== {} ===\n{}\n\n", path, body);
+            entries.push(FileEntry {
+                path: path.to_string(),
+                size: body.len(),
+                language: None,
+                offset: text.chars().count(),
+                len: section.chars().count(),
+            });
+            text.push_str(&section);
+        }
+        (text, entries)
+    }
+
+    fn run(pattern: &str, opts: GrepOptions) -> Vec<GrepHit> {
+        let (text, files) = doc();
+        let re = compile(pattern, &opts).unwrap();
+        search(&text, &files, &re, &opts).unwrap()
+    }
+
+    #[test]
+    fn test_regex_alternation_and_inline_flags() {
+        let hits = run(r"(?i)staking|FEE\b", GrepOptions::default());
+        let found: Vec<_> = hits.iter().map(|h| (h.file.as_deref(), h.line)).collect();
+        assert_eq!(
+            found,
+            vec![(Some("README.md"), 2), (Some("docs/fees.mdx"), 2)]
+        );
+        assert_eq!(hits[0].matched, "Staking");
+    }
+
+    #[test]
+    fn test_context_stays_within_file() {
+        let hits = run(
+            "intro",
+            GrepOptions {
+                context: 5,
+                ..Default::default()
+            },
+        );
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].context.starts_with(">> L1: intro"));
+        assert!(!hits[0].context.contains("Fees"));
+    }
+
+    #[test]
+    fn test_file_glob_filter() {
+        let opts = GrepOptions {
+            files: vec!["*.mdx".to_string()],
+            ignore_case: true,
+            ..Default::default()
+        };
+        let hits = run("fee", opts);
+        assert!(hits
+            .iter()
+            .all(|h| h.file.as_deref() == Some("docs/fees.mdx")));
+        assert_eq!(hits.len(), 2);
+
+        let opts = GrepOptions {
+            files: vec!["docs/**".to_string()],
+            ..Default::default()
+        };
+        assert!(run("rewards", opts).is_empty());
+    }
+
+    #[test]
+    fn test_search_one_file() {
+        let filter = FileFilter::new(&["docs/*".to_string(), "*.md".to_string()]).unwrap();
+        assert!(filter.includes("README.md"));
+        assert!(filter.includes("docs/fees.mdx"));
+        assert!(!filter.includes("docs/api/fees.mdx"));
+        assert!(FileFilter::new(&[]).unwrap().includes("any/file.rs"));
+
+        // Same hits as searching the combined text; globs are the caller's
+        let opts = GrepOptions {
+            context: 1,
+            ..Default::default()
+        };
+        let combined = run("fee", opts.clone());
+        let opts = GrepOptions {
+            files: vec!["*.md".to_string()],
+            ..opts
+        };
+        let re = compile("fee", &opts).unwrap();
+        let body = "Fees\nthe fee is\ncharged per block\nfoo";
+        let hits = search_file("docs/fees.mdx", body, &re, &opts);
+        assert_eq!(hits, combined);
+        assert_eq!(
+            hits[0].context,
+            "   L1: Fees\n>> L2: the fee is\n   L3: charged per block"
+        );
+    }
+
+    #[test]
+    fn test_multiline_spans_lines() {
+        assert!(run(r"fee is\s+charged", GrepOptions::default()).is_empty());
+        let hits = run(
+            r"fee is\s+charged",
+            GrepOptions {
+                multiline: true,
+                context: 0,
+                ..Default::default()
+            },
+        );
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].line, 2);
+        assert_eq!(
+            hits[0].context,
+            ">> L2: the fee is\n>> L3: charged per block"
+        );
+    }
+
+    #[test]
+    fn test_max_results() {
+        let opts = GrepOptions {
+            max_results: 1,
+            ..Default::default()
+        };
+        assert_eq!(run("e", opts).len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_pattern_and_glob() {
+        let err = compile("(unclosed", &GrepOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("invalid regex"), "{err}");
+
+        let (text, files) = doc();
+        let opts = GrepOptions {
+            files: vec!["[".to_string()],
+            ..Default::default()
+        };
+        let re = compile("x", &opts).unwrap();
+        let err = search(&text, &files, &re, &opts).unwrap_err();
+        assert!(err.to_string().contains("invalid file glob"), "{err}");
+    }
+}
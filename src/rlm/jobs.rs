@@ -0,0 +1,199 @@
+//! Registry of in-flight queries, so they can be listed and cancelled.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tokio::sync::watch;
+
+/// A running query as shown by `/edgar jobs list`.
+#[derive(Debug, Clone)]
+pub struct JobInfo {
+    pub id: u64,
+    pub guild: u64,
+    pub user_id: u64,
+    pub user_name: String,
+    pub topic: String,
+    pub question: String,
+    /// What the query is doing, e.g. "iteration 3/15"
+    pub phase: String,
+    pub started_at: i64,
+}
+
+struct Entry {
+    info: JobInfo,
+    cancel: watch::Sender<bool>,
+}
+
+/// In-flight queries by ID. Entries are added by `start` and removed when the
+/// returned handle is dropped, however the query ends.
+#[derive(Default)]
+pub struct Jobs {
+    next_id: AtomicU64,
+    running: Mutex<HashMap<u64, Entry>>,
+}
+
+impl Jobs {
+    fn running(&self) -> MutexGuard<'_, HashMap<u64, Entry>> {
+        self.running.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Register a query. It stays listed while the handle lives.
+    pub fn start(
+        self: &Arc<Self>,
+        guild: u64,
+        user_id: u64,
+        user_name: &str,
+        topic: &str,
+        question: &str,
+    ) -> JobHandle {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let (cancel, cancelled) = watch::channel(false);
+        let info = JobInfo {
+            id,
+            guild,
+            user_id,
+            user_name: user_name.to_string(),
+            topic: topic.to_string(),
+            question: question.to_string(),
+            phase: "starting".to_string(),
+            started_at: chrono::Utc::now().timestamp(),
+        };
+        self.running().insert(id, Entry { info, cancel });
+        JobHandle {
+            jobs: self.clone(),
+            id,
+            cancel: CancelToken(cancelled),
+        }
+    }
+
+    /// A guild's running queries, oldest first.
+    pub fn list(&self, guild: u64) -> Vec<JobInfo> {
+        let mut jobs: Vec<JobInfo> = self
+            .running()
+            .values()
+            .filter(|e| e.info.guild == guild)
+            .map(|e| e.info.clone())
+            .collect();
+        jobs.sort_by_key(|j| j.id);
+        jobs
+    }
+
+    pub fn get(&self, id: u64) -> Option<JobInfo> {
+        self.running().get(&id).map(|e| e.info.clone())
+    }
+
+    /// Ask a query to stop. Returns false if it isn't running.
+    pub fn cancel(&self, id: u64) -> bool {
+        match self.running().get(&id) {
+            Some(entry) => {
+                // Kept as the channel's value, so a task not yet waiting still sees it
+                entry.cancel.send_replace(true);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A registered query. Dropping it removes the query from the registry.
+pub struct JobHandle {
+    jobs: Arc<Jobs>,
+    id: u64,
+    cancel: CancelToken,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn set_phase(&self, phase: String) {
+        if let Some(entry) = self.jobs.running().get_mut(&self.id) {
+            entry.info.phase = phase;
+        }
+    }
+
+    /// Resolves once the query is cancelled.
+    pub async fn cancelled(&self) {
+        self.cancel.cancelled().await
+    }
+
+    /// A token the query's own tasks can watch for the cancellation.
+    pub fn token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+}
+
+impl Drop for JobHandle {
+    fn drop(&mut self) {
+        self.jobs.running().remove(&self.id);
+    }
+}
+
+/// Tells every task of a query that it was cancelled. Clones watch the same
+/// query; the default token is never cancelled.
+#[derive(Clone)]
+pub struct CancelToken(watch::Receiver<bool>);
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self(watch::channel(false).1)
+    }
+}
+
+impl CancelToken {
+    /// Resolves once the query is cancelled; never if it ends otherwise.
+    pub async fn cancelled(&self) {
+        let mut cancelled = self.0.clone();
+        if cancelled.wait_for(|cancelled| *cancelled).await.is_err() {
+            // The job's entry is gone without a cancel
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_register_cancel_and_drop() {
+        let jobs = Arc::new(Jobs::default());
+        let first = jobs.start(1, 10, "ann", "akash", "q1");
+        let second = jobs.start(2, 20, "bob", "cosmos", "q2");
+        assert_eq!(jobs.list(1).len(), 1);
+        assert_eq!(jobs.list(2)[0].user_name, "bob");
+
+        second.set_phase("iteration 2/15".to_string());
+        assert_eq!(jobs.get(second.id()).unwrap().phase, "iteration 2/15");
+
+        // Cancelling before anyone waits still wakes every later wait
+        let token = first.token();
+        assert!(jobs.cancel(first.id()));
+        let wait = std::time::Duration::from_secs(1);
+        tokio::time::timeout(wait, first.cancelled())
+            .await
+            .expect("cancel not delivered");
+        tokio::time::timeout(wait, token.cancelled())
+            .await
+            .expect("cancel not delivered to the token");
+
+        // A job that ends without a cancel never reports one
+        let token = second.token();
+        drop(second);
+        let never = std::time::Duration::from_millis(50);
+        let default = CancelToken::default();
+        assert!(tokio::time::timeout(never, token.cancelled())
+            .await
+            .is_err());
+        assert!(tokio::time::timeout(never, default.cancelled())
+            .await
+            .is_err());
+
+        let id = first.id();
+        drop(first);
+        assert!(jobs.get(id).is_none());
+        assert!(!jobs.cancel(id));
+    }
+}
@@ -1,20 +1,38 @@
 pub mod citations;
+pub mod context;
+pub mod conversation;
 pub mod exec;
+pub mod grep;
+pub mod jobs;
+pub mod progress;
 pub mod prompts;
 pub mod repl;
+pub mod scheduler;
+pub mod tools;
+pub mod usage;
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use tracing::{debug, info, warn};
 
-use crate::docs::types::{DocMeta, QaRecord};
+use crate::docs::types::{
+    AnsweredThread, DocMeta, PhaseUsage, QaRecord, QueryOutcome, Scope, ScopeView,
+};
 use crate::docs::DocumentStore;
-use crate::llm::{LlmClient, Message};
-
-use exec::PersistentSession;
+use crate::llm::{LlmClient, Message, ToolCall};
+use crate::state::RlmConfig;
+
+use context::{truncate, ContextBudget, REPL_OUTPUT_HEADER};
+use conversation::{rank_related, render_history, render_related, FollowUp, Turn, RELATED_THREADS};
+use exec::{ExecLimits, PersistentSession};
+use jobs::CancelToken;
+use progress::{Progress, ProgressSink};
 use repl::Command;
+use usage::{Phase, UsageMeter};
 
 /// Patterns that indicate the LLM refused to engage or produced a non-answer.
 const BROKEN_ANSWER_PATTERNS: &[&str] = &[
@@ -38,6 +56,21 @@ const BROKEN_ANSWER_PATTERNS: &[&str] = &[
     "not contain content related",
 ];
 
+/// Position of the question among an exploration loop's messages; it is never
+/// compacted.
+const QUESTION_MESSAGE: usize = 3;
+/// Chars of REPL output shown to the model per response.
+const REPL_OUTPUT_CHARS: usize = 4000;
+/// Shortest FINAL answer a nested `rlm_query()` loop accepts.
+const NESTED_MIN_ANSWER: usize = 50;
+/// Most `rlm_query()` calls one query makes, across all its loops.
+const MAX_NESTED_QUERIES: u32 = 8;
+/// Wall-clock limit of one `rlm_query()` loop. The REPL timeout of the calling
+/// code is paused while it runs.
+const NESTED_LOOP_TIMEOUT: Duration = Duration::from_secs(300);
+/// Tokens kept spare when sizing the evidence for a max-iteration synthesis.
+const SYNTHESIS_RESERVE: usize = 512;
+
 const STOP_WORDS: &[&str] = &[
     "what", "which", "where", "when", "does", "have", "with", "that", "this", "from", "about",
     "some", "there", "their", "they", "your", "been", "were", "how", "could", "would", "should",
@@ -54,6 +87,32 @@ pub struct RlmResponse {
     pub evidence: Vec<String>,
     /// Public URLs extracted from markdown links in the answer
     pub cited_urls: Vec<String>,
+    /// The question as researched, when a follow-up was rewritten to stand alone
+    pub standalone_question: Option<String>,
+    /// (doc_id, path) of files read via `read_file()`
+    pub files_read: Vec<(String, String)>,
+    /// REPL session a follow-up can continue in (unset if `reuse_session` is off)
+    pub session: Option<Arc<PersistentSession>>,
+    /// LLM usage by phase
+    pub usage: BTreeMap<String, PhaseUsage>,
+    /// Wall-clock time the query took
+    pub duration_ms: u64,
+}
+
+/// Who is asking and what a query reports to, beyond the question itself.
+#[derive(Default)]
+pub struct QueryContext {
+    /// Discord user asking, kept with the Q/A record for usage accounting
+    pub user_id: u64,
+    /// Earlier turns and related threads; empty for a fresh question
+    pub followup: FollowUp,
+    pub progress: ProgressSink,
+    /// Stops the query's nested `rlm_query()` loops and `llm_query_batch()`
+    /// calls mid-tool; dropping the query stops the rest
+    pub cancel: CancelToken,
+    /// Where the query's LLM calls are recorded. The caller keeps a clone to
+    /// account for a query it cancels or that fails.
+    pub meter: UsageMeter,
 }
 
 /// Extract URLs from markdown links `[text](url)` in the answer text.
@@ -80,6 +139,54 @@ enum ExplorationStrategy {
     Deep,
 }
 
+/// How an exploration loop reads the model's next step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LoopDriver {
+    /// ```repl blocks and FINAL(...) in the reply text
+    Fenced,
+    /// Native `run_python` and `final_answer` tool calls, falling back to the
+    /// reply text when the model answers without calling a tool
+    ToolCalls,
+}
+
+impl LoopDriver {
+    fn for_phase(llm: &LlmClient, phase: Phase) -> Self {
+        if llm.tool_calling(phase) {
+            LoopDriver::ToolCalls
+        } else {
+            LoopDriver::Fenced
+        }
+    }
+
+    /// How to hand in an answer, appended to instructions.
+    fn final_instruction(self) -> &'static str {
+        match self {
+            LoopDriver::Fenced => "Wrap in FINAL(...).",
+            LoopDriver::ToolCalls => "Give it with the final_answer tool.",
+        }
+    }
+}
+
+/// The model's next step in an exploration loop.
+struct Step {
+    command: Command,
+    /// The assistant turn to record in the conversation
+    message: Message,
+    /// Set when the step came from a tool call, which must be answered with a
+    /// `tool` message
+    call_id: Option<String>,
+}
+
+impl Step {
+    /// The message that answers this step with `content`.
+    fn reply(&self, content: impl Into<String>) -> Message {
+        match &self.call_id {
+            Some(id) => Message::tool(id, content),
+            None => Message::user(content),
+        }
+    }
+}
+
 /// Internal result from a single exploration loop — not exposed publicly.
 struct LoopResult {
     answer: String,
@@ -90,6 +197,137 @@ struct LoopResult {
     was_final: bool,
     /// The sub-question this loop investigated (None for atomic/single-loop queries).
     sub_question: Option<String>,
+    /// (doc_id, path) of files this loop read
+    files_read: Vec<(String, String)>,
+    session: Arc<PersistentSession>,
+}
+
+/// Context for one exploration loop beyond its question.
+#[derive(Default)]
+struct LoopContext<'a> {
+    /// Set when the loop is a focused sub-investigation of this question
+    original_question: Option<&'a str>,
+    /// Earlier turns of the thread's conversation, rendered for the prompt
+    history: Option<&'a str>,
+    /// Other answered threads on the topic, rendered for the prompt
+    related: Option<&'a str>,
+    /// Session to continue in instead of spawning a fresh one
+    session: Option<Arc<PersistentSession>>,
+    /// Position among the query's loops, for progress events
+    index: usize,
+    progress: ProgressSink,
+    /// Where the loop's LLM calls (and its session's `llm_query()`) are billed
+    meter: UsageMeter,
+    /// Cancellation of the query, seen by its session's long tools
+    cancel: CancelToken,
+    nesting: NestingBudget,
+}
+
+/// Where a loop sits among `rlm_query()` nesting, and the budgets nested loops
+/// get.
+#[derive(Debug, Clone, Default)]
+struct NestingBudget {
+    /// 0 for a top-level loop
+    depth: u32,
+    /// `rlm_query()` is refused in loops this deep
+    max_depth: u32,
+    /// Iterations of each nested loop
+    iterations: u32,
+    /// `rlm_query()` calls the query has left, shared by all its loops
+    calls_left: Arc<AtomicU32>,
+}
+
+impl NestingBudget {
+    /// The budget of a top-level loop of a fresh query.
+    fn new(max_depth: u32, iterations: u32) -> Self {
+        Self {
+            depth: 0,
+            max_depth,
+            iterations,
+            calls_left: Arc::new(AtomicU32::new(MAX_NESTED_QUERIES)),
+        }
+    }
+
+    fn deeper(&self) -> Self {
+        Self {
+            depth: self.depth + 1,
+            ..self.clone()
+        }
+    }
+
+    /// Count one `rlm_query()` call against the query's budget. False once
+    /// it is spent.
+    fn take_call(&self) -> bool {
+        self.calls_left
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                left.checked_sub(1)
+            })
+            .is_ok()
+    }
+}
+
+/// Runs the nested exploration loops of a session's `rlm_query()` calls.
+#[derive(Clone)]
+pub struct SubRlm {
+    engine: RlmEngine,
+    topic: String,
+    /// Nesting of the loop that owns the session
+    nesting: NestingBudget,
+}
+
+impl SubRlm {
+    fn can_nest(&self) -> bool {
+        self.nesting.depth < self.nesting.max_depth
+    }
+
+    /// Count one call against the query's `rlm_query()` budget. False once it
+    /// is spent.
+    fn take_call(&self) -> bool {
+        self.nesting.take_call()
+    }
+
+    /// Investigate `question` over `docs` in a fresh session, one level deeper.
+    /// Gives up after `NESTED_LOOP_TIMEOUT` or once `cancel` fires.
+    async fn run(
+        &self,
+        docs: &[DocMeta],
+        question: &str,
+        meter: UsageMeter,
+        cancel: CancelToken,
+    ) -> Result<LoopResult> {
+        let nesting = self.nesting.deeper();
+        info!(
+            depth = nesting.depth,
+            doc_count = docs.len(),
+            question, "Starting nested rlm_query() loop"
+        );
+        let iterations = nesting.iterations.max(1);
+        let run = self.engine.run_exploration_loop(
+            docs,
+            &self.topic,
+            question,
+            iterations,
+            1,
+            NESTED_MIN_ANSWER,
+            ExplorationStrategy::Broad,
+            LoopContext {
+                meter,
+                cancel: cancel.clone(),
+                nesting,
+                ..Default::default()
+            },
+        );
+        tokio::select! {
+            result = tokio::time::timeout(NESTED_LOOP_TIMEOUT, run) => match result {
+                Ok(result) => result,
+                Err(_) => anyhow::bail!(
+                    "nested investigation timed out after {}s",
+                    NESTED_LOOP_TIMEOUT.as_secs()
+                ),
+            },
+            _ = cancel.cancelled() => anyhow::bail!("query cancelled"),
+        }
+    }
 }
 
 /// Parse decomposition LLM response into sub-questions.
@@ -138,6 +376,53 @@ fn parse_decomposition(response: &str) -> Vec<String> {
         .collect()
 }
 
+/// Report files the session read since `reported` and advance it.
+fn report_reads(
+    session: &PersistentSession,
+    progress: &ProgressSink,
+    index: usize,
+    reported: &mut usize,
+) {
+    let accessed = session.accessed_files();
+    if accessed.len() <= *reported {
+        return;
+    }
+    let files = accessed[*reported..]
+        .iter()
+        .map(|(_, path)| path.clone())
+        .collect();
+    *reported = accessed.len();
+    progress.emit(Progress::FilesRead { index, files });
+}
+
+/// REPL outputs of one response's code blocks as a message body, each capped
+/// so the reply as a whole stays near `REPL_OUTPUT_CHARS`.
+fn render_outputs(outputs: &[String]) -> String {
+    let cap = (REPL_OUTPUT_CHARS / outputs.len().max(1)).max(1000);
+    let render = |output: &String| {
+        if output.is_empty() {
+            "[No output — use print() to see results]".to_string()
+        } else if output.len() > cap {
+            format!(
+                "{}...\n[truncated, {} total chars — narrow your search or read smaller sections]",
+                truncate(output, cap),
+                output.len()
+            )
+        } else {
+            output.clone()
+        }
+    };
+    if outputs.len() == 1 {
+        return render(&outputs[0]);
+    }
+    outputs
+        .iter()
+        .enumerate()
+        .map(|(i, output)| format!("[Block {} of {}]\n{}", i + 1, outputs.len(), render(output)))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
 /// Combine evidence and URLs from multiple loop results, deduplicating.
 fn combine_loop_artifacts(results: &[LoopResult]) -> (Vec<String>, Vec<String>) {
     let mut combined_evidence: Vec<String> = Vec::new();
@@ -166,26 +451,45 @@ fn combine_loop_artifacts(results: &[LoopResult]) -> (Vec<String>, Vec<String>)
 pub struct RlmEngine {
     llm: Arc<LlmClient>,
     store: Arc<DocumentStore>,
+    limits: ExecLimits,
+    /// Prompt size a loop's conversation may reach
+    budget: ContextBudget,
+    driver: LoopDriver,
 }
 
 impl RlmEngine {
-    pub fn new(llm: Arc<LlmClient>, store: Arc<DocumentStore>) -> Self {
-        Self { llm, store }
+    pub fn new(llm: Arc<LlmClient>, store: Arc<DocumentStore>, limits: ExecLimits) -> Self {
+        // Loop conversations go to the exploration and synthesis routes
+        let reply_tokens = [Phase::Iteration, Phase::Synthesis]
+            .into_iter()
+            .map(|phase| llm.route(phase).max_tokens)
+            .max()
+            .unwrap_or_default();
+        let budget = ContextBudget::new(llm.context_tokens(), reply_tokens);
+        let driver = LoopDriver::for_phase(&llm, Phase::Iteration);
+        Self {
+            llm,
+            store,
+            limits,
+            budget,
+            driver,
+        }
     }
 
     /// Fire-and-forget Q/A storage. Logs errors but never fails the response.
     async fn store_qa_record(
         &self,
+        scope: Scope,
         topic: &str,
         question: &str,
         response: &RlmResponse,
         doc_ids: Vec<String>,
+        user_id: u64,
     ) {
-        let id = blake3::hash(format!("{}{}", topic, question).as_bytes())
-            .to_hex()
-            .to_string();
+        let timestamp = chrono::Utc::now().timestamp();
         let record = QaRecord {
-            id,
+            id: QaRecord::new_id(topic, question, timestamp),
+            scope,
             topic: topic.to_string(),
             question: question.to_string(),
             answer: response.answer.clone(),
@@ -193,15 +497,68 @@ impl RlmEngine {
             doc_ids,
             evidence: response.evidence.clone(),
             iterations: response.iterations,
-            timestamp: chrono::Utc::now().timestamp(),
+            timestamp,
+            user_id,
+            usage: response.usage.clone(),
+            duration_ms: response.duration_ms,
+            outcome: QueryOutcome::Answered,
         };
         if let Err(e) = self.store.store_qa(&record).await {
             warn!(error = %e, "Failed to store Q/A record");
         }
     }
 
+    /// Chat on `phase`'s route, recording the call's usage under `phase`.
+    async fn chat(&self, messages: &[Message], phase: Phase, meter: &UsageMeter) -> Result<String> {
+        let completion = self.llm.chat(messages, phase).await?;
+        meter.record(phase, completion.usage);
+        Ok(completion.content)
+    }
+
+    /// Ask `phase`'s model for a loop's next step, offering the REPL as tools
+    /// if that model is driven by tool calls. Only the first tool call is
+    /// acted on.
+    async fn next_step(
+        &self,
+        messages: &[Message],
+        phase: Phase,
+        meter: &UsageMeter,
+    ) -> Result<Step> {
+        let flattened;
+        let (messages, tools) = match LoopDriver::for_phase(&self.llm, phase) {
+            LoopDriver::ToolCalls => (messages, repl::tool_specs()),
+            // Tool turns from a tool-calling exploration model, shown as text
+            LoopDriver::Fenced if self.driver == LoopDriver::ToolCalls => {
+                flattened = repl::flatten_tool_turns(messages);
+                (&flattened[..], Vec::new())
+            }
+            LoopDriver::Fenced => (messages, Vec::new()),
+        };
+        let completion = self.llm.chat_with_tools(messages, &tools, phase).await?;
+        meter.record(phase, completion.usage);
+
+        if completion.tool_calls.len() > 1 {
+            debug!(
+                calls = completion.tool_calls.len(),
+                "Model made several tool calls — running the first"
+            );
+        }
+        match completion.tool_calls.into_iter().next() {
+            Some(call) => Ok(Step {
+                command: Command::from_tool_call(&call),
+                call_id: Some(call.id.clone()),
+                message: Message::tool_call(completion.content, call),
+            }),
+            None => Ok(Step {
+                command: Command::parse(&completion.content),
+                message: Message::assistant(completion.content),
+                call_id: None,
+            }),
+        }
+    }
+
     /// Extract search terms from a question — handles hyphenated phrases and filters stop words.
-    fn extract_keywords(question: &str) -> Vec<String> {
+    pub(crate) fn extract_keywords(question: &str) -> Vec<String> {
         let mut keywords = Vec::new();
 
         for word in question.split_whitespace() {
@@ -235,98 +592,188 @@ impl RlmEngine {
         keywords
     }
 
-    /// Build a broad bootstrap: search_document + file scan + read best match (3000 chars).
+    /// Build a broad bootstrap: search_document + file scan over every document in
+    /// the topic, ranked together, then read the best match (3000 chars).
     fn build_bootstrap_code(docs: &[DocMeta], question: &str) -> String {
-        let doc_id = &docs[0].id;
+        let doc_ids: Vec<&str> = docs.iter().map(|d| d.id.as_str()).collect();
+        let doc_ids = format!("{:?}", doc_ids);
         let keywords = Self::extract_keywords(question);
 
-        // Use search_document (single scan, OR keyword matching, ranked by overlap)
+        // Use search_document (BM25 over the inverted index, file paths boosted)
         // instead of N separate grep calls
         let search_query = keywords.join(" ");
 
         format!(
-            r#"doc_id = "{doc_id}"
+            r#"doc_ids = {doc_ids}
+names = {{d["doc_id"]: d["name"] for d in documents}}
+keywords = {keywords}
 
-# Search for relevant content (single pass, ranked by keyword overlap)
-results = search_document(doc_id, "{search_query}", 5)
-print(f"=== {{len(results)}} search results for: {search_query} ===")
-for r in results:
-    print(f"\n[offset={{r['offset']}}, matches={{r['match_count']}}]")
+# Search every document (BM25-ranked) and rank the hits together
+results = []
+for did in doc_ids:
+    results += search_document(did, "{search_query}", 5)
+results.sort(key=lambda r: -r["score"])
+print(f"=== {{len(results)}} search results across {{len(doc_ids)}} documents for: {search_query} ===")
+for r in results[:8]:
+    print(f"\n[{{names[r['doc_id']]}}: {{r['file'] or 'offset=' + str(r['offset'])}}, score={{r['score']}}, matches={{r['match_count']}}]")
     print(r["content"])
 print()
 
-# Show relevant files by name
-files = list_files(doc_id)
-keywords = {keywords}
-relevant = [(sum(1 for k in keywords if k in f["name"].lower()), f) for f in files]
-relevant = [(s,f) for s,f in relevant if s > 0]
-relevant.sort(key=lambda x: -x[0])
-print(f"=== {{len(files)}} total files, {{len(relevant)}} match keywords by name ===")
-for score, f in relevant[:10]:
-    print(f"  [offset={{f['offset']}}] {{f['name']}}")
-
-# Auto-read the best matching file
+# Which documents are relevant
+best_score = {{}}
+for r in results:
+    best_score[r["doc_id"]] = max(best_score.get(r["doc_id"], 0), r["score"])
+print(f"=== {{len(best_score)}} of {{len(doc_ids)}} documents relevant ===")
+for did in sorted(best_score, key=lambda d: -best_score[d]):
+    print(f"  {{names[did]}} (doc_id={{did}}): best score {{best_score[did]}}")
+
+# Show relevant files by name, across all documents
+file_score = {{}}
+for r in results:
+    if r["file"]:
+        key = (r["doc_id"], r["file"])
+        file_score[key] = max(file_score.get(key, 0), r["score"])
+relevant = []
+total_files = 0
+for did in doc_ids:
+    files = list_files(did)
+    total_files += len(files)
+    for f in files:
+        score = sum(1 for k in keywords if k in f["name"].lower())
+        if score > 0:
+            relevant.append((score, file_score.get((did, f["name"]), 0), did, f))
+relevant.sort(key=lambda x: (-x[0], -x[1]))
+print(f"\n=== {{total_files}} total files, {{len(relevant)}} match keywords by name ===")
+for score, _, did, f in relevant[:10]:
+    print(f"  [{{names[did]}}, offset={{f['offset']}}] {{f['name']}}")
+
+# Auto-read the best matching file in the corpus: by name, else the file
+# holding the best search result
+best_doc, best = None, None
 if relevant:
-    best = relevant[0][1]["name"]
-    print(f"\n=== Reading: {{best}} ===")
-    content = read_file(doc_id, best)
+    best_doc, best = relevant[0][2], relevant[0][3]["name"]
+elif results and results[0]["file"]:
+    best_doc, best = results[0]["doc_id"], results[0]["file"]
+if best:
+    print(f"\n=== Reading: {{best}} ({{names[best_doc]}}) ===")
+    content = read_file(best_doc, best)
     print(content[:3000])
     if len(content) > 3000:
         print(f"... [{{len(content)}} total chars]")
 elif results:
-    # No filename match — read around the best search result
+    # No file to read — read around the best search result
     best_offset = max(results[0]["offset"] - 500, 0)
-    print(f"\n=== Content around best match (offset {{best_offset}}) ===")
-    print(get_section(doc_id, best_offset, 3000))
+    print(f"\n=== Content around best match ({{names[results[0]['doc_id']]}}, offset {{best_offset}}) ===")
+    print(get_section(results[0]["doc_id"], best_offset, 3000))
 "#,
-            doc_id = doc_id,
+            doc_ids = doc_ids,
             search_query = search_query,
             keywords = format!("{:?}", keywords),
         )
     }
 
-    /// Build a deep bootstrap: grep with high context + read best match at 6000 chars.
+    /// Build a deep bootstrap: grep with high context in every document of the
+    /// topic, ranked together, then read the best match at 6000 chars.
     fn build_deep_bootstrap_code(docs: &[DocMeta], question: &str) -> String {
-        let doc_id = &docs[0].id;
+        let doc_ids: Vec<&str> = docs.iter().map(|d| d.id.as_str()).collect();
+        let doc_ids = format!("{:?}", doc_ids);
         let keywords = Self::extract_keywords(question);
-        let grep_pattern = keywords.join("|");
+        // Without keywords the pattern would be a bare `(?i)` matching every
+        // line; show each document's files to pick from instead
+        if keywords.is_empty() {
+            return format!(
+                r#"doc_ids = {doc_ids}
+names = {{d["doc_id"]: d["name"] for d in documents}}
+
+# No keywords to grep for — list each document's files, or show the start of
+# a document that has none
+for did in doc_ids:
+    files = list_files(did)
+    print(f"=== {{names[did]}} (doc_id={{did}}): {{len(files)}} files ===")
+    for f in files[:20]:
+        print(f"  [offset={{f['offset']}}] {{f['name']}}")
+    if len(files) > 20:
+        print(f"  ... {{len(files) - 20}} more")
+    if not files:
+        print(get_section(did, 0, 2000))
+"#,
+                doc_ids = doc_ids,
+            );
+        }
+        let grep_pattern = keywords
+            .iter()
+            .map(|k| regex::escape(k))
+            .collect::<Vec<_>>()
+            .join("|");
 
         format!(
-            r#"doc_id = "{doc_id}"
+            r#"doc_ids = {doc_ids}
+names = {{d["doc_id"]: d["name"] for d in documents}}
+keywords = {keywords}
 
-# Deep grep with high context on top keywords
-hits = grep(doc_id, r"(?i){grep_pattern}", 8, 30)
-print(f"=== {{len(hits)}} grep hits for: {grep_pattern} ===")
+# Deep grep with high context on top keywords, in every document; rank hits by
+# how many distinct keywords their context covers
+hits = []
+for did in doc_ids:
+    for h in grep(did, r"(?i){grep_pattern}", 8, 30):
+        text = h["context"].lower()
+        h["doc_id"] = did
+        h["score"] = sum(1 for k in keywords if k in text)
+        hits.append(h)
+hits.sort(key=lambda h: -h["score"])
+print(f"=== {{len(hits)}} grep hits across {{len(doc_ids)}} documents for: {grep_pattern} ===")
 for h in hits[:10]:
-    print(f"\n[line {{h['line']}}]")
+    print(f"\n[{{names[h['doc_id']]}}: {{h['file'] or h['doc_id']}} line {{h['line']}}]")
     print(h["context"])
 
-# List files to find best match
-files = list_files(doc_id)
-keywords = {keywords}
-relevant = [(sum(1 for k in keywords if k in f["name"].lower()), f) for f in files]
-relevant = [(s,f) for s,f in relevant if s > 0]
-relevant.sort(key=lambda x: -x[0])
-print(f"\n=== {{len(files)}} total files, {{len(relevant)}} match keywords ===")
-for score, f in relevant[:5]:
-    print(f"  [offset={{f['offset']}}] {{f['name']}}")
-
-# Deep-read the best matching file (6000 chars)
+# Which documents are relevant
+doc_hits = {{}}
+for h in hits:
+    doc_hits[h["doc_id"]] = doc_hits.get(h["doc_id"], 0) + 1
+print(f"\n=== {{len(doc_hits)}} of {{len(doc_ids)}} documents relevant ===")
+for did in sorted(doc_hits, key=lambda d: -doc_hits[d]):
+    print(f"  {{names[did]}} (doc_id={{did}}): {{doc_hits[did]}} hits")
+
+# List files across all documents to find the best match
+file_score = {{}}
+for h in hits:
+    if h["file"]:
+        key = (h["doc_id"], h["file"])
+        file_score[key] = file_score.get(key, 0) + h["score"]
+relevant = []
+total_files = 0
+for did in doc_ids:
+    files = list_files(did)
+    total_files += len(files)
+    for f in files:
+        score = sum(1 for k in keywords if k in f["name"].lower())
+        if score > 0:
+            relevant.append((score, file_score.get((did, f["name"]), 0), did, f))
+relevant.sort(key=lambda x: (-x[0], -x[1]))
+print(f"\n=== {{total_files}} total files, {{len(relevant)}} match keywords ===")
+for score, _, did, f in relevant[:5]:
+    print(f"  [{{names[did]}}, offset={{f['offset']}}] {{f['name']}}")
+
+# Deep-read the best matching file in the corpus (6000 chars): by name, else
+# the file holding the best grep hit
+best_doc, best = None, None
 if relevant:
-    best = relevant[0][1]["name"]
-    print(f"\n=== Deep reading: {{best}} ===")
-    content = read_file(doc_id, best)
+    best_doc, best = relevant[0][2], relevant[0][3]["name"]
+elif hits and hits[0]["file"]:
+    best_doc, best = hits[0]["doc_id"], hits[0]["file"]
+if best:
+    print(f"\n=== Deep reading: {{best}} ({{names[best_doc]}}) ===")
+    content = read_file(best_doc, best)
     print(content[:6000])
     if len(content) > 6000:
         print(f"... [{{len(content)}} total chars]")
 elif hits:
     # Read around the best grep hit
-    best_line = hits[0]["line"]
-    approx_offset = max(best_line * 80 - 1000, 0)
-    print(f"\n=== Content around best hit (approx offset {{approx_offset}}) ===")
-    print(get_section(doc_id, approx_offset, 6000))
+    approx_offset = max(hits[0]["line"] * 80 - 1000, 0)
+    print(f"\n=== Content around best hit ({{names[hits[0]['doc_id']]}}, approx offset {{approx_offset}}) ===")
+    print(get_section(hits[0]["doc_id"], approx_offset, 6000))
 "#,
-            doc_id = doc_id,
+            doc_ids = doc_ids,
             grep_pattern = grep_pattern,
             keywords = format!("{:?}", keywords),
         )
@@ -341,6 +788,7 @@ elif hits:
         question: &str,
         topic_docs: &[DocMeta],
         max_subs: u32,
+        meter: &UsageMeter,
     ) -> Result<Vec<String>> {
         let doc_names: Vec<String> = topic_docs
             .iter()
@@ -348,22 +796,16 @@ elif hits:
             .collect();
 
         let messages = vec![
-            Message {
-                role: "system".to_string(),
-                content: prompts::DECOMPOSE_PROMPT.to_string(),
-            },
-            Message {
-                role: "user".to_string(),
-                content: format!(
-                    "Available documents: {}\nMaximum sub-questions: {}\n\nQuestion: {}",
-                    doc_names.join(", "),
-                    max_subs,
-                    question,
-                ),
-            },
+            Message::system(prompts::DECOMPOSE_PROMPT),
+            Message::user(format!(
+                "Available documents: {}\nMaximum sub-questions: {}\n\nQuestion: {}",
+                doc_names.join(", "),
+                max_subs,
+                question,
+            )),
         ];
 
-        let response = self.llm.chat(&messages, None).await?;
+        let response = self.chat(&messages, Phase::Decompose, meter).await?;
         debug!(response = %response, "Decomposition response");
 
         let subs = parse_decomposition(&response);
@@ -380,6 +822,38 @@ elif hits:
         Ok(subs)
     }
 
+    /// Rewrite a follow-up question so it stands alone, using the thread's
+    /// earlier turns. Returns None if it already stands alone or the rewrite fails.
+    async fn rewrite_followup(
+        &self,
+        history: &[Turn],
+        question: &str,
+        meter: &UsageMeter,
+    ) -> Option<String> {
+        let messages = vec![
+            Message::system(prompts::FOLLOWUP_PROMPT),
+            Message::user(format!(
+                "Conversation so far:\n{}\n\nLatest message: {}",
+                render_history(history),
+                question,
+            )),
+        ];
+
+        let response = match self.chat(&messages, Phase::Rewrite, meter).await {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Follow-up rewrite failed, using the question as asked: {e}");
+                return None;
+            }
+        };
+        let rewritten = response.trim().trim_matches('"').trim();
+        if rewritten.is_empty() || rewritten.eq_ignore_ascii_case(question.trim()) {
+            return None;
+        }
+        info!(question, rewritten, "Follow-up rewritten");
+        Some(rewritten.to_string())
+    }
+
     // ─── Phase 2: Exploration loop (single or sub) ───────────────────────
 
     /// Run a single exploration loop with its own Python session and strategy.
@@ -396,10 +870,43 @@ elif hits:
         min_code_executions: u32,
         min_answer_len: usize,
         strategy: ExplorationStrategy,
-        original_question: Option<&str>,
+        context: LoopContext<'_>,
     ) -> Result<LoopResult> {
-        let session =
-            PersistentSession::spawn(self.store.clone(), self.llm.clone(), topic_docs.to_vec());
+        let LoopContext {
+            original_question,
+            history,
+            related,
+            session,
+            index,
+            progress,
+            meter,
+            cancel,
+            nesting,
+        } = context;
+        let session = session.unwrap_or_else(|| {
+            let sub_rlm = SubRlm {
+                engine: self.clone(),
+                topic: topic.to_string(),
+                nesting: nesting.clone(),
+            };
+            Arc::new(PersistentSession::spawn(
+                self.store.clone(),
+                self.llm.clone(),
+                topic_docs.to_vec(),
+                self.limits,
+                Some(sub_rlm),
+            ))
+        });
+        session.set_usage_meter(meter.clone());
+        session.set_cancel(cancel);
+        // A reused session's earlier reads belong to earlier answers
+        let files_before = session.accessed_files().len();
+        let mut files_reported = files_before;
+        progress.emit(Progress::LoopStarted {
+            index,
+            question: question.to_string(),
+            max_iterations,
+        });
 
         let doc_summary: Vec<String> = topic_docs
             .iter()
@@ -428,17 +935,38 @@ elif hits:
                 oq,
                 question,
             )
+        } else if nesting.depth > 0 {
+            prompts::NESTED_LOOP_APPENDIX.to_string()
         } else {
             String::new()
         };
 
+        let mut conversation_context = match history {
+            Some(history) => format!("{}\n{}\n", prompts::CONVERSATION_APPENDIX, history),
+            None => String::new(),
+        };
+        if let Some(related) = related {
+            conversation_context.push_str(&format!(
+                "{}\n{}\n",
+                prompts::RELATED_THREADS_APPENDIX,
+                related
+            ));
+        }
+
+        let driver_appendix = match self.driver {
+            LoopDriver::Fenced => "",
+            LoopDriver::ToolCalls => prompts::TOOL_CALLING_APPENDIX,
+        };
+
         let system_with_docs = format!(
-            "{}\n\nDocuments loaded for topic '{}':\n{}\n{}{}",
-            prompts::SYSTEM_PROMPT,
+            "{}\n\nDocuments loaded for topic '{}':\n{}\n{}{}{}{}",
+            prompts::system_prompt(),
             topic,
             doc_summary.join("\n"),
             strategy_appendix,
+            conversation_context,
             sub_loop_context,
+            driver_appendix,
         );
 
         // Strategy-specific bootstrap code (uses question keywords for search)
@@ -448,6 +976,11 @@ elif hits:
         };
 
         let bootstrap_output = session.execute(&bootstrap_code).await?;
+        progress.emit(Progress::CodeExecuted {
+            index,
+            output_len: bootstrap_output.len(),
+        });
+        report_reads(&session, &progress, index, &mut files_reported);
         debug!(
             ?strategy,
             is_sub = original_question.is_some(),
@@ -458,40 +991,49 @@ elif hits:
             debug!("  │ {}", line);
         }
 
-        let bootstrap_output_msg = if bootstrap_output.len() > 4000 {
+        let bootstrap_output_msg = if bootstrap_output.len() > REPL_OUTPUT_CHARS {
             format!(
                 "{}...\n[truncated, {} total chars — use grep() or read_file() for more]",
-                &bootstrap_output[..4000],
+                truncate(&bootstrap_output, REPL_OUTPUT_CHARS),
                 bootstrap_output.len()
             )
         } else {
             bootstrap_output.clone()
         };
 
-        let mut messages = vec![
-            Message {
-                role: "system".to_string(),
-                content: system_with_docs,
-            },
-            Message {
-                role: "assistant".to_string(),
-                content: format!(
+        // The bootstrap run is shown as the model's own first step, in the
+        // form the driver expects
+        let bootstrap_output_msg = format!("{}\n{}", REPL_OUTPUT_HEADER, bootstrap_output_msg);
+        let (bootstrap_step, bootstrap_reply) = match self.driver {
+            LoopDriver::Fenced => (
+                Message::assistant(format!(
                     "I'll start by reading the documents.\n\n```repl\n{}\n```",
                     bootstrap_code
+                )),
+                Message::user(bootstrap_output_msg),
+            ),
+            LoopDriver::ToolCalls => (
+                Message::tool_call(
+                    "I'll start by reading the documents.",
+                    ToolCall::new(
+                        "call_bootstrap",
+                        repl::RUN_PYTHON,
+                        &serde_json::json!({ "code": bootstrap_code }),
+                    ),
                 ),
-            },
-            Message {
-                role: "user".to_string(),
-                content: format!("[REPL Output]\n{}", bootstrap_output_msg),
-            },
-            Message {
-                role: "user".to_string(),
-                content: format!(
-                    "The REPL is working. Now answer this question using the document content above \
-                    and further searches as needed: {}",
-                    question
-                ),
-            },
+                Message::tool("call_bootstrap", bootstrap_output_msg),
+            ),
+        };
+
+        let mut messages = vec![
+            Message::system(system_with_docs),
+            bootstrap_step,
+            bootstrap_reply,
+            Message::user(format!(
+                "The REPL is working. Now answer this question using the document content above \
+                and further searches as needed: {}",
+                question
+            )),
         ];
 
         let mut code_executions = 1u32; // bootstrap counts as one
@@ -502,12 +1044,16 @@ elif hits:
 
         for i in 0..max_iterations {
             let iteration = i + 1;
-            let response = self.llm.chat(&messages, None).await?;
+            progress.emit(Progress::Iteration { index, iteration });
+            self.budget.fit(&mut messages, &[QUESTION_MESSAGE]);
+            let mut step = self.next_step(&messages, Phase::Iteration, &meter).await?;
+            let response = &step.message.content;
 
             debug!(
                 ?strategy,
                 iteration,
                 response_len = response.len(),
+                tool_call = step.call_id.is_some(),
                 "─── LLM Response ───"
             );
             for line in response.lines().take(50) {
@@ -517,10 +1063,26 @@ elif hits:
                 debug!("  │ ... ({} lines total)", response.lines().count());
             }
 
-            let cmd = Command::parse(&response);
-            debug!(?strategy, iteration, cmd = ?cmd, "Parsed command");
+            debug!(?strategy, iteration, cmd = ?step.command, "Parsed command");
+
+            // FINAL_VAR(name) answers with a variable the model built up
+            if let Command::FinalVar(name) = &step.command {
+                match session.read_variable(name).await {
+                    Ok(answer) => step.command = Command::Final(answer),
+                    Err(e) => {
+                        debug!(?strategy, iteration, error = %e, "FINAL_VAR not resolved");
+                        let reply = step.reply(format!(
+                            "Error: FINAL_VAR({}): {}. Assign your answer to that variable \
+                            as a string in code first, or give it directly with FINAL(...).",
+                            name, e
+                        ));
+                        messages.extend([step.message, reply]);
+                        continue;
+                    }
+                }
+            }
 
-            match cmd {
+            match &step.command {
                 Command::Final(answer) => {
                     // Gate 1: enough code executions?
                     if code_executions < min_code_executions {
@@ -528,19 +1090,13 @@ elif hits:
                             ?strategy,
                             iteration, code_executions, "FINAL rejected — not enough code runs"
                         );
-                        messages.push(Message {
-                            role: "assistant".to_string(),
-                            content: response,
-                        });
-                        messages.push(Message {
-                            role: "user".to_string(),
-                            content: format!(
-                                "You only ran {} code block(s). Read the actual document content first. \
-                                Use get_section(documents[0][\"doc_id\"], 0, 5000) to read the start, \
-                                then search for terms related to my question. Print everything you read.",
-                                code_executions
-                            ),
-                        });
+                        let reply = step.reply(format!(
+                            "You only ran {} code block(s). Read the actual document content first. \
+                            Use get_section(documents[0][\"doc_id\"], 0, 5000) to read the start, \
+                            then search for terms related to my question. Print everything you read.",
+                            code_executions
+                        ));
+                        messages.extend([step.message, reply]);
                         continue;
                     }
 
@@ -552,17 +1108,12 @@ elif hits:
                             answer_len = answer.len(),
                             "FINAL rejected — too short"
                         );
-                        messages.push(Message {
-                            role: "assistant".to_string(),
-                            content: response,
-                        });
-                        messages.push(Message {
-                            role: "user".to_string(),
-                            content: "Your answer is too brief. Include specific details from the \
-                                document content you read — quote file names, function signatures, \
-                                configuration fields, or other concrete information you found."
-                                .to_string(),
-                        });
+                        let reply = step.reply(
+                            "Your answer is too brief. Include specific details from the \
+                            document content you read — quote file names, function signatures, \
+                            configuration fields, or other concrete information you found.",
+                        );
+                        messages.extend([step.message, reply]);
                         continue;
                     }
 
@@ -574,16 +1125,21 @@ elif hits:
                         is_sub = original_question.is_some(),
                         "Loop complete"
                     );
+                    let answer = answer.clone();
                     let mut cited_urls = extract_cited_urls(&answer);
 
                     // Enforce citations: resolve URLs from files the LLM actually read
-                    let accessed = session.accessed_files();
+                    let accessed = session.accessed_files().split_off(files_before);
                     let extra = citations::resolve_citations(&accessed, topic_docs, &cited_urls);
                     if !extra.is_empty() {
                         debug!(extra_count = extra.len(), "Programmatic citations added");
                         cited_urls.extend(extra);
                     }
 
+                    progress.emit(Progress::LoopFinished {
+                        index,
+                        iterations: iteration,
+                    });
                     return Ok(LoopResult {
                         answer,
                         iterations: iteration,
@@ -591,73 +1147,77 @@ elif hits:
                         cited_urls,
                         was_final: true,
                         sub_question: original_question.map(|_| question.to_string()),
+                        files_read: accessed,
+                        session,
                     });
                 }
-                Command::RunCode(code) => {
-                    debug!(?strategy, iteration, "─── Executing Code ───");
-                    for line in code.lines() {
-                        debug!("  │ {}", line);
-                    }
-
-                    messages.push(Message {
-                        role: "assistant".to_string(),
-                        content: response,
-                    });
+                Command::RunCode(blocks) => {
+                    // Every block runs, in order; each output is reported on its own
+                    let mut outputs = Vec::with_capacity(blocks.len());
+                    for (b, code) in blocks.iter().enumerate() {
+                        debug!(
+                            ?strategy,
+                            iteration,
+                            block = b + 1,
+                            "─── Executing Code ───"
+                        );
+                        for line in code.lines() {
+                            debug!("  │ {}", line);
+                        }
 
-                    let output = session.execute(&code).await?;
-                    code_executions += 1;
+                        let output = session.execute(code).await?;
+                        code_executions += 1;
+                        progress.emit(Progress::CodeExecuted {
+                            index,
+                            output_len: output.len(),
+                        });
+                        report_reads(&session, &progress, index, &mut files_reported);
 
-                    debug!(
-                        ?strategy,
-                        iteration,
-                        code_executions,
-                        output_len = output.len(),
-                        "─── Code Output ───"
-                    );
-                    for line in output.lines().take(30) {
-                        debug!("  │ {}", line);
-                    }
-                    if output.lines().count() > 30 {
-                        debug!("  │ ... ({} lines total)", output.lines().count());
-                    }
+                        debug!(
+                            ?strategy,
+                            iteration,
+                            code_executions,
+                            output_len = output.len(),
+                            "─── Code Output ───"
+                        );
+                        for line in output.lines().take(30) {
+                            debug!("  │ {}", line);
+                        }
+                        if output.lines().count() > 30 {
+                            debug!("  │ ... ({} lines total)", output.lines().count());
+                        }
 
-                    // Collect substantive outputs as evidence (skip empty/error-only)
-                    if output.len() > 50 && !output.starts_with("Error:") {
-                        evidence.push(output.clone());
+                        // Collect substantive outputs as evidence (skip empty/error-only)
+                        if output.len() > 50 && !output.starts_with("Error:") {
+                            evidence.push(output.clone());
+                        }
+                        outputs.push(output);
                     }
 
-                    let output_msg = if output.is_empty() {
-                        "[No output — use print() to see results]".to_string()
-                    } else if output.len() > 4000 {
-                        format!(
-                            "{}...\n[truncated, {} total chars — narrow your search or read smaller sections]",
-                            &output[..4000],
-                            output.len()
-                        )
-                    } else {
-                        output
-                    };
-
-                    messages.push(Message {
-                        role: "user".to_string(),
-                        content: format!("[REPL Output]\n{}", output_msg),
-                    });
+                    let reply = step.reply(format!(
+                        "{}\n{}",
+                        REPL_OUTPUT_HEADER,
+                        render_outputs(&outputs)
+                    ));
+                    messages.extend([step.message, reply]);
                 }
-                Command::InvalidCommand => {
+                // FINAL_VAR was resolved to a Final above
+                Command::InvalidCommand | Command::FinalVar(_) => {
                     debug!(?strategy, iteration, "InvalidCommand — nudging");
-                    messages.push(Message {
-                        role: "assistant".to_string(),
-                        content: response,
-                    });
-                    messages.push(Message {
-                        role: "user".to_string(),
-                        content: format!(
-                            "I need you to write Python code to read the documents. Wrap code in \
-                            ```repl\\n...\\n```. There are {} document(s) in `documents`. \
-                            Try: print(get_section(documents[0][\"doc_id\"], 0, 5000))",
-                            topic_docs.len()
-                        ),
-                    });
+                    let how = match self.driver {
+                        LoopDriver::Fenced => "Wrap code in ```repl\\n...\\n```.",
+                        LoopDriver::ToolCalls => {
+                            "Call the run_python tool with your code, or final_answer with your answer."
+                        }
+                    };
+                    let reply = step.reply(format!(
+                        "I need you to write Python code to read the documents. {} \
+                        There are {} document(s) in `documents`. \
+                        Try: print(get_section(documents[0][\"doc_id\"], 0, 5000))",
+                        how,
+                        topic_docs.len()
+                    ));
+                    messages.extend([step.message, reply]);
                 }
             }
         }
@@ -672,19 +1232,25 @@ elif hits:
         );
 
         let answer = self
-            .synthesize_from_evidence(&mut messages, &evidence, question)
+            .synthesize_from_evidence(&mut messages, &evidence, question, &session, &meter)
+            .await?;
+        let answer = self
+            .validate_answer(answer, &evidence, question, &meter)
             .await?;
-        let answer = self.validate_answer(answer, &evidence, question).await?;
         let mut cited_urls = extract_cited_urls(&answer);
 
         // Enforce citations: resolve URLs from files the LLM actually read
-        let accessed = session.accessed_files();
+        let accessed = session.accessed_files().split_off(files_before);
         let extra = citations::resolve_citations(&accessed, topic_docs, &cited_urls);
         if !extra.is_empty() {
             debug!(extra_count = extra.len(), "Programmatic citations added (synthesis)");
             cited_urls.extend(extra);
         }
 
+        progress.emit(Progress::LoopFinished {
+            index,
+            iterations: max_iterations,
+        });
         Ok(LoopResult {
             answer,
             iterations: max_iterations,
@@ -692,6 +1258,8 @@ elif hits:
             cited_urls,
             was_final: false,
             sub_question: original_question.map(|_| question.to_string()),
+            files_read: accessed,
+            session,
         })
     }
 
@@ -705,6 +1273,7 @@ elif hits:
         question: &str,
         results: &[LoopResult],
         sources: Vec<String>,
+        meter: &UsageMeter,
     ) -> Result<RlmResponse> {
         // Build the findings document from all sub-loop results
         let mut findings = String::new();
@@ -719,31 +1288,20 @@ elif hits:
             if !r.evidence.is_empty() {
                 findings.push_str("**Key Evidence:**\n");
                 for (j, ev) in r.evidence.iter().take(3).enumerate() {
-                    let truncated = if ev.len() > 1500 {
-                        &ev[..1500]
-                    } else {
-                        ev.as_str()
-                    };
-                    findings.push_str(&format!("Evidence {}: {}\n\n", j + 1, truncated));
+                    findings.push_str(&format!("Evidence {}: {}\n\n", j + 1, truncate(ev, 1500)));
                 }
             }
             findings.push_str("---\n\n");
         }
 
         let messages = vec![
-            Message {
-                role: "system".to_string(),
-                content: prompts::SYNTHESIS_PROMPT.to_string(),
-            },
-            Message {
-                role: "user".to_string(),
-                content: format!(
-                    "**Original Question:** {}\n\n\
-                     **Sub-Investigations Completed:**\n\n{}\n\n\
-                     Synthesize a comprehensive answer. Wrap in FINAL(...).",
-                    question, findings,
-                ),
-            },
+            Message::system(prompts::SYNTHESIS_PROMPT),
+            Message::user(format!(
+                "**Original Question:** {}\n\n\
+                 **Sub-Investigations Completed:**\n\n{}\n\n\
+                 Synthesize a comprehensive answer. Wrap in FINAL(...).",
+                question, findings,
+            )),
         ];
 
         info!(
@@ -752,7 +1310,7 @@ elif hits:
             "Synthesizing from sub-investigations"
         );
 
-        let response = self.llm.chat(&messages, None).await?;
+        let response = self.chat(&messages, Phase::Synthesis, meter).await?;
         let answer = match Command::parse(&response) {
             Command::Final(a) => a,
             _ => response,
@@ -770,7 +1328,7 @@ elif hits:
         }
 
         let answer = self
-            .validate_answer(answer, &combined_evidence, question)
+            .validate_answer(answer, &combined_evidence, question, meter)
             .await?;
 
         // Also capture URLs from post-validation answer
@@ -781,6 +1339,12 @@ elif hits:
         }
 
         let iterations = results.iter().map(|r| r.iterations).max().unwrap_or(0);
+        let mut files_read: Vec<(String, String)> = Vec::new();
+        for file in results.iter().flat_map(|r| &r.files_read) {
+            if !files_read.contains(file) {
+                files_read.push(file.clone());
+            }
+        }
 
         Ok(RlmResponse {
             answer,
@@ -788,26 +1352,84 @@ elif hits:
             sources,
             evidence: combined_evidence,
             cited_urls: combined_urls,
+            standalone_question: None,
+            files_read,
+            session: None,
+            usage: BTreeMap::new(),
+            duration_ms: 0,
         })
     }
 
     // ─── Orchestrator ────────────────────────────────────────────────────
 
+    /// Threads in `view` that answered questions similar to `question` on the
+    /// topic, best first, leaving out `exclude` (the thread being asked in).
+    pub async fn related_threads(
+        &self,
+        view: &ScopeView,
+        topic: &str,
+        question: &str,
+        exclude: Option<u64>,
+    ) -> Vec<AnsweredThread> {
+        let threads = match self.store.list_threads(&view.scopes(), topic).await {
+            Ok(threads) => threads,
+            Err(e) => {
+                warn!(error = %e, "Failed to list answered threads");
+                return Vec::new();
+            }
+        };
+        let candidates = threads
+            .into_iter()
+            .filter(|t| Some(t.thread_id) != exclude)
+            .collect();
+        rank_related(candidates, question, RELATED_THREADS)
+    }
+
     /// Orchestrate the full RLM pipeline: decompose → parallel sub-loops → synthesize.
     ///
     /// For atomic questions (no decomposition), runs a single exploration loop directly.
     /// For decomposable questions, spawns focused sub-loops in parallel, then synthesizes
     /// their findings into a unified answer.
+    ///
+    /// The topic's documents come from every scope in `view`; the Q/A record is
+    /// kept in the guild's own scope.
+    ///
+    /// A follow-up (non-empty `context.followup.history`) is first rewritten to
+    /// stand alone, and the earlier turns are shown to the model. With
+    /// `reuse_session` the (first) loop continues in `followup.session` and the
+    /// response carries the session for the next turn. `followup.related`
+    /// threads are shown to every loop as earlier answers on the topic.
+    ///
+    /// Each phase, loop iteration, code run and file read is reported to
+    /// `context.progress`. Every LLM call's tokens and latency are summed by
+    /// phase into the response and the Q/A record, kept under `context.user_id`.
     pub async fn query(
         &self,
+        view: &ScopeView,
         topic: &str,
         question: &str,
-        max_iterations: u32,
-        min_code_executions: u32,
-        min_answer_len: usize,
-        parallel_loops: u32,
+        config: &RlmConfig,
+        context: QueryContext,
     ) -> Result<RlmResponse> {
-        let topic_docs = self.store.list_by_label(topic).await?;
+        let started = Instant::now();
+        let QueryContext {
+            user_id,
+            followup,
+            progress,
+            cancel,
+            meter,
+        } = context;
+        let &RlmConfig {
+            max_iterations,
+            min_code_executions,
+            min_answer_len,
+            parallel_loops,
+            reuse_session,
+            rlm_query_depth,
+            rlm_query_iterations,
+        } = config;
+        let nesting = NestingBudget::new(rlm_query_depth, rlm_query_iterations);
+        let topic_docs = self.store.list_topic(view, topic).await?;
         if topic_docs.is_empty() {
             return Ok(RlmResponse {
                 answer: format!(
@@ -818,22 +1440,47 @@ elif hits:
                 sources: vec![],
                 evidence: vec![],
                 cited_urls: vec![],
+                standalone_question: None,
+                files_read: vec![],
+                session: None,
+                usage: BTreeMap::new(),
+                duration_ms: 0,
             });
         }
 
+        let standalone_question = if followup.history.is_empty() {
+            None
+        } else {
+            self.rewrite_followup(&followup.history, question, &meter).await
+        };
+        let researched = standalone_question.clone();
+        let question = researched.as_deref().unwrap_or(question);
+        let history = (!followup.history.is_empty()).then(|| render_history(&followup.history));
+        let related = (!followup.related.is_empty()).then(|| render_related(&followup.related));
+
         let sources: Vec<String> = topic_docs.iter().map(|d| d.source.clone()).collect();
         let doc_ids: Vec<String> = topic_docs.iter().map(|d| d.id.clone()).collect();
 
+        // A session started on another set of documents (one was added,
+        // removed or hidden since) would serve stale ones; start over then
+        let session = followup.session.filter(|session| {
+            let current: HashSet<&str> = doc_ids.iter().map(String::as_str).collect();
+            reuse_session && session.doc_ids().collect::<HashSet<_>>() == current
+        });
+
         let max_subs = parallel_loops.max(1);
 
         // ── Phase 1: Decompose ──
         let sub_questions = self
-            .decompose_question(question, &topic_docs, max_subs)
+            .decompose_question(question, &topic_docs, max_subs, &meter)
             .await
             .unwrap_or_else(|e| {
                 warn!("Decomposition failed, falling back to atomic: {e}");
                 Vec::new()
             });
+        progress.emit(Progress::Decomposed {
+            sub_questions: sub_questions.clone(),
+        });
 
         if sub_questions.is_empty() {
             // ── Atomic: single exploration loop ──
@@ -852,7 +1499,17 @@ elif hits:
                     min_code_executions,
                     min_answer_len,
                     ExplorationStrategy::Broad,
-                    None,
+                    LoopContext {
+                        original_question: None,
+                        history: history.as_deref(),
+                        related: related.as_deref(),
+                        session,
+                        index: 0,
+                        progress,
+                        meter: meter.clone(),
+                        cancel,
+                        nesting,
+                    },
                 )
                 .await?;
 
@@ -862,8 +1519,13 @@ elif hits:
                 sources,
                 evidence: result.evidence,
                 cited_urls: result.cited_urls,
+                standalone_question,
+                files_read: result.files_read,
+                session: reuse_session.then_some(result.session),
+                usage: meter.snapshot(),
+                duration_ms: started.elapsed().as_millis() as u64,
             };
-            self.store_qa_record(topic, question, &response, doc_ids)
+            self.store_qa_record(view.home(), topic, question, &response, doc_ids, user_id)
                 .await;
             return Ok(response);
         }
@@ -884,6 +1546,8 @@ elif hits:
         );
 
         let mut tasks = tokio::task::JoinSet::new();
+        // The first sub-loop continues in the conversation's session
+        let mut session = session;
         for (i, sub_q) in sub_questions.iter().enumerate() {
             let strategy = strategies[i % strategies.len()];
             let engine = self.clone();
@@ -891,6 +1555,13 @@ elif hits:
             let sq = sub_q.clone();
             let oq = question.to_string();
             let t = topic.to_string();
+            let history = history.clone();
+            let related = related.clone();
+            let session = session.take();
+            let progress = progress.clone();
+            let meter = meter.clone();
+            let cancel = cancel.clone();
+            let nesting = nesting.clone();
             tasks.spawn(async move {
                 engine
                     .run_exploration_loop(
@@ -901,7 +1572,17 @@ elif hits:
                         min_code_executions,
                         sub_min_answer,
                         strategy,
-                        Some(&oq),
+                        LoopContext {
+                            original_question: Some(&oq),
+                            history: history.as_deref(),
+                            related: related.as_deref(),
+                            session,
+                            index: i,
+                            progress,
+                            meter,
+                            cancel,
+                            nesting,
+                        },
                     )
                     .await
             });
@@ -943,11 +1624,22 @@ elif hits:
         );
 
         // ── Phase 3: Synthesize ──
-        let response = self
-            .synthesize_findings(question, &results, sources)
+        progress.emit(Progress::Synthesizing);
+        let mut response = self
+            .synthesize_findings(question, &results, sources, &meter)
             .await?;
+        response.standalone_question = standalone_question;
+        if reuse_session {
+            let first = results
+                .iter()
+                .find(|r| r.sub_question.as_ref() == sub_questions.first())
+                .or(results.first());
+            response.session = first.map(|r| r.session.clone());
+        }
 
-        self.store_qa_record(topic, question, &response, doc_ids)
+        response.usage = meter.snapshot();
+        response.duration_ms = started.elapsed().as_millis() as u64;
+        self.store_qa_record(view.home(), topic, question, &response, doc_ids, user_id)
             .await;
         Ok(response)
     }
@@ -958,47 +1650,62 @@ elif hits:
         messages: &mut Vec<Message>,
         evidence: &[String],
         question: &str,
+        session: &PersistentSession,
+        meter: &UsageMeter,
     ) -> Result<String> {
-        if !evidence.is_empty() {
-            let evidence_summary = evidence
-                .iter()
-                .enumerate()
-                .map(|(i, e)| {
-                    let truncated = if e.len() > 2000 {
-                        &e[..2000]
-                    } else {
-                        e.as_str()
-                    };
-                    format!("--- Evidence {} ---\n{}", i + 1, truncated)
-                })
-                .collect::<Vec<_>>()
-                .join("\n\n");
-
-            messages.push(Message {
-                role: "user".to_string(),
-                content: format!(
-                    "Here is all the document content collected during this session:\n\n{}\n\n\
-                    Based ONLY on this evidence, answer the question: {}\n\
-                    Include specific details, names, and quotes from the text above. Wrap in FINAL(...).",
-                    evidence_summary, question
-                ),
-            });
+        // Keep as much evidence as fits beside the system prompt and question,
+        // the most recent first; older turns are compacted below to make room
+        let mut room = self
+            .budget
+            .room_after(&messages[..=QUESTION_MESSAGE], SYNTHESIS_RESERVE);
+        let mut sections = Vec::new();
+        for (i, e) in evidence.iter().enumerate().rev() {
+            let section = format!("--- Evidence {} ---\n{}", i + 1, truncate(e, 2000));
+            if section.len() > room {
+                debug!(
+                    kept = sections.len(),
+                    total = evidence.len(),
+                    "Evidence cut to fit the context window"
+                );
+                break;
+            }
+            room -= section.len();
+            sections.push(section);
+        }
+        sections.reverse();
+
+        let driver = LoopDriver::for_phase(&self.llm, Phase::Synthesis);
+        if !sections.is_empty() {
+            messages.push(Message::user(format!(
+                "Here is the document content collected during this session:\n\n{}\n\n\
+                Based ONLY on this evidence, answer the question: {}\n\
+                Include specific details, names, and quotes from the text above. {}",
+                sections.join("\n\n"),
+                question,
+                driver.final_instruction()
+            )));
         } else {
-            messages.push(Message {
-                role: "user".to_string(),
-                content: format!(
-                    "Summarize everything you found about: {}\nWrap in FINAL(...).",
-                    question
-                ),
-            });
+            messages.push(Message::user(format!(
+                "Summarize everything you found about: {}\n{}",
+                question,
+                driver.final_instruction()
+            )));
         }
 
-        let response = self.llm.chat(messages, None).await?;
-        debug!("Synthesized: {}", &response[..response.len().min(500)]);
+        self.budget.fit(messages, &[QUESTION_MESSAGE]);
+        let step = self.next_step(messages, Phase::Synthesis, meter).await?;
+        debug!("Synthesized: {}", truncate(&step.message.content, 500));
 
-        Ok(match Command::parse(&response) {
+        Ok(match step.command {
             Command::Final(a) => a,
-            _ => response,
+            Command::FinalVar(name) => match session.read_variable(&name).await {
+                Ok(a) => a,
+                Err(e) => {
+                    warn!(error = %e, "FINAL_VAR not resolved in synthesis");
+                    step.message.content
+                }
+            },
+            _ => step.message.content,
         })
     }
 
@@ -1009,6 +1716,7 @@ elif hits:
         answer: String,
         evidence: &[String],
         question: &str,
+        meter: &UsageMeter,
     ) -> Result<String> {
         // Check for known broken patterns
         let answer_lower = answer.to_lowercase();
@@ -1032,35 +1740,24 @@ elif hits:
                 .iter()
                 .take(5)
                 .enumerate()
-                .map(|(i, e)| {
-                    let truncated = if e.len() > 3000 {
-                        &e[..3000]
-                    } else {
-                        e.as_str()
-                    };
-                    format!("--- Source {} ---\n{}", i + 1, truncated)
-                })
+                .map(|(i, e)| format!("--- Source {} ---\n{}", i + 1, truncate(e, 3000)))
                 .collect::<Vec<_>>()
                 .join("\n\n");
 
             let rescue_messages = vec![
-                Message {
-                    role: "system".to_string(),
-                    content: "You are a helpful assistant. Answer the question using ONLY the \
-                        provided document excerpts. Be specific and quote the text directly."
-                        .to_string(),
-                },
-                Message {
-                    role: "user".to_string(),
-                    content: format!(
-                        "Document excerpts:\n\n{}\n\nQuestion: {}\n\n\
-                        Answer with specific details from the excerpts above.",
-                        evidence_text, question
-                    ),
-                },
+                Message::system("You are a helpful assistant. Answer the question using ONLY the \
+                    provided document excerpts. Be specific and quote the text directly."
+                    .to_string()),
+                Message::user(format!(
+                    "Document excerpts:\n\n{}\n\nQuestion: {}\n\n\
+                    Answer with specific details from the excerpts above.",
+                    evidence_text, question
+                )),
             ];
 
-            let rescue = self.llm.chat(&rescue_messages, None).await?;
+            let rescue = self
+                .chat(&rescue_messages, Phase::Validation, meter)
+                .await?;
             info!(rescue_len = rescue.len(), "Rescue answer generated");
 
             // Strip FINAL() wrapper if present
@@ -1083,6 +1780,19 @@ elif hits:
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_render_outputs() {
+        assert_eq!(render_outputs(&["42\n".to_string()]), "42\n");
+
+        let outputs = vec![String::new(), "x".repeat(3000), "done".to_string()];
+        let rendered = render_outputs(&outputs);
+        assert!(rendered.starts_with(
+            "[Block 1 of 3]\n[No output — use print() to see results]\n\n[Block 2 of 3]\n"
+        ));
+        assert!(rendered.contains("[truncated, 3000 total chars"));
+        assert!(rendered.ends_with("[Block 3 of 3]\ndone"));
+    }
+
     #[test]
     fn test_parse_decomposition_atomic() {
         assert!(parse_decomposition("ATOMIC").is_empty());
@@ -1121,4 +1831,198 @@ mod tests {
         let subs = parse_decomposition(input);
         assert!(subs.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_bootstrap_ranks_across_documents() {
+        use crate::docs::types::DocInfo;
+
+        let dir = std::env::temp_dir().join(format!("edgar-bootstrap-{}", std::process::id()));
+        let store = Arc::new(DocumentStore::new(&dir).await.unwrap());
+        let info = |name| DocInfo {
+            scope: Scope::Global,
+            name,
+            source: name,
+            label: "t",
+            url_context: None,
+            commit: None,
+        };
+        let sdk = vec![("README.md".to_string(), "The SDK wraps the chain API.".to_string())];
+        let provider = vec![
+            ("README.md".to_string(), "Provider services.".to_string()),
+            (
+                "docs/bid_pricing.md".to_string(),
+                "GPU bid pricing is computed per block from the GPU model.".to_string(),
+            ),
+        ];
+        let sdk = store.store_files(&sdk, &info("o/sdk")).await.unwrap();
+        let provider = store.store_files(&provider, &info("o/provider")).await.unwrap();
+        let docs = vec![
+            store.get_meta(&sdk).await.unwrap(),
+            store.get_meta(&provider).await.unwrap(),
+        ];
+        let llm = Arc::new(LlmClient::from_env().unwrap());
+        let session = PersistentSession::spawn(store, llm, docs.clone(), ExecLimits::default(), None);
+
+        let question = "How is GPU bid pricing computed?";
+        for (code, reading) in [
+            (RlmEngine::build_bootstrap_code(&docs, question), "=== Reading: docs/bid_pricing.md (o/provider) ==="),
+            (RlmEngine::build_deep_bootstrap_code(&docs, question), "=== Deep reading: docs/bid_pricing.md (o/provider) ==="),
+        ] {
+            let output = session.execute(&code).await.unwrap();
+            assert!(!output.contains("Error"), "{output}");
+            assert!(output.contains("across 2 documents"), "{output}");
+            assert!(output.contains("1 of 2 documents relevant"), "{output}");
+            assert!(output.contains(&format!("o/provider (doc_id={})", provider)), "{output}");
+            assert!(output.contains(reading), "{output}");
+        }
+
+        // A question without keywords lists files instead of grepping for everything
+        let code = RlmEngine::build_deep_bootstrap_code(&docs, "What is it?");
+        assert!(!code.contains("grep("));
+        let output = session.execute(&code).await.unwrap();
+        assert!(!output.contains("Error"), "{output}");
+        let listed = format!("o/provider (doc_id={}): 2 files", provider);
+        assert!(output.contains(&listed), "{output}");
+        assert!(output.contains("docs/bid_pricing.md"), "{output}");
+    }
+
+    /// A session whose `rlm_query()` runs nested loops against a mock LLM
+    /// answering each prompt with `reply(prompt)`, over two documents.
+    /// Returns the session and the provider document's ID.
+    async fn nesting_session<F>(
+        name: &str,
+        nesting: NestingBudget,
+        reply: F,
+    ) -> (PersistentSession, String)
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        use crate::docs::types::DocInfo;
+        use crate::llm::mock::{serve, test_client};
+
+        let dir =
+            std::env::temp_dir().join(format!("edgar-nested-{}-{}", name, std::process::id()));
+        let store = Arc::new(DocumentStore::new(&dir).await.unwrap());
+        let info = |name| DocInfo {
+            scope: Scope::Global,
+            name,
+            source: name,
+            label: "t",
+            url_context: None,
+            commit: None,
+        };
+        let sdk = vec![(
+            "README.md".to_string(),
+            "The SDK wraps the chain API.".to_string(),
+        )];
+        let provider = vec![(
+            "docs/bid_pricing.md".to_string(),
+            "GPU bid pricing is computed per block from the GPU model.".to_string(),
+        )];
+        let sdk = store.store_files(&sdk, &info("o/sdk")).await.unwrap();
+        let provider = store
+            .store_files(&provider, &info("o/provider"))
+            .await
+            .unwrap();
+        let docs = vec![
+            store.get_meta(&sdk).await.unwrap(),
+            store.get_meta(&provider).await.unwrap(),
+        ];
+        let llm = Arc::new(test_client(serve(reply).await));
+        let engine = RlmEngine::new(llm.clone(), store.clone(), ExecLimits::default());
+        let sub_rlm = SubRlm {
+            engine,
+            topic: "t".to_string(),
+            nesting,
+        };
+        let session =
+            PersistentSession::spawn(store, llm, docs, ExecLimits::default(), Some(sub_rlm));
+        (session, provider)
+    }
+
+    const NESTED_ANSWER: &str =
+        "FINAL(GPU bid pricing is computed per block from the GPU model, per docs/bid_pricing.md.)";
+
+    #[tokio::test]
+    async fn test_rlm_query_depth_and_doc_ids() {
+        // The nested loop tries to nest again, then answers
+        let (session, provider) = nesting_session("depth", NestingBudget::new(1, 3), |prompt| {
+            if prompt.contains("nesting limit reached") {
+                NESTED_ANSWER.to_string()
+            } else {
+                "```repl\nprint(rlm_query('How are bids placed?'))\n```".to_string()
+            }
+        })
+        .await;
+        let code = format!(
+            "r = rlm_query('How is GPU bid pricing computed?', doc_ids=['{}'])\n\
+             print(r['finished'], r['iterations'])\n\
+             print(r['answer'])\n\
+             print(r['evidence'][0])",
+            provider
+        );
+        let output = session.execute(&code).await.unwrap();
+        assert!(output.starts_with("True 2\nGPU bid pricing"), "{output}");
+        // Only the requested document was loaded
+        assert!(output.contains("across 1 documents"), "{output}");
+        assert!(!output.contains("o/sdk"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn test_rlm_query_iteration_and_call_limits() {
+        let nesting = NestingBudget {
+            calls_left: Arc::new(AtomicU32::new(1)),
+            ..NestingBudget::new(1, 2)
+        };
+        // The nested loop never answers, so it runs out of iterations
+        let (session, _) = nesting_session("limits", nesting, |prompt| {
+            if prompt.starts_with("The REPL is working") || prompt.starts_with(REPL_OUTPUT_HEADER) {
+                "```repl\nprint('still looking')\n```".to_string()
+            } else {
+                "Bid pricing is computed per block from the GPU model.".to_string()
+            }
+        })
+        .await;
+        let output = session
+            .execute("r = rlm_query('How is GPU bid pricing computed?')\nprint(r['finished'], r['iterations'])")
+            .await
+            .unwrap();
+        assert_eq!(output.trim(), "False 2");
+
+        // The query's one call is spent
+        let output = session
+            .execute("print(rlm_query('How are bids placed?'))")
+            .await
+            .unwrap();
+        assert!(output.contains("ValueError"), "{output}");
+        assert!(
+            output.contains("call limit of this question reached"),
+            "{output}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rlm_query_stops_on_cancel() {
+        let (session, _) = nesting_session("cancel", NestingBudget::new(1, 1000), |_| {
+            "```repl\nprint('still looking')\n```".to_string()
+        })
+        .await;
+        let jobs = Arc::new(jobs::Jobs::default());
+        let job = jobs.start(1, 1, "ann", "t", "q");
+        session.set_cancel(job.token());
+        let id = job.id();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            jobs.cancel(id);
+        });
+
+        let started = Instant::now();
+        let output = session
+            .execute("print(rlm_query('How is GPU bid pricing computed?'))")
+            .await
+            .unwrap();
+        assert!(output.contains("RuntimeError"), "{output}");
+        assert!(output.contains("query cancelled"), "{output}");
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
 }
@@ -0,0 +1,295 @@
+//! Progress events emitted while a query runs, and a log that renders them as
+//! a live status for Discord.
+
+use tokio::sync::mpsc;
+
+/// Longest sub-question or file list shown per status line, in chars.
+const LINE_CHARS: usize = 120;
+/// Files shown per loop (the most recent reads).
+const SHOWN_FILES: usize = 3;
+
+/// One step of a running query.
+#[derive(Debug, Clone)]
+pub enum Progress {
+    /// Waiting for a running slot; `position` 1 is next
+    Queued { position: usize },
+    /// Phase 1 result; empty for an atomic question
+    Decomposed { sub_questions: Vec<String> },
+    /// An exploration loop started (`index` 0 for an atomic question)
+    LoopStarted {
+        index: usize,
+        question: String,
+        max_iterations: u32,
+    },
+    /// The loop asked the model for its next step
+    Iteration { index: usize, iteration: u32 },
+    /// A code block ran in the loop's REPL
+    CodeExecuted { index: usize, output_len: usize },
+    /// Files read via `read_file()` by the last code block
+    FilesRead { index: usize, files: Vec<String> },
+    LoopFinished { index: usize, iterations: u32 },
+    /// Phase 3: combining sub-investigation findings
+    Synthesizing,
+}
+
+/// Where a query reports progress. The default sink drops every event.
+#[derive(Clone, Default)]
+pub struct ProgressSink(Option<mpsc::UnboundedSender<Progress>>);
+
+impl ProgressSink {
+    pub fn channel() -> (Self, mpsc::UnboundedReceiver<Progress>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Self(Some(tx)), rx)
+    }
+
+    /// Report an event. Never fails: a listener that went away is ignored.
+    pub fn emit(&self, event: Progress) {
+        if let Some(tx) = &self.0 {
+            let _ = tx.send(event);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LoopStatus {
+    question: String,
+    max_iterations: u32,
+    iteration: u32,
+    code_runs: u32,
+    /// Most recent last
+    files: Vec<String>,
+    finished: bool,
+}
+
+/// Accumulated progress of one query, rendered as status text.
+#[derive(Debug, Default)]
+pub struct ProgressLog {
+    /// Place in line while waiting to run
+    queued: Option<usize>,
+    /// None until decomposition finishes
+    sub_questions: Option<usize>,
+    loops: Vec<LoopStatus>,
+    synthesizing: bool,
+}
+
+impl ProgressLog {
+    pub fn apply(&mut self, event: Progress) {
+        // Any event but `Queued` means the query is running
+        self.queued = None;
+        match event {
+            Progress::Queued { position } => self.queued = Some(position),
+            Progress::Decomposed { sub_questions } => {
+                self.sub_questions = Some(sub_questions.len());
+            }
+            Progress::LoopStarted {
+                index,
+                question,
+                max_iterations,
+            } => {
+                let status = self.status(index);
+                status.question = question;
+                status.max_iterations = max_iterations;
+            }
+            Progress::Iteration { index, iteration } => {
+                self.status(index).iteration = iteration;
+            }
+            Progress::CodeExecuted { index, .. } => {
+                self.status(index).code_runs += 1;
+            }
+            Progress::FilesRead { index, files } => {
+                let status = self.status(index);
+                for file in files {
+                    status.files.retain(|f| f != &file);
+                    status.files.push(file);
+                }
+            }
+            Progress::LoopFinished { index, iterations } => {
+                let status = self.status(index);
+                status.iteration = iterations;
+                status.finished = true;
+            }
+            Progress::Synthesizing => self.synthesizing = true,
+        }
+    }
+
+    fn status(&mut self, index: usize) -> &mut LoopStatus {
+        if self.loops.len() <= index {
+            self.loops.resize_with(index + 1, LoopStatus::default);
+        }
+        &mut self.loops[index]
+    }
+
+    /// One-line summary of where the query is, e.g. "iteration 3/15".
+    pub fn phase(&self) -> String {
+        if let Some(position) = self.queued {
+            return format!("queued (#{})", position);
+        }
+        if self.synthesizing {
+            return "synthesizing".to_string();
+        }
+        if self.sub_questions.is_none() {
+            return "planning".to_string();
+        }
+        let loops: Vec<String> = self
+            .loops
+            .iter()
+            .map(|status| {
+                if status.finished {
+                    "done".to_string()
+                } else if status.iteration == 0 {
+                    "bootstrapping".to_string()
+                } else {
+                    format!("iteration {}/{}", status.iteration, status.max_iterations)
+                }
+            })
+            .collect();
+        match loops.len() {
+            0 => "exploring".to_string(),
+            1 => loops[0].clone(),
+            _ => loops
+                .iter()
+                .enumerate()
+                .map(|(i, l)| format!("[{}] {}", i + 1, l))
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    }
+
+    /// Status text, one line per step.
+    pub fn render(&self) -> String {
+        if let Some(position) = self.queued {
+            return format!("Waiting for a free slot — you're #{} in line", position);
+        }
+        let mut lines = Vec::new();
+        match self.sub_questions {
+            None => lines.push("Planning the investigation...".to_string()),
+            Some(0) => lines.push("Single investigation".to_string()),
+            Some(n) => lines.push(format!("Split into {} sub-investigations", n)),
+        }
+
+        let numbered = self.loops.len() > 1;
+        for (i, status) in self.loops.iter().enumerate() {
+            if numbered {
+                lines.push(format!("**[{}]** {}", i + 1, clip(&status.question)));
+            }
+
+            let state = if status.finished {
+                format!("done after {} iteration(s)", status.iteration)
+            } else if status.iteration == 0 {
+                "reading the documents".to_string()
+            } else {
+                format!("iteration {}/{}", status.iteration, status.max_iterations)
+            };
+            let mut line = format!("{} · {} code run(s)", state, status.code_runs);
+            if !status.files.is_empty() {
+                let start = status.files.len().saturating_sub(SHOWN_FILES);
+                line.push_str(&format!(
+                    " · read {}",
+                    clip(&status.files[start..].join(", "))
+                ));
+            }
+            lines.push(if numbered {
+                format!("  {}", line)
+            } else {
+                line
+            });
+        }
+
+        if self.synthesizing {
+            lines.push("Synthesizing findings...".to_string());
+        }
+        lines.join("\n")
+    }
+}
+
+fn clip(text: &str) -> String {
+    if text.chars().count() <= LINE_CHARS {
+        return text.to_string();
+    }
+    let mut clipped: String = text.chars().take(LINE_CHARS - 3).collect();
+    clipped.push_str("...");
+    clipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_atomic_loop() {
+        let mut log = ProgressLog::default();
+        assert_eq!(log.render(), "Planning the investigation...");
+
+        log.apply(Progress::Decomposed {
+            sub_questions: vec![],
+        });
+        log.apply(Progress::LoopStarted {
+            index: 0,
+            question: "q".to_string(),
+            max_iterations: 15,
+        });
+        log.apply(Progress::CodeExecuted {
+            index: 0,
+            output_len: 10,
+        });
+        log.apply(Progress::FilesRead {
+            index: 0,
+            files: vec!["a.md".to_string(), "b.md".to_string()],
+        });
+        log.apply(Progress::Iteration {
+            index: 0,
+            iteration: 2,
+        });
+        log.apply(Progress::FilesRead {
+            index: 0,
+            files: vec!["c.md".to_string(), "d.md".to_string(), "a.md".to_string()],
+        });
+        assert_eq!(
+            log.render(),
+            "Single investigation\niteration 2/15 · 1 code run(s) · read c.md, d.md, a.md"
+        );
+        assert_eq!(log.phase(), "iteration 2/15");
+    }
+
+    #[test]
+    fn test_queued_until_running() {
+        let mut log = ProgressLog::default();
+        log.apply(Progress::Queued { position: 3 });
+        assert_eq!(log.phase(), "queued (#3)");
+        log.apply(Progress::Queued { position: 1 });
+        assert_eq!(log.render(), "Waiting for a free slot — you're #1 in line");
+        log.apply(Progress::Decomposed {
+            sub_questions: vec![],
+        });
+        assert_eq!(log.render(), "Single investigation");
+    }
+
+    #[test]
+    fn test_render_sub_investigations() {
+        let mut log = ProgressLog::default();
+        log.apply(Progress::Decomposed {
+            sub_questions: vec!["one".to_string(), "two".to_string()],
+        });
+        // Events can arrive before the loop's start event
+        log.apply(Progress::Iteration {
+            index: 1,
+            iteration: 1,
+        });
+        log.apply(Progress::LoopStarted {
+            index: 0,
+            question: "one".to_string(),
+            max_iterations: 8,
+        });
+        log.apply(Progress::LoopFinished {
+            index: 0,
+            iterations: 3,
+        });
+        log.apply(Progress::Synthesizing);
+        let rendered = log.render();
+        assert!(rendered.starts_with(
+            "Split into 2 sub-investigations\n**[1]** one\n  done after 3 iteration(s)"
+        ));
+        assert!(rendered.ends_with("Synthesizing findings..."));
+        assert_eq!(log.phase(), "synthesizing");
+    }
+}
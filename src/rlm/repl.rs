@@ -1,30 +1,144 @@
+use std::ops::Range;
+
+use crate::llm::{Message, ToolCall, ToolSpec};
+
+/// Native tool that runs code in the REPL.
+pub const RUN_PYTHON: &str = "run_python";
+/// Native tool that ends the loop with an answer.
+pub const FINAL_ANSWER: &str = "final_answer";
+
+/// The REPL offered as native tools, for models driven by tool calls.
+pub fn tool_specs() -> Vec<ToolSpec> {
+    vec![
+        ToolSpec {
+            name: RUN_PYTHON,
+            description: "Run Python code in the persistent REPL and return what it printed. \
+                          Variables and the document tools stay loaded between calls.",
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "code": {"type": "string", "description": "Python code to run"}
+                },
+                "required": ["code"],
+            }),
+        },
+        ToolSpec {
+            name: FINAL_ANSWER,
+            description: "Give the final, sourced answer to the question. Ends the investigation.",
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "answer": {"type": "string", "description": "The complete answer, in markdown"}
+                },
+                "required": ["answer"],
+            }),
+        },
+    ]
+}
+
+/// `messages` with native tool turns in the fenced form, for a model that
+/// isn't offered tools: a `run_python` call becomes a ```repl block, a
+/// `final_answer` call `FINAL(...)`, and a tool result a user turn.
+pub fn flatten_tool_turns(messages: &[Message]) -> Vec<Message> {
+    messages
+        .iter()
+        .map(|message| {
+            if message.tool_call_id.is_some() {
+                return Message::user(message.content.clone());
+            }
+            if message.tool_calls.is_empty() {
+                return message.clone();
+            }
+            let mut parts = vec![message.content.clone()];
+            for call in &message.tool_calls {
+                parts.push(match Command::from_tool_call(call) {
+                    Command::RunCode(blocks) => blocks
+                        .iter()
+                        .map(|code| format!("```repl\n{}\n```", code))
+                        .collect::<Vec<_>>()
+                        .join("\n\n"),
+                    Command::Final(answer) => format!("FINAL({})", answer),
+                    _ => format!("{}({})", call.function.name, call.function.arguments),
+                });
+            }
+            parts.retain(|part| !part.is_empty());
+            Message::assistant(parts.join("\n\n"))
+        })
+        .collect()
+}
+
+/// Fence tags whose blocks are run as code.
+const CODE_TAGS: &[&str] = &["repl", "python", "py"];
+
 /// Parsed command from LLM output.
 #[derive(Debug)]
 pub enum Command {
-    /// Python code to execute (from ```repl or ```python blocks).
-    RunCode(String),
+    /// Python code to execute: every ```repl or ```python block, in order.
+    RunCode(Vec<String>),
     /// Final answer — loop terminates.
     Final(String),
+    /// `FINAL_VAR(name)` — the answer is the text of session variable `name`.
+    FinalVar(String),
     /// Unrecognized output — continue conversation.
     InvalidCommand,
 }
 
 impl Command {
-    /// Parse an LLM response into a structured command.
+    /// Parse an LLM response into a structured command. `FINAL(` or
+    /// `FINAL_VAR(` inside code fences, inline code, double-quoted text or
+    /// blockquotes is not a final answer.
     pub fn parse(input: &str) -> Self {
-        // Check for FINAL(...) first
-        if let Some(answer) = extract_final(input) {
-            return Command::Final(answer);
+        let fences = find_fences(input);
+
+        // Check for FINAL(...) / FINAL_VAR(...) first, whichever comes first
+        let final_at = find_unquoted(input, &fences, "FINAL(");
+        let var_at = find_unquoted(input, &fences, "FINAL_VAR(");
+        if let Some(idx) = var_at.filter(|v| final_at.is_none_or(|f| *v < f)) {
+            let name = extract_final(&input[idx + "FINAL_VAR(".len()..]);
+            if is_identifier(&name) {
+                return Command::FinalVar(name);
+            }
+        }
+        if let Some(idx) = final_at {
+            return Command::Final(extract_final(&input[idx + "FINAL(".len()..]));
         }
 
-        // Check for code blocks: ```repl, ```python, ```py
-        if let Some(code) = extract_code_block(input) {
-            return Command::RunCode(code);
+        // Then for code blocks: ```repl, ```python, ```py
+        let blocks: Vec<String> = fences
+            .iter()
+            .filter(|f| CODE_TAGS.contains(&f.tag.as_str()))
+            .map(|f| input[f.code.clone()].trim())
+            .filter(|code| !code.is_empty())
+            .map(str::to_string)
+            .collect();
+        if !blocks.is_empty() {
+            return Command::RunCode(blocks);
         }
 
         Command::InvalidCommand
     }
 
+    /// Read a native tool call. An unknown tool or missing arguments is an
+    /// `InvalidCommand`.
+    pub fn from_tool_call(call: &ToolCall) -> Self {
+        let Ok(args) = serde_json::from_str::<serde_json::Value>(&call.function.arguments) else {
+            return Command::InvalidCommand;
+        };
+        let field = |key: &str| {
+            args[key]
+                .as_str()
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+        };
+        let command = match call.function.name.as_str() {
+            RUN_PYTHON => field("code").map(|code| Command::RunCode(vec![code])),
+            FINAL_ANSWER => field("answer").map(Command::Final),
+            _ => None,
+        };
+        command.unwrap_or(Command::InvalidCommand)
+    }
+
     pub fn get_final(&self) -> Option<&str> {
         match self {
             Command::Final(s) => Some(s),
@@ -32,18 +146,103 @@ impl Command {
         }
     }
 
-    pub fn get_code(&self) -> Option<&str> {
+    pub fn get_code(&self) -> Option<Vec<&str>> {
         match self {
-            Command::RunCode(s) => Some(s),
+            Command::RunCode(blocks) => Some(blocks.iter().map(String::as_str).collect()),
             _ => None,
         }
     }
 }
 
-/// Extract content from FINAL(...) using paren-counting.
-fn extract_final(input: &str) -> Option<String> {
-    let idx = input.find("FINAL(")?;
-    let after = &input[idx + 6..]; // skip "FINAL("
+/// A ``` fenced block: its span in the input, language tag and code.
+struct Fence {
+    span: Range<usize>,
+    tag: String,
+    code: Range<usize>,
+}
+
+/// Every fenced block in `input`, in order. An unclosed fence runs to the end.
+fn find_fences(input: &str) -> Vec<Fence> {
+    let mut fences = Vec::new();
+    let mut pos = 0;
+    while let Some(open) = input[pos..].find("```") {
+        let start = pos + open;
+        let after = start + 3;
+        let rest = &input[after..];
+        let tag_len = rest.find(|c: char| c.is_whitespace()).unwrap_or(rest.len());
+        let tag = rest[..tag_len].to_lowercase();
+        // Code starts on the next line; the rest of the opening line is ignored
+        let code_start = match rest.find('\n') {
+            Some(newline) => after + newline + 1,
+            None => after + tag_len,
+        };
+        let (code_end, end) = match input[code_start..].find("```") {
+            Some(close) => (code_start + close, code_start + close + 3),
+            None => (input.len(), input.len()),
+        };
+        fences.push(Fence {
+            span: start..end,
+            tag,
+            code: code_start..code_end,
+        });
+        pos = end;
+    }
+    fences
+}
+
+/// Byte offset of the first `marker` outside fences, inline code, double
+/// quotes and blockquote lines. Quotes don't carry over line breaks, so a
+/// stray quote can't hide the rest of the response.
+fn find_unquoted(input: &str, fences: &[Fence], marker: &str) -> Option<usize> {
+    let mut gaps = Vec::new();
+    let mut pos = 0;
+    for fence in fences {
+        gaps.push(pos..fence.span.start);
+        pos = fence.span.end;
+    }
+    gaps.push(pos..input.len());
+
+    for gap in gaps {
+        let mut offset = gap.start;
+        for line in input[gap].split_inclusive('\n') {
+            if !line.trim_start().starts_with('>') {
+                if let Some(i) = find_in_line(line, marker) {
+                    return Some(offset + i);
+                }
+            }
+            offset += line.len();
+        }
+    }
+    None
+}
+
+fn find_in_line(line: &str, marker: &str) -> Option<usize> {
+    let mut in_code = false;
+    let mut in_quote = false;
+    for (i, ch) in line.char_indices() {
+        match ch {
+            '`' => in_code = !in_code,
+            '"' if !in_code => in_quote = !in_quote,
+            '“' if !in_code => in_quote = true,
+            '”' if !in_code => in_quote = false,
+            _ if !in_code && !in_quote && line[i..].starts_with(marker) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Extract the content of `FINAL(...)` after its opening paren, using
+/// paren-counting.
+fn extract_final(after: &str) -> String {
     let mut depth = 1i32;
     let mut end = None;
 
@@ -68,49 +267,16 @@ fn extract_final(input: &str) -> Option<String> {
 
     // Strip surrounding quotes if present
     let trimmed = content.trim();
-    let unquoted = if (trimmed.starts_with('"') && trimmed.ends_with('"'))
-        || (trimmed.starts_with('\'') && trimmed.ends_with('\''))
+    let unquoted = if trimmed.len() >= 2
+        && ((trimmed.starts_with('"') && trimmed.ends_with('"'))
+            || (trimmed.starts_with('\'') && trimmed.ends_with('\'')))
     {
         &trimmed[1..trimmed.len() - 1]
     } else {
         trimmed
     };
 
-    Some(unquoted.to_string())
-}
-
-/// Extract code from ```repl, ```python, or ```py blocks.
-fn extract_code_block(input: &str) -> Option<String> {
-    // Find opening fence
-    let patterns = ["```repl", "```python", "```py"];
-    let mut best_start = None;
-
-    for pat in &patterns {
-        if let Some(idx) = input.find(pat) {
-            match best_start {
-                None => best_start = Some((idx, pat.len())),
-                Some((prev, _)) if idx < prev => best_start = Some((idx, pat.len())),
-                _ => {}
-            }
-        }
-    }
-
-    let (start_idx, pat_len) = best_start?;
-    let after_tag = &input[start_idx + pat_len..];
-
-    // Skip to next newline (the rest of the opening fence line)
-    let code_start = after_tag.find('\n').map(|i| i + 1).unwrap_or(0);
-    let code_region = &after_tag[code_start..];
-
-    // Find closing ```
-    let end = code_region.find("```").unwrap_or(code_region.len());
-    let code = code_region[..end].trim();
-
-    if code.is_empty() {
-        None
-    } else {
-        Some(code.to_string())
-    }
+    unquoted.to_string()
 }
 
 #[cfg(test)]
@@ -142,14 +308,77 @@ mod tests {
     fn test_parse_code_block() {
         let input = "Let me check:\n```repl\nprint('hello')\n```\n";
         let cmd = Command::parse(input);
-        assert_eq!(cmd.get_code(), Some("print('hello')"));
+        assert_eq!(cmd.get_code(), Some(vec!["print('hello')"]));
     }
 
     #[test]
     fn test_parse_python_block() {
         let input = "```python\nx = 1 + 2\nprint(x)\n```";
         let cmd = Command::parse(input);
-        assert_eq!(cmd.get_code(), Some("x = 1 + 2\nprint(x)"));
+        assert_eq!(cmd.get_code(), Some(vec!["x = 1 + 2\nprint(x)"]));
+    }
+
+    #[test]
+    fn test_parse_every_code_block() {
+        let input = "First:\n```repl\na = 1\n```\nAn example:\n```json\n{}\n```\n\
+                     Then:\n```python\nprint(a)\n```\n```py\n\n```";
+        let cmd = Command::parse(input);
+        assert_eq!(cmd.get_code(), Some(vec!["a = 1", "print(a)"]));
+    }
+
+    #[test]
+    fn test_parse_unclosed_block() {
+        let cmd = Command::parse("```repl\nprint(1)\n");
+        assert_eq!(cmd.get_code(), Some(vec!["print(1)"]));
+    }
+
+    #[test]
+    fn test_final_inside_fence_is_code() {
+        let input = "```repl\nanswer = 'x'\nprint(\"FINAL(answer)\")\n```";
+        let cmd = Command::parse(input);
+        assert_eq!(
+            cmd.get_code(),
+            Some(vec!["answer = 'x'\nprint(\"FINAL(answer)\")"])
+        );
+    }
+
+    #[test]
+    fn test_final_inside_quotes_is_ignored() {
+        let input = "When I'm done I will reply with `FINAL(answer)` or \"FINAL(...)\".\n\
+                     > FINAL(quoted from a document)\n\
+                     ```repl\nprint(1)\n```";
+        let cmd = Command::parse(input);
+        assert_eq!(cmd.get_code(), Some(vec!["print(1)"]));
+
+        // A stray quote doesn't hide a FINAL on a later line
+        let input = "The doc says \"stake.\nFINAL(42)";
+        assert_eq!(Command::parse(input).get_final(), Some("42"));
+    }
+
+    #[test]
+    fn test_final_after_code_block() {
+        let input = "```repl\nprint(\"FINAL(no)\")\n```\nFINAL(yes)";
+        assert_eq!(Command::parse(input).get_final(), Some("yes"));
+    }
+
+    #[test]
+    fn test_parse_final_var() {
+        let cmd = Command::parse("Done.\nFINAL_VAR(report)");
+        assert!(matches!(cmd, Command::FinalVar(name) if name == "report"));
+
+        let cmd = Command::parse("FINAL_VAR(\"report\")");
+        assert!(matches!(cmd, Command::FinalVar(name) if name == "report"));
+
+        // Not a variable name — fall back to the code block
+        let cmd = Command::parse("```repl\nx = 1\n```\nFINAL_VAR(x + 1)");
+        assert_eq!(cmd.get_code(), Some(vec!["x = 1"]));
+
+        // The earlier of FINAL and FINAL_VAR wins
+        let cmd = Command::parse("FINAL(See below) FINAL_VAR(report)");
+        assert_eq!(cmd.get_final(), Some("See below"));
+
+        let cmd = Command::parse("```repl\nFINAL_VAR(report)\n```");
+        assert!(matches!(cmd, Command::RunCode(_)));
     }
 
     #[test]
@@ -158,4 +387,63 @@ mod tests {
         let cmd = Command::parse(input);
         assert!(matches!(cmd, Command::InvalidCommand));
     }
+
+    #[test]
+    fn test_from_tool_call() {
+        let call = |name: &str, args: serde_json::Value| ToolCall::new("call_0", name, &args);
+
+        let cmd =
+            Command::from_tool_call(&call(RUN_PYTHON, serde_json::json!({"code": "print(1)\n"})));
+        assert_eq!(cmd.get_code(), Some(vec!["print(1)"]));
+
+        let cmd = Command::from_tool_call(&call(FINAL_ANSWER, serde_json::json!({"answer": "42"})));
+        assert_eq!(cmd.get_final(), Some("42"));
+
+        let cmd = Command::from_tool_call(&call(RUN_PYTHON, serde_json::json!({"source": "x"})));
+        assert!(matches!(cmd, Command::InvalidCommand));
+
+        let cmd = Command::from_tool_call(&call("search", serde_json::json!({"code": "x"})));
+        assert!(matches!(cmd, Command::InvalidCommand));
+
+        let mut broken = call(RUN_PYTHON, serde_json::json!({}));
+        broken.function.arguments = "{\"code\": ".to_string();
+        assert!(matches!(
+            Command::from_tool_call(&broken),
+            Command::InvalidCommand
+        ));
+    }
+
+    #[test]
+    fn test_flatten_tool_turns() {
+        let call = ToolCall::new(
+            "call_0",
+            RUN_PYTHON,
+            &serde_json::json!({"code": "print(1)"}),
+        );
+        let messages = vec![
+            Message::system("prompt"),
+            Message::tool_call("Let me look.", call),
+            Message::tool("call_0", "1"),
+            Message::tool_call(
+                "",
+                ToolCall::new("call_1", FINAL_ANSWER, &serde_json::json!({"answer": "42"})),
+            ),
+        ];
+        let flat = flatten_tool_turns(&messages);
+
+        let roles: Vec<&str> = flat.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["system", "assistant", "user", "assistant"]);
+        assert!(flat
+            .iter()
+            .all(|m| m.tool_calls.is_empty() && m.tool_call_id.is_none()));
+        assert_eq!(flat[1].content, "Let me look.\n\n```repl\nprint(1)\n```");
+        assert_eq!(flat[2].content, "1");
+        assert_eq!(flat[3].content, "FINAL(42)");
+        // Read back, the flattened turns are the same commands
+        assert_eq!(
+            Command::parse(&flat[1].content).get_code(),
+            Some(vec!["print(1)"])
+        );
+        assert_eq!(Command::parse(&flat[3].content).get_final(), Some("42"));
+    }
 }
@@ -0,0 +1,298 @@
+//! Admission control in front of `RlmEngine::query`: a global cap on running
+//! queries, per-user and per-guild rate limits, and a FIFO queue in which
+//! admin queries go ahead of everyone else's.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use tokio::sync::oneshot;
+
+/// Rate limits count queries submitted within this window.
+const RATE_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Scheduler limits.
+#[derive(Debug, Clone, Copy)]
+pub struct SchedulerConfig {
+    /// Queries running at once across all guilds
+    pub max_concurrent: usize,
+    /// Queries one user may submit per hour
+    pub user_per_hour: usize,
+    /// Queries one guild may submit per hour
+    pub guild_per_hour: usize,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 2,
+            user_per_hour: 10,
+            guild_per_hour: 60,
+        }
+    }
+}
+
+impl SchedulerConfig {
+    /// Read `QUERY_MAX_CONCURRENT`, `QUERY_USER_PER_HOUR` and
+    /// `QUERY_GUILD_PER_HOUR`, keeping defaults for unset or invalid values.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| {
+            dotenv::var(name)
+                .ok()
+                .and_then(|v| v.trim().parse::<usize>().ok())
+                .filter(|v| *v > 0)
+        };
+        Self {
+            max_concurrent: var("QUERY_MAX_CONCURRENT").unwrap_or(defaults.max_concurrent),
+            user_per_hour: var("QUERY_USER_PER_HOUR").unwrap_or(defaults.user_per_hour),
+            guild_per_hour: var("QUERY_GUILD_PER_HOUR").unwrap_or(defaults.guild_per_hour),
+        }
+    }
+}
+
+/// A submission refused by a rate limit.
+#[derive(Debug)]
+pub struct RateLimited {
+    /// "user" or "server"
+    pub limit: &'static str,
+    pub per_hour: usize,
+    /// When the oldest counted submission leaves the window
+    pub retry_after: Duration,
+}
+
+struct Waiter {
+    id: u64,
+    priority: bool,
+    admit: oneshot::Sender<()>,
+}
+
+#[derive(Default)]
+struct State {
+    running: usize,
+    queue: VecDeque<Waiter>,
+    user_submissions: HashMap<u64, VecDeque<Instant>>,
+    guild_submissions: HashMap<u64, VecDeque<Instant>>,
+}
+
+pub struct Scheduler {
+    config: SchedulerConfig,
+    next_id: AtomicU64,
+    state: Mutex<State>,
+}
+
+impl Scheduler {
+    pub fn new(config: SchedulerConfig) -> Self {
+        Self {
+            config,
+            next_id: AtomicU64::new(1),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Submit a query: counts it against the rate limits and takes a running
+    /// slot, or a place in the queue. `priority` (admin) queries queue ahead
+    /// of other queries. The slot is held until the ticket is dropped.
+    pub fn submit(
+        self: &Arc<Self>,
+        user: u64,
+        guild: u64,
+        priority: bool,
+    ) -> Result<Ticket, RateLimited> {
+        let now = Instant::now();
+        let mut state = self.state();
+
+        let user_window = window(&mut state.user_submissions, user, now);
+        check_rate(user_window, self.config.user_per_hour, "user", now)?;
+        let guild_window = window(&mut state.guild_submissions, guild, now);
+        check_rate(guild_window, self.config.guild_per_hour, "server", now)?;
+        state.user_submissions.entry(user).or_default().push_back(now);
+        state.guild_submissions.entry(guild).or_default().push_back(now);
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let admit = if state.running < self.config.max_concurrent && state.queue.is_empty() {
+            state.running += 1;
+            None
+        } else {
+            let (tx, rx) = oneshot::channel();
+            let waiter = Waiter {
+                id,
+                priority,
+                admit: tx,
+            };
+            // Behind earlier priority waiters, ahead of everyone else
+            let at = if priority {
+                state
+                    .queue
+                    .iter()
+                    .position(|w| !w.priority)
+                    .unwrap_or(state.queue.len())
+            } else {
+                state.queue.len()
+            };
+            state.queue.insert(at, waiter);
+            Some(rx)
+        };
+
+        Ok(Ticket {
+            scheduler: self.clone(),
+            id,
+            admit,
+        })
+    }
+
+    /// Hand a freed slot to the next waiter still waiting, or give it back.
+    fn release(&self) {
+        let mut state = self.state();
+        while let Some(waiter) = state.queue.pop_front() {
+            if waiter.admit.send(()).is_ok() {
+                return;
+            }
+        }
+        state.running = state.running.saturating_sub(1);
+    }
+
+    fn position(&self, id: u64) -> Option<usize> {
+        self.state()
+            .queue
+            .iter()
+            .position(|w| w.id == id)
+            .map(|i| i + 1)
+    }
+
+    /// Leave the queue. Returns false if the waiter was already admitted.
+    fn withdraw(&self, id: u64) -> bool {
+        let mut state = self.state();
+        match state.queue.iter().position(|w| w.id == id) {
+            Some(i) => {
+                state.queue.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Submissions of `key` still inside the rate window.
+fn window(
+    submissions: &mut HashMap<u64, VecDeque<Instant>>,
+    key: u64,
+    now: Instant,
+) -> &VecDeque<Instant> {
+    let times = submissions.entry(key).or_default();
+    while times
+        .front()
+        .is_some_and(|t| now.duration_since(*t) >= RATE_WINDOW)
+    {
+        times.pop_front();
+    }
+    times
+}
+
+fn check_rate(
+    times: &VecDeque<Instant>,
+    per_hour: usize,
+    limit: &'static str,
+    now: Instant,
+) -> Result<(), RateLimited> {
+    if times.len() < per_hour {
+        return Ok(());
+    }
+    let oldest = times.front().copied().unwrap_or(now);
+    Err(RateLimited {
+        limit,
+        per_hour,
+        retry_after: RATE_WINDOW.saturating_sub(now.duration_since(oldest)),
+    })
+}
+
+/// A submitted query: waiting in the queue, or admitted and holding a running
+/// slot. Dropping it leaves the queue or frees the slot.
+pub struct Ticket {
+    scheduler: Arc<Scheduler>,
+    id: u64,
+    /// Set while queued
+    admit: Option<oneshot::Receiver<()>>,
+}
+
+impl Ticket {
+    /// Place in line (1 = next), or None once admitted.
+    pub fn position(&self) -> Option<usize> {
+        self.admit.as_ref()?;
+        self.scheduler.position(self.id)
+    }
+
+    /// Wait until the query may run. Cancel-safe.
+    pub async fn admitted(&mut self) {
+        if let Some(admit) = &mut self.admit {
+            let _ = admit.await;
+            self.admit = None;
+        }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        // Still queued: just leave. Otherwise a slot is held (or was handed
+        // over while nobody was waiting for it) — pass it on.
+        if self.admit.is_none() || !self.scheduler.withdraw(self.id) {
+            self.scheduler.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(max_concurrent: usize, user_per_hour: usize) -> Arc<Scheduler> {
+        Arc::new(Scheduler::new(SchedulerConfig {
+            max_concurrent,
+            user_per_hour,
+            guild_per_hour: 100,
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_fifo_with_priority() {
+        let scheduler = scheduler(1, 10);
+        let running = scheduler.submit(1, 1, false).unwrap();
+        assert_eq!(running.position(), None);
+
+        let mut first = scheduler.submit(2, 1, false).unwrap();
+        let second = scheduler.submit(3, 1, false).unwrap();
+        let mut admin = scheduler.submit(4, 1, true).unwrap();
+        assert_eq!(admin.position(), Some(1));
+        assert_eq!(first.position(), Some(2));
+        assert_eq!(second.position(), Some(3));
+
+        drop(running);
+        admin.admitted().await;
+        assert_eq!(admin.position(), None);
+        assert_eq!(first.position(), Some(1));
+
+        // A waiter that gives up leaves the line
+        drop(second);
+        drop(admin);
+        first.admitted().await;
+        drop(first);
+        assert_eq!(scheduler.state().running, 0);
+    }
+
+    #[test]
+    fn test_user_rate_limit() {
+        let scheduler = scheduler(10, 2);
+        let _a = scheduler.submit(1, 1, false).unwrap();
+        let _b = scheduler.submit(1, 1, false).unwrap();
+        let limited = scheduler.submit(1, 1, false).err().unwrap();
+        assert_eq!(limited.limit, "user");
+        assert!(limited.retry_after > Duration::from_secs(60 * 59));
+        // Other users are unaffected
+        assert!(scheduler.submit(2, 1, false).is_ok());
+    }
+}
@@ -0,0 +1,1091 @@
+//! Declarative registry of the functions exposed in the RLM's Python REPL.
+//!
+//! Each [`Tool`] declares its name, typed parameters with defaults, a docstring
+//! and an async Rust handler. The registry binds the positional and keyword
+//! arguments a session's Python worker sends, and generates the `help()` text
+//! and the tool section of the system prompt, so the prompt always describes
+//! the API the REPL has.
+
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use futures::{stream, StreamExt};
+use serde_json::{json, Map, Value};
+
+use crate::docs::types::{DocExcerpt, DocMeta};
+use crate::docs::DocumentStore;
+use crate::llm::LlmClient;
+
+use super::exec::{CancelSlot, FileTracker, UsageSlot};
+use super::grep::{self, GrepOptions};
+use super::jobs::CancelToken;
+use super::usage::{Phase, UsageMeter};
+use super::SubRlm;
+
+/// Longest `read_file()` result, in chars.
+const READ_FILE_MAX_CHARS: usize = 20_000;
+/// Most prompts one `llm_query_batch()` call takes.
+const LLM_QUERY_BATCH_MAX: usize = 50;
+/// Key the worker sends in place of an argument JSON can't carry, with the
+/// value's Python type name.
+const UNSUPPORTED: &str = "$unsupported";
+
+/// Type of a tool parameter, checked when the tool is called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamType {
+    Str,
+    /// A non-negative integer
+    Int,
+    Bool,
+    /// A string or a list of strings
+    StrList,
+}
+
+impl ParamType {
+    fn name(self) -> &'static str {
+        match self {
+            ParamType::Str => "str",
+            ParamType::Int => "int",
+            ParamType::Bool => "bool",
+            ParamType::StrList => "str | list[str]",
+        }
+    }
+}
+
+/// Default of a tool parameter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DefaultValue {
+    /// The caller must pass the argument
+    Required,
+    None,
+    Int(u64),
+    Bool(bool),
+}
+
+impl DefaultValue {
+    /// Python literal, as shown in signatures.
+    fn literal(self) -> String {
+        match self {
+            DefaultValue::Required => String::new(),
+            DefaultValue::None => "None".to_string(),
+            DefaultValue::Int(n) => n.to_string(),
+            DefaultValue::Bool(true) => "True".to_string(),
+            DefaultValue::Bool(false) => "False".to_string(),
+        }
+    }
+
+    fn value(self) -> Value {
+        match self {
+            DefaultValue::Required | DefaultValue::None => Value::Null,
+            DefaultValue::Int(n) => n.into(),
+            DefaultValue::Bool(b) => b.into(),
+        }
+    }
+}
+
+/// One parameter of a tool.
+#[derive(Debug, Clone, Copy)]
+pub struct Param {
+    pub name: &'static str,
+    pub ty: ParamType,
+    pub default: DefaultValue,
+    pub doc: &'static str,
+}
+
+impl Param {
+    const fn required(name: &'static str, ty: ParamType, doc: &'static str) -> Self {
+        Self {
+            name,
+            ty,
+            default: DefaultValue::Required,
+            doc,
+        }
+    }
+
+    const fn optional(
+        name: &'static str,
+        ty: ParamType,
+        default: DefaultValue,
+        doc: &'static str,
+    ) -> Self {
+        Self {
+            name,
+            ty,
+            default,
+            doc,
+        }
+    }
+
+    /// Check an argument against this parameter's type.
+    fn extract(&self, tool: &str, value: &Value) -> Result<Value, ToolError> {
+        if value.is_null() && self.default == DefaultValue::None {
+            return Ok(Value::Null);
+        }
+        let converted = match (self.ty, value) {
+            (ParamType::Str, Value::String(_)) | (ParamType::Bool, Value::Bool(_)) => {
+                Some(value.clone())
+            }
+            (ParamType::Int, Value::Number(n)) => n.as_u64().map(Value::from),
+            (ParamType::StrList, Value::String(_)) => Some(vec![value.clone()].into()),
+            (ParamType::StrList, Value::Array(items)) if items.iter().all(Value::is_string) => {
+                Some(value.clone())
+            }
+            _ => None,
+        };
+        converted.ok_or_else(|| {
+            let expected = match self.ty {
+                ParamType::Int => "a non-negative int",
+                ty => ty.name(),
+            };
+            ToolError::Type(format!(
+                "{}() argument '{}' must be {}, not {}",
+                tool,
+                self.name,
+                expected,
+                python_type(value)
+            ))
+        })
+    }
+}
+
+/// Python type name of an argument the worker sent.
+fn python_type(value: &Value) -> &str {
+    match value {
+        Value::Null => "NoneType",
+        Value::Bool(_) => "bool",
+        Value::Number(n) if n.is_f64() => "float",
+        Value::Number(_) => "int",
+        Value::String(_) => "str",
+        Value::Array(_) => "list",
+        Value::Object(map) => map
+            .get(UNSUPPORTED)
+            .and_then(Value::as_str)
+            .unwrap_or("dict"),
+    }
+}
+
+/// Error from a tool call, raised in Python.
+#[derive(Debug)]
+pub enum ToolError {
+    /// A call that doesn't match the signature, raised as `TypeError`
+    Type(String),
+    /// A bad argument value, raised as `ValueError`
+    Invalid(String),
+    /// A backend failure, raised as `RuntimeError`
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for ToolError {
+    fn from(e: anyhow::Error) -> Self {
+        ToolError::Failed(e)
+    }
+}
+
+impl ToolError {
+    /// The Python exception type and message the error is raised as.
+    fn exception(&self) -> (&'static str, String) {
+        match self {
+            ToolError::Type(msg) => ("TypeError", msg.clone()),
+            ToolError::Invalid(msg) => ("ValueError", msg.clone()),
+            ToolError::Failed(e) => ("RuntimeError", e.to_string()),
+        }
+    }
+}
+
+pub type ToolResult = Result<Value, ToolError>;
+
+/// What tool handlers can reach.
+#[derive(Clone)]
+pub struct ToolEnv {
+    pub store: Arc<DocumentStore>,
+    pub llm: Arc<LlmClient>,
+    pub docs: Arc<Vec<DocMeta>>,
+    pub file_tracker: FileTracker,
+    pub usage: UsageSlot,
+    pub cancel: CancelSlot,
+    /// Runs `rlm_query()` loops; None where nesting isn't set up
+    pub sub_rlm: Option<SubRlm>,
+}
+
+impl ToolEnv {
+    /// Meter of the query the session is serving.
+    fn meter(&self) -> UsageMeter {
+        self.usage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Cancellation of the query the session is serving.
+    fn cancel(&self) -> CancelToken {
+        self.cancel
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Reject `doc_id`/`doc_ids` arguments naming documents outside the
+    /// session, so a query can't read another guild's documents by ID.
+    fn check_doc_ids(&self, args: &ToolArgs) -> Result<(), ToolError> {
+        let mut ids: Vec<String> = args.strs("doc_ids").unwrap_or_default();
+        ids.extend(args.str("doc_id").ok());
+        match ids
+            .iter()
+            .find(|id| !self.docs.iter().any(|d| &d.id == *id))
+        {
+            Some(unknown) => Err(ToolError::Invalid(format!(
+                "unknown doc_id '{}' — use a doc_id from `documents`",
+                unknown
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A call's arguments, bound to parameter names with defaults filled in.
+#[derive(Debug, Clone)]
+pub struct ToolArgs {
+    values: Map<String, Value>,
+}
+
+impl ToolArgs {
+    fn get(&self, name: &str) -> Result<&Value, ToolError> {
+        self.values
+            .get(name)
+            .ok_or_else(|| ToolError::Failed(anyhow::anyhow!("no parameter '{}'", name)))
+    }
+
+    fn mismatch(name: &str) -> ToolError {
+        ToolError::Failed(anyhow::anyhow!("parameter '{}' has the wrong type", name))
+    }
+
+    pub fn str(&self, name: &str) -> Result<String, ToolError> {
+        self.get(name)?
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| Self::mismatch(name))
+    }
+
+    pub fn usize(&self, name: &str) -> Result<usize, ToolError> {
+        self.get(name)?
+            .as_u64()
+            .map(|n| n as usize)
+            .ok_or_else(|| Self::mismatch(name))
+    }
+
+    pub fn bool(&self, name: &str) -> Result<bool, ToolError> {
+        self.get(name)?
+            .as_bool()
+            .ok_or_else(|| Self::mismatch(name))
+    }
+
+    /// A `StrList` argument; `None` is an empty list.
+    pub fn strs(&self, name: &str) -> Result<Vec<String>, ToolError> {
+        match self.get(name)? {
+            Value::Null => Ok(Vec::new()),
+            Value::Array(items) => items
+                .iter()
+                .map(|v| v.as_str().map(str::to_string))
+                .collect::<Option<_>>()
+                .ok_or_else(|| Self::mismatch(name)),
+            _ => Err(Self::mismatch(name)),
+        }
+    }
+}
+
+type Handler = fn(ToolEnv, ToolArgs) -> BoxFuture<'static, ToolResult>;
+
+/// A function exposed in the REPL.
+pub struct Tool {
+    pub name: &'static str,
+    pub params: &'static [Param],
+    /// One paragraph: what the tool does and what it returns
+    pub doc: &'static str,
+    /// Runs nested loops or fan-outs with budgets of their own; the calling
+    /// code's time limit restarts once it returns
+    pub long: bool,
+    pub handler: Handler,
+}
+
+impl Tool {
+    /// e.g. `grep(doc_id, pattern, context=3, ...)`
+    pub fn signature(&self) -> String {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|p| match p.default {
+                DefaultValue::Required => p.name.to_string(),
+                d => format!("{}={}", p.name, d.literal()),
+            })
+            .collect();
+        format!("{}({})", self.name, params.join(", "))
+    }
+
+    /// Full description, as printed by `help(tool)`.
+    pub fn help(&self) -> String {
+        let mut out = format!("{}\n    {}\n", self.signature(), self.doc);
+        if !self.params.is_empty() {
+            out.push_str("\n    Arguments:\n");
+            for p in self.params {
+                let default = match p.default {
+                    DefaultValue::Required => String::new(),
+                    d => format!(" = {}", d.literal()),
+                };
+                out.push_str(&format!(
+                    "      {}: {}{} — {}\n",
+                    p.name,
+                    p.ty.name(),
+                    default,
+                    p.doc
+                ));
+            }
+        }
+        out
+    }
+
+    /// Match positional and keyword arguments to parameters, Python-style.
+    pub fn bind(&self, args: &[Value], kwargs: &Map<String, Value>) -> Result<ToolArgs, ToolError> {
+        let name = self.name;
+        if args.len() > self.params.len() {
+            return Err(ToolError::Type(format!(
+                "{}() takes at most {} arguments ({} given)",
+                name,
+                self.params.len(),
+                args.len()
+            )));
+        }
+        let mut slots: Vec<Option<&Value>> = vec![None; self.params.len()];
+        for (slot, arg) in slots.iter_mut().zip(args) {
+            *slot = Some(arg);
+        }
+        for (key, value) in kwargs {
+            let Some(idx) = self.params.iter().position(|p| p.name == key) else {
+                return Err(ToolError::Type(format!(
+                    "{}() got an unexpected keyword argument '{}'",
+                    name, key
+                )));
+            };
+            if slots[idx].is_some() {
+                return Err(ToolError::Type(format!(
+                    "{}() got multiple values for argument '{}'",
+                    name, key
+                )));
+            }
+            slots[idx] = Some(value);
+        }
+
+        let mut values = Map::new();
+        for (param, slot) in self.params.iter().zip(slots) {
+            let value = match (slot, param.default) {
+                (Some(v), _) => param.extract(name, v)?,
+                (None, DefaultValue::Required) => {
+                    return Err(ToolError::Type(format!(
+                        "{}() missing required argument '{}'",
+                        name, param.name
+                    )))
+                }
+                (None, default) => default.value(),
+            };
+            values.insert(param.name.to_string(), value);
+        }
+        Ok(ToolArgs { values })
+    }
+
+    async fn call(&self, env: &ToolEnv, args: &[Value], kwargs: &Map<String, Value>) -> ToolResult {
+        let bound = self.bind(args, kwargs)?;
+        tracing::debug!(args = ?bound.values, "REPL tool: {}", self.name);
+        env.check_doc_ids(&bound)?;
+        (self.handler)(env.clone(), bound).await
+    }
+}
+
+use DefaultValue as D;
+use ParamType as T;
+
+const DOC_ID: Param = Param::required("doc_id", T::Str, "a `doc_id` from `documents`");
+
+/// Every REPL tool, in the order the prompt lists them.
+pub static TOOLS: &[Tool] = &[
+    Tool {
+        name: "list_documents",
+        params: &[],
+        doc: "every loaded document as `{\"doc_id\", \"name\", \"source\", \"size\"}` (same as `documents`).",
+        long: false,
+        handler: list_documents,
+    },
+    Tool {
+        name: "list_files",
+        params: &[DOC_ID],
+        doc: "`[{\"offset\": N, \"name\": \"...\", \"size\": ..., \"language\": ...}, ...]` (full TOC).",
+        long: false,
+        handler: list_files,
+    },
+    Tool {
+        name: "read_file",
+        params: &[
+            DOC_ID,
+            Param::required("filename", T::Str, "file path or a unique part of it"),
+        ],
+        doc: "full raw text (partial/fuzzy filename match), up to 20K chars. Raises `ValueError` if no file matches.",
+        long: false,
+        handler: read_file,
+    },
+    Tool {
+        name: "grep",
+        params: &[
+            DOC_ID,
+            Param::required("pattern", T::Str, "Rust regex syntax, e.g. r\"(?i)fee|cost\""),
+            Param::optional("context", T::Int, D::Int(3), "lines shown before and after each match"),
+            Param::optional("max_results", T::Int, D::Int(10), "maximum hits returned"),
+            Param::optional("files", T::StrList, D::None, "glob(s) limiting the search, e.g. \"*.md\" or [\"docs/**\"]; globs without `/` match file names"),
+            Param::optional("multiline", T::Bool, D::Bool(false), "search whole files so a pattern can span lines"),
+            Param::optional("ignore_case", T::Bool, D::Bool(false), "case-insensitive matching (same as `(?i)`)"),
+        ],
+        doc: "regex search + rich context lines; each hit has `file`, `line` within that file, `match` and `context`. `files=\"*.md\"` (or a list of globs) limits the search to matching files; `multiline=True` lets a pattern span lines. Bad patterns raise `ValueError`.",
+        long: false,
+        handler: grep,
+    },
+    Tool {
+        name: "search_document",
+        params: &[
+            DOC_ID,
+            Param::required("query", T::Str, "keywords; `\"quoted phrases\"` must match exactly"),
+            Param::optional("max_results", T::Int, D::Int(5), "maximum excerpts returned"),
+        ],
+        doc: "BM25-ranked keyword search (file-path matches boosted, `\"quoted phrases\"` must match exactly), ~600-char excerpts with `score`, `file`, `offset`.",
+        long: false,
+        handler: search_document,
+    },
+    Tool {
+        name: "semantic_search",
+        params: &[
+            DOC_ID,
+            Param::required("query", T::Str, "natural-language description of what to find"),
+            Param::optional("k", T::Int, D::Int(5), "maximum excerpts returned"),
+            Param::optional("hybrid", T::Bool, D::Bool(false), "fuse the ranking with BM25"),
+        ],
+        doc: "embedding similarity search for paraphrased/conceptual questions (same result shape as `search_document`); `hybrid=True` fuses it with BM25. Raises if the doc has no embeddings — fall back to `search_document`.",
+        long: false,
+        handler: semantic_search,
+    },
+    Tool {
+        name: "get_section",
+        params: &[
+            DOC_ID,
+            Param::required("offset", T::Int, "char offset, e.g. a hit's `offset`"),
+            Param::optional("length", T::Int, D::Int(2000), "chars to read"),
+        ],
+        doc: "precise char-range extraction.",
+        long: false,
+        handler: get_section,
+    },
+    Tool {
+        name: "llm_query",
+        params: &[Param::required("prompt", T::Str, "instructions plus the text to analyze")],
+        doc: "sub-LLM for deep analysis, summarization, extraction, or Q&A on any text you feed it.",
+        long: false,
+        handler: llm_query,
+    },
+    Tool {
+        name: "llm_query_batch",
+        params: &[
+            Param::required("prompts", T::StrList, "one prompt per item, e.g. the same instructions plus each file's text"),
+            Param::optional("max_concurrency", T::Int, D::Int(4), "sub-LLM calls in flight at once"),
+        ],
+        doc: "runs `llm_query` on every prompt concurrently and returns the answers as a list, in prompt order — use it instead of looping `llm_query` to map over many files. At most 50 prompts per call; a prompt whose call fails gets `\"Error: ...\"` in its place.",
+        long: true,
+        handler: llm_query_batch,
+    },
+    Tool {
+        name: "rlm_query",
+        params: &[
+            Param::required("question", T::Str, "what the nested investigation should find out"),
+            Param::optional("doc_ids", T::StrList, D::None, "`doc_id`(s) to investigate; default all of `documents`"),
+        ],
+        doc: "delegates `question` to a nested investigation with its own fresh REPL and returns `{\"answer\", \"evidence\", \"files_read\", \"iterations\", \"finished\"}` — only what you print enters your context, so use it for \"read every file under X and summarize Y\". Nesting depth, iterations and calls per question are limited; raises `ValueError` past a limit.",
+        long: true,
+        handler: rlm_query,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Tool> {
+    TOOLS.iter().find(|t| t.name == name)
+}
+
+/// The tool list for the system prompt: one line per tool, plus `help()`.
+pub fn prompt_section() -> String {
+    let mut lines: Vec<String> = TOOLS
+        .iter()
+        .map(|t| format!("- `{}` → {}", t.signature(), t.doc))
+        .collect();
+    lines.push(
+        "- `help(tool)` → a tool's argument types, defaults and docs; `help()` lists every tool."
+            .to_string(),
+    );
+    lines.join("\n")
+}
+
+/// `help()` output with no argument.
+fn overview() -> String {
+    let mut out = String::from("Tools (help(name) for details):\n");
+    for tool in TOOLS {
+        out.push_str(&format!("  {}\n", tool.signature()));
+    }
+    out
+}
+
+/// What a session's worker needs to set up the REPL: the `documents`
+/// variable, and each tool's name and `help()` text.
+pub fn manifest(docs: &[DocMeta]) -> Value {
+    let tools: Vec<Value> = TOOLS
+        .iter()
+        .map(|t| json!({"name": t.name, "help": t.help(), "long": t.long}))
+        .collect();
+    json!({
+        "documents": docs.iter().map(doc_summary).collect::<Vec<_>>(),
+        "tools": tools,
+        "overview": overview(),
+    })
+}
+
+/// Run a tool call from a session's worker, returning the reply it expects:
+/// `{"value": ...}`, or the exception to raise as `{"error", "message"}`.
+pub async fn call(env: &ToolEnv, name: &str, args: &[Value], kwargs: &Map<String, Value>) -> Value {
+    let result = match find(name) {
+        Some(tool) => tool.call(env, args, kwargs).await,
+        None => Err(ToolError::Failed(anyhow::anyhow!(
+            "no tool named '{}'",
+            name
+        ))),
+    };
+    match result {
+        Ok(value) => json!({ "value": value }),
+        Err(e) => {
+            let (error, message) = e.exception();
+            json!({ "error": error, "message": message })
+        }
+    }
+}
+
+fn doc_summary(doc: &DocMeta) -> Value {
+    json!({
+        "doc_id": doc.id,
+        "name": doc.name,
+        "source": doc.source,
+        "size": doc.size,
+    })
+}
+
+fn excerpt(ex: &DocExcerpt) -> Value {
+    json!({
+        "doc_id": ex.doc_id,
+        "offset": ex.offset,
+        "content": ex.content,
+        "match_count": ex.match_count,
+        "score": (f64::from(ex.score) * 1000.0).round() / 1000.0,
+        "file": ex.file,
+    })
+}
+
+fn list_documents(env: ToolEnv, _args: ToolArgs) -> BoxFuture<'static, ToolResult> {
+    Box::pin(async move { Ok(env.docs.iter().map(doc_summary).collect()) })
+}
+
+fn list_files(env: ToolEnv, args: ToolArgs) -> BoxFuture<'static, ToolResult> {
+    Box::pin(async move {
+        let files = env.store.list_files(&args.str("doc_id")?).await?;
+        tracing::debug!(file_count = files.len(), "REPL: list_files result");
+        Ok(files
+            .iter()
+            .map(|f| {
+                json!({
+                    "offset": f.offset,
+                    "name": f.path,
+                    "size": f.size,
+                    "language": f.language,
+                })
+            })
+            .collect())
+    })
+}
+
+fn read_file(env: ToolEnv, args: ToolArgs) -> BoxFuture<'static, ToolResult> {
+    Box::pin(async move {
+        let doc_id = args.str("doc_id")?;
+        let filename = args.str("filename")?;
+        let Some(entry) = env.store.find_file(&doc_id, &filename).await? else {
+            return Err(ToolError::Invalid(format!(
+                "File '{}' not found. Use list_files() to see available files.",
+                filename
+            )));
+        };
+
+        // Record successful file access for citation tracking
+        if let Ok(mut tracker) = env.file_tracker.lock() {
+            tracker.push((doc_id.clone(), entry.path.clone()));
+        }
+
+        let body = env.store.get_file(&doc_id, &entry.path).await?;
+        Ok(body
+            .chars()
+            .take(READ_FILE_MAX_CHARS)
+            .collect::<String>()
+            .into())
+    })
+}
+
+fn grep(env: ToolEnv, args: ToolArgs) -> BoxFuture<'static, ToolResult> {
+    Box::pin(async move {
+        let doc_id = args.str("doc_id")?;
+        let opts = GrepOptions {
+            context: args.usize("context")?,
+            max_results: args.usize("max_results")?,
+            files: args.strs("files")?,
+            multiline: args.bool("multiline")?,
+            ignore_case: args.bool("ignore_case")?,
+        };
+        let re = grep::compile(&args.str("pattern")?, &opts)
+            .map_err(|e| ToolError::Invalid(e.to_string()))?;
+        let files = env.store.list_files(&doc_id).await?;
+        let hits = if env.store.has_file_records(&doc_id).await? {
+            // Read only the files the globs select, until enough hits
+            let filter = grep::FileFilter::new(&opts.files)
+                .map_err(|e| ToolError::Invalid(e.to_string()))?;
+            let mut hits = Vec::new();
+            for entry in files.iter().filter(|f| filter.includes(&f.path)) {
+                if hits.len() >= opts.max_results {
+                    break;
+                }
+                let body = env.store.get_file(&doc_id, &entry.path).await?;
+                let opts = GrepOptions {
+                    max_results: opts.max_results - hits.len(),
+                    ..opts.clone()
+                };
+                hits.extend(grep::search_file(&entry.path, &body, &re, &opts));
+            }
+            hits
+        } else {
+            let text = env.store.get_content(&doc_id).await?;
+            grep::search(&text, &files, &re, &opts)
+                .map_err(|e| ToolError::Invalid(e.to_string()))?
+        };
+
+        tracing::debug!(results = hits.len(), "REPL: grep result");
+        Ok(hits
+            .iter()
+            .map(|hit| {
+                json!({
+                    "line": hit.line,
+                    "file": hit.file,
+                    "match": hit.matched,
+                    "context": hit.context,
+                })
+            })
+            .collect())
+    })
+}
+
+fn search_document(env: ToolEnv, args: ToolArgs) -> BoxFuture<'static, ToolResult> {
+    Box::pin(async move {
+        let excerpts = env
+            .store
+            .search(
+                &args.str("doc_id")?,
+                &args.str("query")?,
+                args.usize("max_results")?,
+            )
+            .await?;
+        tracing::debug!(results = excerpts.len(), "REPL: search_document result");
+        Ok(excerpts.iter().map(excerpt).collect())
+    })
+}
+
+fn semantic_search(env: ToolEnv, args: ToolArgs) -> BoxFuture<'static, ToolResult> {
+    Box::pin(async move {
+        let doc_id = args.str("doc_id")?;
+        let query = args.str("query")?;
+        let k = args.usize("k")?;
+        let model = env.llm.embedding_model().ok_or_else(|| {
+            anyhow::anyhow!("semantic search is disabled (no embedding model configured)")
+        })?;
+        let vectors = env.llm.embed(std::slice::from_ref(&query)).await?;
+        let vector = vectors
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("empty embedding response"))?;
+        let excerpts = if args.bool("hybrid")? {
+            env.store
+                .hybrid_search(&doc_id, &query, model, &vector, k)
+                .await?
+        } else {
+            env.store
+                .semantic_search(&doc_id, model, &vector, k)
+                .await?
+        };
+        tracing::debug!(results = excerpts.len(), "REPL: semantic_search result");
+        Ok(excerpts.iter().map(excerpt).collect())
+    })
+}
+
+fn get_section(env: ToolEnv, args: ToolArgs) -> BoxFuture<'static, ToolResult> {
+    Box::pin(async move {
+        let section = env
+            .store
+            .get_section(
+                &args.str("doc_id")?,
+                args.usize("offset")?,
+                args.usize("length")?,
+            )
+            .await?;
+        Ok(section.into())
+    })
+}
+
+fn llm_query(env: ToolEnv, args: ToolArgs) -> BoxFuture<'static, ToolResult> {
+    Box::pin(async move {
+        let completion = env.llm.sub_query(&args.str("prompt")?).await?;
+        env.meter().record(Phase::LlmQuery, completion.usage);
+        Ok(completion.content.into())
+    })
+}
+
+fn llm_query_batch(env: ToolEnv, args: ToolArgs) -> BoxFuture<'static, ToolResult> {
+    Box::pin(async move {
+        let prompts = args.strs("prompts")?;
+        if prompts.len() > LLM_QUERY_BATCH_MAX {
+            return Err(ToolError::Invalid(format!(
+                "llm_query_batch() takes at most {} prompts ({} given) — split them into several calls",
+                LLM_QUERY_BATCH_MAX,
+                prompts.len()
+            )));
+        }
+        // The client's LLM_MAX_CONCURRENT limit still applies on top of this
+        let max_concurrency = args.usize("max_concurrency")?.max(1);
+
+        // `buffered` runs the calls concurrently but yields them in order
+        let count = prompts.len();
+        let calls = stream::iter(prompts)
+            .map(|prompt| {
+                let llm = env.llm.clone();
+                async move { llm.sub_query(&prompt).await }
+            })
+            .buffered(max_concurrency)
+            .collect::<Vec<_>>();
+        let cancel = env.cancel();
+        let completions = tokio::select! {
+            completions = calls => completions,
+            _ = cancel.cancelled() => {
+                return Err(ToolError::Failed(anyhow::anyhow!("query cancelled")));
+            }
+        };
+
+        let meter = env.meter();
+        let mut failed = 0;
+        let answers: Vec<Value> = completions
+            .into_iter()
+            .map(|completion| match completion {
+                Ok(completion) => {
+                    meter.record(Phase::LlmQuery, completion.usage);
+                    completion.content.into()
+                }
+                Err(e) => {
+                    failed += 1;
+                    format!("Error: {:#}", e).into()
+                }
+            })
+            .collect();
+        tracing::debug!(prompts = count, failed, "REPL: llm_query_batch result");
+        Ok(answers.into())
+    })
+}
+
+fn rlm_query(env: ToolEnv, args: ToolArgs) -> BoxFuture<'static, ToolResult> {
+    Box::pin(async move {
+        let question = args.str("question")?;
+        let doc_ids = args.strs("doc_ids")?;
+        let sub_rlm = env
+            .sub_rlm
+            .as_ref()
+            .filter(|sub| sub.can_nest())
+            .ok_or_else(|| {
+                ToolError::Invalid(
+                    "rlm_query() nesting limit reached — investigate directly instead".to_string(),
+                )
+            })?;
+        if !sub_rlm.take_call() {
+            return Err(ToolError::Invalid(
+                "rlm_query() call limit of this question reached — investigate directly instead"
+                    .to_string(),
+            ));
+        }
+
+        // Unknown doc_ids were rejected before the handler ran
+        let docs: Vec<DocMeta> = if doc_ids.is_empty() {
+            env.docs.to_vec()
+        } else {
+            env.docs
+                .iter()
+                .filter(|d| doc_ids.contains(&d.id))
+                .cloned()
+                .collect()
+        };
+        let result = sub_rlm
+            .run(&docs, &question, env.meter(), env.cancel())
+            .await?;
+
+        // Files the nested loop read count toward this loop's citations
+        if let Ok(mut tracker) = env.file_tracker.lock() {
+            tracker.extend(result.files_read.iter().cloned());
+        }
+        tracing::debug!(
+            iterations = result.iterations,
+            answer_len = result.answer.len(),
+            "REPL: rlm_query result"
+        );
+        Ok(json!({
+            "answer": result.answer,
+            "evidence": result.evidence,
+            "files_read": result
+                .files_read
+                .iter()
+                .map(|(doc_id, path)| json!({"doc_id": doc_id, "path": path}))
+                .collect::<Vec<_>>(),
+            "iterations": result.iterations,
+            "finished": result.was_final,
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::docs::types::{DocInfo, Scope};
+    use crate::llm::mock::{limit_concurrency, serve_slowly, test_client};
+    use crate::rlm::jobs::Jobs;
+
+    /// Bind a call as the worker sends it: a JSON list and object.
+    fn call(tool: &str, args: Value, kwargs: Value) -> Result<Map<String, Value>, String> {
+        find(tool)
+            .unwrap()
+            .bind(args.as_array().unwrap(), kwargs.as_object().unwrap())
+            .map(|bound| bound.values)
+            .map_err(|e| e.exception().1)
+    }
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            find("grep").unwrap().signature(),
+            "grep(doc_id, pattern, context=3, max_results=10, files=None, multiline=False, ignore_case=False)"
+        );
+        assert_eq!(
+            find("list_documents").unwrap().signature(),
+            "list_documents()"
+        );
+        assert_eq!(
+            find("llm_query_batch").unwrap().signature(),
+            "llm_query_batch(prompts, max_concurrency=4)"
+        );
+    }
+
+    #[test]
+    fn test_prompt_section_covers_every_tool() {
+        let section = prompt_section();
+        for tool in TOOLS {
+            assert!(
+                section.contains(&format!("`{}`", tool.signature())),
+                "{}",
+                tool.name
+            );
+        }
+        assert!(section.contains("help(tool)"));
+    }
+
+    #[test]
+    fn test_bind_positional_keywords_and_defaults() {
+        let values = call("grep", json!(["d", "fee", 5]), json!({"files": "*.md"})).unwrap();
+        assert_eq!(
+            Value::Object(values),
+            json!({
+                "doc_id": "d",
+                "pattern": "fee",
+                "context": 5,
+                "max_results": 10,
+                "files": ["*.md"],
+                "multiline": false,
+                "ignore_case": false,
+            })
+        );
+        let values = call(
+            "grep",
+            json!(["d", "x"]),
+            json!({"files": ["a", "b"], "multiline": true}),
+        )
+        .unwrap();
+        assert_eq!(values["files"], json!(["a", "b"]));
+        assert_eq!(values["multiline"], json!(true));
+    }
+
+    #[test]
+    fn test_bind_errors() {
+        let err = |args, kwargs| call("grep", args, kwargs).unwrap_err();
+        assert!(err(json!(["d"]), json!({})).contains("missing required argument 'pattern'"));
+        assert!(err(json!(["d", "x"]), json!({"contxt": 2}))
+            .contains("unexpected keyword argument 'contxt'"));
+        assert!(err(json!(["d", "x", 2]), json!({"context": 2}))
+            .contains("multiple values for argument 'context'"));
+        assert!(err(json!(["d", "x", "many"]), json!({}))
+            .contains("'context' must be a non-negative int, not str"));
+        assert!(err(json!(["d", "x", -1]), json!({})).contains("non-negative int"));
+        assert!(err(json!(["d", "x", true]), json!({})).contains("not bool"));
+        assert!(err(json!(["d", "x", 1.5]), json!({})).contains("not float"));
+        assert!(err(json!(["d", {"$unsupported": "set"}]), json!({}))
+            .contains("'pattern' must be str, not set"));
+        assert!(err(json!(["d", "x", 1, 2, 3, 4, 5, 6]), json!({}))
+            .contains("at most 7 arguments (8 given)"));
+    }
+
+    #[test]
+    fn test_help_lists_arguments() {
+        let help = find("get_section").unwrap().help();
+        assert!(help.starts_with("get_section(doc_id, offset, length=2000)\n"));
+        assert!(help.contains("length: int = 2000 — chars to read"));
+    }
+
+    /// Tools over an empty store, calling `llm`.
+    async fn env(name: &str, llm: LlmClient) -> ToolEnv {
+        let dir = std::env::temp_dir().join(format!("edgar-tools-{}-{}", name, std::process::id()));
+        ToolEnv {
+            store: Arc::new(DocumentStore::new(&dir).await.unwrap()),
+            llm: Arc::new(llm),
+            docs: Arc::default(),
+            file_tracker: FileTracker::default(),
+            usage: UsageSlot::default(),
+            cancel: CancelSlot::default(),
+            sub_rlm: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grep_searches_per_file_records() {
+        let mut env = env("grep", test_client(String::new())).await;
+        let files = vec![
+            ("README.md".to_string(), "the fee is paid daily".to_string()),
+            (
+                "docs/fees.mdx".to_string(),
+                "Fees\nthe fee is\ncharged per block".to_string(),
+            ),
+            ("docs/gas.mdx".to_string(), "gas fee".to_string()),
+        ];
+        let info = DocInfo {
+            scope: Scope::Guild(1),
+            name: "o/r",
+            source: "github:o/r",
+            label: "akash",
+            url_context: None,
+            commit: None,
+        };
+        let doc_id = env.store.store_files(&files, &info).await.unwrap();
+        assert!(env.store.has_file_records(&doc_id).await.unwrap());
+        env.docs = Arc::new(vec![env.store.get_meta(&doc_id).await.unwrap()]);
+
+        let search = |kwargs: Value| {
+            let args = [json!(doc_id), json!("fee")];
+            let env = env.clone();
+            async move { super::call(&env, "grep", &args, kwargs.as_object().unwrap()).await }
+        };
+        let hits = search(json!({"files": "*.mdx", "context": 0})).await;
+        assert_eq!(
+            hits["value"],
+            json!([
+                {"line": 2, "file": "docs/fees.mdx", "match": "fee", "context": ">> L2: the fee is"},
+                {"line": 1, "file": "docs/gas.mdx", "match": "fee", "context": ">> L1: gas fee"},
+            ])
+        );
+        let hits = search(json!({"max_results": 2})).await;
+        let found: Vec<&str> = hits["value"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|h| h["file"].as_str().unwrap())
+            .collect();
+        assert_eq!(found, vec!["README.md", "docs/fees.mdx"]);
+        let err = search(json!({"files": "["})).await;
+        assert_eq!(err["error"], "ValueError");
+    }
+
+    async fn batch(env: &ToolEnv, prompts: &[&str], max_concurrency: usize) -> Value {
+        let kwargs = json!({"max_concurrency": max_concurrency});
+        super::call(
+            env,
+            "llm_query_batch",
+            &[json!(prompts)],
+            kwargs.as_object().unwrap(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_llm_query_batch_order_and_usage() {
+        // The first prompt is answered last
+        let (base, _) = serve_slowly(
+            |prompt| Duration::from_millis(if prompt == "a" { 300 } else { 0 }),
+            |prompt| prompt.to_uppercase(),
+        )
+        .await;
+        let env = env("batch-order", test_client(base)).await;
+        let meter = UsageMeter::default();
+        *env.usage.lock().unwrap() = meter.clone();
+
+        let reply = batch(&env, &["a", "b", "c"], 3).await;
+        assert_eq!(reply, json!({"value": ["A", "B", "C"]}));
+        let usage = meter.snapshot();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage["llm_query"].calls, 3);
+        assert_eq!(usage["llm_query"].prompt_tokens, 30);
+    }
+
+    #[tokio::test]
+    async fn test_llm_query_batch_concurrency_limits() {
+        let prompts = ["a"; 6];
+        let slow = |_: &str| Duration::from_millis(100);
+
+        let (base, load) = serve_slowly(slow, str::to_string).await;
+        let env = env("batch-concurrency", test_client(base)).await;
+        let reply = batch(&env, &prompts, 2).await;
+        assert_eq!(reply["value"].as_array().unwrap().len(), 6);
+        assert_eq!(load.peak(), 2);
+
+        // LLM_MAX_CONCURRENT applies on top of max_concurrency
+        let (base, load) = serve_slowly(slow, str::to_string).await;
+        let mut client = test_client(base);
+        limit_concurrency(&mut client, 1);
+        let env = env("batch-permits", client).await;
+        batch(&env, &prompts, 4).await;
+        assert_eq!(load.peak(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_llm_query_batch_stops_on_cancel() {
+        let (base, _) = serve_slowly(|_| Duration::from_secs(30), str::to_string).await;
+        let env = env("batch-cancel", test_client(base)).await;
+        let jobs = Arc::new(Jobs::default());
+        let job = jobs.start(1, 1, "ann", "t", "q");
+        *env.cancel.lock().unwrap() = job.token();
+        let id = job.id();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            jobs.cancel(id);
+        });
+
+        let started = std::time::Instant::now();
+        let reply = batch(&env, &["a", "b"], 2).await;
+        assert_eq!(
+            reply,
+            json!({"error": "RuntimeError", "message": "query cancelled"})
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
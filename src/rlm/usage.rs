@@ -0,0 +1,256 @@
+//! LLM token accounting: a meter each query's calls are recorded into, and
+//! rollups of stored Q/A records for `/edgar usage`.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use tracing::debug;
+
+use crate::docs::types::{PhaseUsage, QaRecord};
+use crate::llm::Usage;
+
+/// Which step of a query an LLM call served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Rewriting a follow-up to stand alone
+    Rewrite,
+    Decompose,
+    /// The model's next step in an exploration loop
+    Iteration,
+    /// `llm_query()` called from the REPL
+    LlmQuery,
+    /// Combining findings, or answering from evidence at max iterations
+    Synthesis,
+    /// Rescuing a broken answer
+    Validation,
+}
+
+impl Phase {
+    /// Every phase, in the order a query reaches them.
+    pub const ALL: [Phase; 6] = [
+        Phase::Rewrite,
+        Phase::Decompose,
+        Phase::Iteration,
+        Phase::LlmQuery,
+        Phase::Synthesis,
+        Phase::Validation,
+    ];
+
+    /// The phase named `name` (as in `as_str`).
+    pub fn parse(name: &str) -> Option<Phase> {
+        Phase::ALL.into_iter().find(|phase| phase.as_str() == name)
+    }
+
+    /// Key in `QaRecord::usage`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Rewrite => "rewrite",
+            Phase::Decompose => "decompose",
+            Phase::Iteration => "iteration",
+            Phase::LlmQuery => "llm_query",
+            Phase::Synthesis => "synthesis",
+            Phase::Validation => "validation",
+        }
+    }
+}
+
+/// Collects the LLM usage of one query, by phase. Clones share the tally.
+#[derive(Clone, Default)]
+pub struct UsageMeter(Arc<Mutex<BTreeMap<String, PhaseUsage>>>);
+
+impl UsageMeter {
+    pub fn record(&self, phase: Phase, usage: Usage) {
+        debug!(
+            phase = phase.as_str(),
+            prompt_tokens = usage.prompt_tokens,
+            completion_tokens = usage.completion_tokens,
+            latency_ms = usage.latency_ms,
+            tokens_per_sec = usage.tokens_per_sec(),
+            "LLM call"
+        );
+        let mut phases = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        phases
+            .entry(phase.as_str().to_string())
+            .or_default()
+            .add(&PhaseUsage {
+                calls: 1,
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+                latency_ms: usage.latency_ms,
+            });
+    }
+
+    /// Usage recorded so far, by phase.
+    pub fn snapshot(&self) -> BTreeMap<String, PhaseUsage> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
+
+/// How `/edgar usage` groups queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    User,
+    Topic,
+    /// UTC date, e.g. "2025-03-14"
+    Day,
+}
+
+/// Usage of a group of queries.
+#[derive(Debug, Clone, Default)]
+pub struct UsageTotals {
+    pub queries: u32,
+    pub usage: PhaseUsage,
+    /// Summed wall-clock time of the queries
+    pub duration_ms: u64,
+}
+
+/// Sum records' usage per user, topic or day, heaviest token users first.
+pub fn summarize(records: &[QaRecord], grouping: Grouping) -> Vec<(String, UsageTotals)> {
+    let mut groups: BTreeMap<String, UsageTotals> = BTreeMap::new();
+    for record in records {
+        let key = match grouping {
+            Grouping::User => record.user_id.to_string(),
+            Grouping::Topic => record.topic.clone(),
+            Grouping::Day => chrono::DateTime::from_timestamp(record.timestamp, 0)
+                .map(|t| t.format("%Y-%m-%d").to_string())
+                .unwrap_or_default(),
+        };
+        let totals = groups.entry(key).or_default();
+        totals.queries += 1;
+        totals.usage.add(&record.total_usage());
+        totals.duration_ms += record.duration_ms;
+    }
+
+    let mut groups: Vec<(String, UsageTotals)> = groups.into_iter().collect();
+    match grouping {
+        // Days read best in order, newest first
+        Grouping::Day => groups.reverse(),
+        _ => groups.sort_by_key(|(_, totals)| Reverse(totals.usage.total_tokens())),
+    }
+    groups
+}
+
+/// Records' usage summed per phase.
+pub fn by_phase(records: &[QaRecord]) -> BTreeMap<String, PhaseUsage> {
+    let mut phases: BTreeMap<String, PhaseUsage> = BTreeMap::new();
+    for record in records {
+        for (phase, usage) in &record.usage {
+            phases.entry(phase.clone()).or_default().add(usage);
+        }
+    }
+    phases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docs::types::{QueryOutcome, Scope};
+
+    fn record(user_id: u64, topic: &str, timestamp: i64, meter: &UsageMeter) -> QaRecord {
+        QaRecord {
+            id: format!("{}{}", topic, timestamp),
+            scope: Scope::Guild(1),
+            topic: topic.to_string(),
+            question: "q".to_string(),
+            answer: "a".to_string(),
+            cited_urls: vec![],
+            doc_ids: vec![],
+            evidence: vec![],
+            iterations: 1,
+            timestamp,
+            user_id,
+            usage: meter.snapshot(),
+            duration_ms: 1000,
+            outcome: QueryOutcome::Answered,
+        }
+    }
+
+    fn usage(prompt_tokens: u64, completion_tokens: u64) -> Usage {
+        Usage {
+            prompt_tokens,
+            completion_tokens,
+            latency_ms: 500,
+        }
+    }
+
+    #[test]
+    fn test_meter_sums_by_phase() {
+        let meter = UsageMeter::default();
+        meter.record(Phase::Iteration, usage(100, 10));
+        meter.clone().record(Phase::Iteration, usage(200, 20));
+        meter.record(Phase::Synthesis, usage(50, 50));
+
+        let phases = meter.snapshot();
+        let iteration = phases["iteration"];
+        assert_eq!(iteration.calls, 2);
+        assert_eq!(iteration.total_tokens(), 330);
+        assert_eq!(iteration.tokens_per_sec(), 30.0);
+        assert_eq!(phases["synthesis"].calls, 1);
+    }
+
+    #[test]
+    fn test_summarize() {
+        let small = UsageMeter::default();
+        small.record(Phase::Decompose, usage(10, 0));
+        let large = UsageMeter::default();
+        large.record(Phase::Iteration, usage(1000, 100));
+
+        // 2025-01-01 and 2025-01-02, UTC
+        let records = vec![
+            record(7, "akash", 1_735_689_600, &small),
+            record(8, "akash", 1_735_776_000, &large),
+            record(7, "cosmos", 1_735_776_000, &large),
+        ];
+
+        let users = summarize(&records, Grouping::User);
+        assert_eq!(users[0].0, "7");
+        assert_eq!(users[0].1.queries, 2);
+        assert_eq!(users[0].1.usage.total_tokens(), 1110);
+
+        let days = summarize(&records, Grouping::Day);
+        assert_eq!(days[0].0, "2025-01-02");
+        assert_eq!(days[1].1.queries, 1);
+
+        let phases = by_phase(&records);
+        assert_eq!(phases["iteration"].calls, 2);
+        assert_eq!(phases["decompose"].prompt_tokens, 10);
+    }
+
+    #[test]
+    fn test_unanswered_queries_count() {
+        let meter = UsageMeter::default();
+        meter.record(Phase::Iteration, usage(300, 30));
+        let cancelled = QaRecord::unanswered(
+            Scope::Guild(1),
+            "akash",
+            "q",
+            7,
+            QueryOutcome::Cancelled,
+            meter.snapshot(),
+            2000,
+        );
+        assert!(cancelled.answer.is_empty());
+
+        let records = vec![record(7, "akash", cancelled.timestamp, &meter), cancelled];
+        let users = summarize(&records, Grouping::User);
+        assert_eq!(users[0].1.queries, 2);
+        assert_eq!(users[0].1.usage.total_tokens(), 660);
+        assert_eq!(users[0].1.duration_ms, 3000);
+
+        // Records from before outcomes were kept were all answered
+        let mut legacy = serde_json::to_value(&records[0]).unwrap();
+        legacy.as_object_mut().unwrap().remove("outcome");
+        let legacy: QaRecord = serde_json::from_value(legacy).unwrap();
+        assert_eq!(legacy.outcome, QueryOutcome::Answered);
+        let stored: QaRecord =
+            serde_json::from_slice(&serde_json::to_vec(&records[1]).unwrap()).unwrap();
+        assert_eq!(stored.outcome, QueryOutcome::Cancelled);
+    }
+}
@@ -1,17 +1,33 @@
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, RwLockMappedWriteGuard, RwLockWriteGuard};
 
+use crate::docs::types::{Scope, ScopeView};
 use crate::docs::DocumentStore;
 use crate::llm::LlmClient;
+use crate::rlm::conversation::Conversations;
+use crate::rlm::jobs::Jobs;
+use crate::rlm::scheduler::Scheduler;
 use crate::rlm::RlmEngine;
 
 /// Configurable RLM parameters (admins can modify at runtime).
+/// Persisted under the `rlm` config key; missing fields take their defaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RlmConfig {
     pub min_code_executions: u32,
     pub max_iterations: u32,
     pub min_answer_len: usize,
     pub parallel_loops: u32,
+    /// Let a follow-up question in a thread continue in the previous answer's
+    /// REPL session, keeping its variables
+    pub reuse_session: bool,
+    /// How deep `rlm_query()` may nest investigations; 0 disables it
+    pub rlm_query_depth: u32,
+    /// Iterations each `rlm_query()` investigation gets
+    pub rlm_query_iterations: u32,
 }
 
 impl Default for RlmConfig {
@@ -21,17 +37,123 @@ impl Default for RlmConfig {
             max_iterations: 15,
             min_answer_len: 150,
             parallel_loops: 2,
+            reuse_session: true,
+            rlm_query_depth: 1,
+            rlm_query_iterations: 6,
         }
     }
 }
 
+/// Config key for `RlmConfig`.
+pub const RLM_CONFIG_KEY: &str = "rlm";
+/// Config key for the admin role ID list.
+pub const ADMIN_ROLES_KEY: &str = "admin_roles";
+/// Config key for whether a guild sees the global document library.
+pub const LIBRARY_KEY: &str = "library";
+
+/// Settings of one guild. Each value is loaded from the guild's scope, falling
+/// back to the global scope (where settings from before per-guild isolation
+/// live) and then to the built-in default.
+#[derive(Debug, Clone)]
+pub struct GuildSettings {
+    pub rlm: RlmConfig,
+    pub admin_roles: HashSet<u64>,
+    /// Include the global library in topics, autocomplete and `/edgar sources`.
+    /// Off until the guild opts in with `/edgar config library`.
+    pub include_global: bool,
+}
+
 pub struct AppState {
     pub store: Arc<DocumentStore>,
     pub llm: Arc<LlmClient>,
     pub rlm: Arc<RlmEngine>,
+    /// Bot operators (`ADMIN_USER_IDS`): admin in every guild, and the only
+    /// users who can manage the global library.
     pub admin_ids: HashSet<u64>,
-    pub admin_role_ids: Arc<RwLock<HashSet<u64>>>,
-    pub rlm_config: Arc<RwLock<RlmConfig>>,
+    /// Admin roles for guilds that haven't configured their own (`ADMIN_ROLE_IDS`)
+    pub default_admin_roles: HashSet<u64>,
+    /// Per-guild settings, loaded on first use
+    pub guilds: Arc<RwLock<HashMap<u64, GuildSettings>>>,
+    /// Conversation memory of threads `/edgar ask` was used in
+    pub conversations: Arc<Conversations>,
+    /// Queries in flight, for `/edgar jobs`
+    pub jobs: Arc<Jobs>,
+    /// Rate limits and the queue every `/edgar ask` waits in
+    pub scheduler: Arc<Scheduler>,
+}
+
+impl AppState {
+    /// A guild's current settings.
+    pub async fn guild_settings(&self, guild: u64) -> anyhow::Result<GuildSettings> {
+        if let Some(settings) = self.guilds.read().await.get(&guild) {
+            return Ok(settings.clone());
+        }
+        Ok(self.guild_settings_mut(guild).await?.clone())
+    }
+
+    /// Lock a guild's settings for update. Hold the guard across the store
+    /// commit so concurrent updates can't interleave.
+    pub async fn guild_settings_mut(
+        &self,
+        guild: u64,
+    ) -> anyhow::Result<RwLockMappedWriteGuard<'_, GuildSettings>> {
+        let mut guilds = self.guilds.write().await;
+        if let Entry::Vacant(entry) = guilds.entry(guild) {
+            entry.insert(self.load_guild_settings(guild).await?);
+        }
+        Ok(RwLockWriteGuard::map(guilds, |g| {
+            g.get_mut(&guild).expect("guild settings loaded above")
+        }))
+    }
+
+    /// The document scopes visible from a guild.
+    pub async fn view(&self, guild: u64) -> anyhow::Result<ScopeView> {
+        let settings = self.guild_settings(guild).await?;
+        Ok(ScopeView {
+            guild,
+            include_global: settings.include_global,
+        })
+    }
+
+    async fn load_guild_settings(&self, guild: u64) -> anyhow::Result<GuildSettings> {
+        let scope = Scope::Guild(guild);
+        let rlm = self
+            .load_with_fallback(scope, RLM_CONFIG_KEY)
+            .await?
+            .unwrap_or_default();
+        let admin_roles = self
+            .load_with_fallback::<Vec<u64>>(scope, ADMIN_ROLES_KEY)
+            .await?
+            .map(|roles| roles.into_iter().collect())
+            .unwrap_or_else(|| self.default_admin_roles.clone());
+        let include_global = self
+            .load_with_fallback(scope, LIBRARY_KEY)
+            .await?
+            .unwrap_or(false);
+        tracing::debug!(
+            guild,
+            ?rlm,
+            roles = admin_roles.len(),
+            include_global,
+            "Loaded guild settings"
+        );
+        Ok(GuildSettings {
+            rlm,
+            admin_roles,
+            include_global,
+        })
+    }
+
+    async fn load_with_fallback<T: serde::de::DeserializeOwned>(
+        &self,
+        scope: Scope,
+        name: &str,
+    ) -> anyhow::Result<Option<T>> {
+        match self.store.load_config(scope, name).await? {
+            Some(value) => Ok(Some(value)),
+            None => self.store.load_config(Scope::Global, name).await,
+        }
+    }
 }
 
 pub type Context<'a> = poise::Context<'a, AppState, anyhow::Error>;